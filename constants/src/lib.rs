@@ -1,21 +1,43 @@
-/// TODO: Make port actually configured
-pub const BASE_IP: &str = "127.0.0.1:7272";
+pub const HOST: &str = "127.0.0.1";
+/// Port the server listens on unless `port` is configured
+pub const DEFAULT_PORT: u16 = 7272;
+/// Seconds between fetches unless `poll_interval` is configured
+pub const DEFAULT_POLL_INTERVAL: u64 = 5;
+/// Shortest poll interval, to stay well within the osu! API rate limits
+pub const MIN_POLL_INTERVAL: u64 = 2;
+/// Recent scores fetched unless `recent_limit` is configured
+pub const DEFAULT_RECENT_LIMIT: usize = 20;
+/// The API returns at most this many scores per request
+pub const MAX_RECENT_LIMIT: usize = 100;
+/// Followed by `:<port>` and an endpoint
 #[cfg(feature = "gui")]
-pub const BASE_URI: &str = constcat::concat!("ws://", BASE_IP);
+pub const BASE_URI: &str = constcat::concat!("ws://", HOST);
 /// Endpoints
 pub const USER_ENDPOINT: &str = "/";
 pub const TOPS_ENDPOINT: &str = "/tops";
 pub const FIRSTS_ENDPOINT: &str = "/firsts";
 pub const RECENT_ENDPOINT: &str = "/recent";
-/// Full URIs
-#[cfg(feature = "gui")]
-pub const USER_URI: &str = constcat::concat!(BASE_URI, USER_ENDPOINT);
-#[cfg(feature = "gui")]
-pub const TOPS_URI: &str = constcat::concat!(BASE_URI, TOPS_ENDPOINT);
-#[cfg(feature = "gui")]
-pub const FIRSTS_URI: &str = constcat::concat!(BASE_URI, FIRSTS_ENDPOINT);
-#[cfg(feature = "gui")]
-pub const RECENT_URI: &str = constcat::concat!(BASE_URI, RECENT_ENDPOINT);
+pub const PINNED_ENDPOINT: &str = "/pinned";
+pub const MOST_PLAYED_ENDPOINT: &str = "/most-played";
+pub const GRIND_ENDPOINT: &str = "/grind";
+pub const COMPARE_ENDPOINT: &str = "/compare";
+/// JSON Schema of the versioned payloads
+pub const SCHEMA_ENDPOINT: &str = "/schema";
+/// Server-sent events, followed by a channel name, e.g. `/events/user`
+pub const EVENTS_ENDPOINT: &str = "/events/";
+/// Rendered overlay files, followed by a file name, e.g. `/overlay/stats.html`
+pub const OVERLAY_ENDPOINT: &str = "/overlay/";
+/// Control endpoints
+pub const CONTROL_TRACK_ENDPOINT: &str = "/control/track";
+pub const CONTROL_UNTRACK_ENDPOINT: &str = "/control/untrack";
+pub const CONTROL_SESSION_RESET_ENDPOINT: &str = "/control/session/reset";
+pub const CONTROL_REFRESH_ENDPOINT: &str = "/control/refresh";
+/// OAuth login, opened in a browser
+pub const OAUTH_LOGIN_ENDPOINT: &str = "/oauth/login";
+/// Where osu! redirects to after a login, registered as the callback URL of the OAuth application
+pub const OAUTH_CALLBACK_ENDPOINT: &str = "/oauth/callback";
+/// Query selecting rosu-v2 payloads instead of the versioned ones
+pub const RAW_FORMAT_QUERY: &str = "?format=raw";
 
 pub const CONFIG_VERSION: u64 = 1;
 pub const APP_ID: &'static str = "com.chiffa.rosuTracker";
@@ -1,7 +1,12 @@
-use clap::{Arg, ArgGroup, ArgMatches, Command};
-use color_eyre::eyre::Result;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgGroup, ArgMatches, Command, value_parser};
+use color_eyre::eyre::{Error, Result};
+use constants::DEFAULT_PORT;
+use server::control;
+use server::setup::{ConfigSource, default_config_path, thread_init};
 use tracker_cosmic::init_with_flags;
-use types::Api;
+use types::{Api, Config};
 fn generate_commands() -> Command {
     let mut command = Command::new("rosu-tracker").subcommand(
         Command::new("init")
@@ -18,6 +23,59 @@ Will override your previously saved settings if rerun! If ran without any flags,
                 Arg::new("client_secret").short('s').long("secret").help("Your osu!api client secret").long_help("Client secret for osu!api v2. If you don't know where to get one, visit https://osu.ppy.sh/home/account/edit"),
             ])
             .group(ArgGroup::new("setup_flags").args(["username", "client_id", "client_secret"]).multiple(true).requires_all(["setup_flags"])),
+    ).subcommand(
+        Command::new("daemon")
+            .about("Runs the tracker headless, configured by a config file")
+            .long_about(
+                "Runs the tracker headless, configured by a config file.
+SIGTERM or SIGINT shut it down gracefully, SIGHUP reloads the config file without dropping clients",
+            )
+            .arg(
+                Arg::new("config")
+                    .short('c')
+                    .long("config")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Path to config.toml")
+                    .long_help("Path to config.toml. Defaults to config.toml in your config directory"),
+            ),
+    ).subcommand(
+        Command::new("store-secret")
+            .about("Stores an osu!api client secret in the system keyring")
+            .long_about(
+                "Stores an osu!api client secret, read from standard input, in the system keyring.
+Config files can leave `secret` empty afterwards. Without a keyring the secret is stored in a file only you can read",
+            )
+            .arg(
+                Arg::new("client_id")
+                    .short('i')
+                    .long("id")
+                    .required(true)
+                    .help("Your osu!api client ID"),
+            ),
+    ).subcommand(
+        Command::new("control")
+            .about("Controls a running tracker through its control API")
+            .subcommand_required(true)
+            .args([
+                Arg::new("token")
+                    .short('t')
+                    .long("token")
+                    .help("Control token")
+                    .long_help("Control token. Defaults to control_token from the config file"),
+                Arg::new("config")
+                    .short('c')
+                    .long("config")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Path to config.toml to read the token from"),
+            ])
+            .subcommands([
+                Command::new("track")
+                    .about("Start tracking another user until the config is reloaded")
+                    .arg(Arg::new("username").required(true).help("Username of the user you want to track")),
+                Command::new("untrack").about("Stop tracking anyone until the config is reloaded"),
+                Command::new("reset-session").about("Start a new session from the current statistics"),
+                Command::new("refresh").about("Refetch everything right away"),
+            ]),
     );
 
     #[cfg(feature = "cosmic")]
@@ -61,15 +119,76 @@ fn cli_flag_handler(matches: ArgMatches) -> Option<Api> {
     None
 }
 
+fn daemon_config(matches: &ArgMatches) -> Option<ConfigSource> {
+    let path = match matches.get_one::<PathBuf>("config") {
+        Some(path) => path.to_owned(),
+        None => default_config_path()?,
+    };
+    Some(ConfigSource::File(path))
+}
+
+fn store_secret(matches: &ArgMatches) -> Result<()> {
+    let id = matches
+        .get_one::<String>("client_id")
+        .expect("client_id is required");
+    eprintln!("Client secret:");
+    let mut secret = String::new();
+    std::io::stdin().read_line(&mut secret)?;
+    let secret = secret.trim();
+    if secret.is_empty() {
+        return Err(Error::msg("No client secret given"));
+    }
+    utils::secret::store(id, secret)?;
+    eprintln!("Stored the client secret");
+    Ok(())
+}
+
+async fn control_init(matches: &ArgMatches) -> Result<()> {
+    let config = daemon_config(matches)
+        .ok_or_else(|| Error::msg("Failed to find configuration"))
+        .and_then(|source| source.load());
+    // Without a config the tracker is most likely running on the default port
+    let port = config.as_ref().map_or(DEFAULT_PORT, Config::port);
+    let token = match matches.get_one::<String>("token") {
+        Some(token) => token.to_owned(),
+        None => config?.control_token.ok_or_else(|| {
+            Error::msg("No control_token in the config, please provide --token <token>")
+        })?,
+    };
+    let command = match matches.subcommand() {
+        Some(("track", track)) => {
+            let username = track
+                .get_one::<String>("username")
+                .expect("username is required");
+            control::Command::Track(username.to_owned())
+        }
+        Some(("untrack", _)) => control::Command::Untrack,
+        Some(("reset-session", _)) => control::Command::ResetSession,
+        Some(("refresh", _)) => control::Command::Refresh,
+        _ => unreachable!("subcommand is required"),
+    };
+    control::send_remote(&token, port, command).await
+}
+
 #[cfg(not(feature = "cosmic"))]
 #[tokio::main]
 async fn main() -> Result<()> {
     let command = generate_commands();
     let matches = command.get_matches();
 
-    let config = cli_flag_handler(matches);
+    if let Some(secret) = matches.subcommand_matches("store-secret") {
+        return store_secret(secret);
+    }
+    if let Some(control) = matches.subcommand_matches("control") {
+        return control_init(control).await;
+    }
 
-    tui_init(config).await
+    let source = match matches.subcommand_matches("daemon") {
+        Some(daemon) => daemon_config(daemon),
+        None => cli_flag_handler(matches).map(|api| ConfigSource::Fixed(api.into())),
+    };
+
+    tui_init(source).await
 }
 
 #[cfg(feature = "cosmic")]
@@ -77,21 +196,38 @@ fn main() -> Result<()> {
     let command = generate_commands();
     let matches = command.get_matches();
 
+    if let Some(secret) = matches.subcommand_matches("store-secret") {
+        return store_secret(secret);
+    }
+    if let Some(daemon) = matches.subcommand_matches("daemon") {
+        let source = daemon_config(daemon);
+        return tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(tui_init(source));
+    }
+    if let Some(control) = matches.subcommand_matches("control") {
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(control_init(control));
+    }
+
     let config = cli_flag_handler(matches);
 
     gui_init(config)
 }
 
-#[cfg(not(feature = "cosmic"))]
-async fn tui_init(config: Option<Api>) -> Result<()> {
-    use color_eyre::eyre::Error;
-    use server::setup::thread_init;
-    use types::Either;
-
-    match config {
-        Some(c) => thread_init(Either::Left(c)).await,
-        None => Err(Error::msg("Failed to find configuration")),
-    }
+async fn tui_init(source: Option<ConfigSource>) -> Result<()> {
+    let source = source.ok_or_else(|| Error::msg("Failed to find configuration"))?;
+    let (handle, commands) = control::channel();
+    let signal_handle = handle.clone();
+    tokio::spawn(async move {
+        if let Err(e) = control::forward_signals(signal_handle).await {
+            eprintln!("Failed to listen for signals: {e}");
+        }
+    });
+    thread_init(source, handle, commands).await
 }
 
 #[cfg(feature = "cosmic")]
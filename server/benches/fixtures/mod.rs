@@ -0,0 +1,51 @@
+//! A made up player with full top plays, grown from one user, score and most played beatmap as
+//! the osu! API sends them. Shared by the benchmarks and the tests of the state file
+
+use std::time::Duration;
+
+use rosu_v2::prelude::{MostPlayedMap, Score, UserExtended};
+
+pub fn user() -> UserExtended {
+    serde_json::from_str(include_str!("user.json")).expect("invalid user fixture")
+}
+
+/// `count` scores on different beatmaps, best first
+pub fn scores(count: u32) -> Vec<Score> {
+    let seed: Score =
+        serde_json::from_str(include_str!("score.json")).expect("invalid score fixture");
+    (0..count)
+        .map(|i| {
+            let mut score = seed.clone();
+            score.id += u64::from(i);
+            score.map_id += i;
+            score.pp = score.pp.map(|pp| pp * 0.99_f32.powi(i as i32));
+            score.ended_at -= Duration::from_secs(u64::from(i) * 86_400);
+            if let Some(map) = score.map.as_mut() {
+                map.map_id = score.map_id;
+                map.mapset_id += i;
+                map.version = format!("{} {i}", map.version);
+            }
+            if let Some(mapset) = score.mapset.as_mut() {
+                mapset.mapset_id += i;
+                mapset.title = format!("{} {i}", mapset.title);
+            }
+            score
+        })
+        .collect()
+}
+
+/// `count` beatmaps, most played first
+pub fn most_played(count: u32) -> Vec<MostPlayedMap> {
+    let seed: MostPlayedMap = serde_json::from_str(include_str!("most_played.json"))
+        .expect("invalid most played fixture");
+    (0..count)
+        .map(|i| {
+            let mut most_played = seed.clone();
+            most_played.count -= i as usize;
+            most_played.map_id += i;
+            most_played.map.map_id = most_played.map_id;
+            most_played.mapset.mapset_id += i;
+            most_played
+        })
+        .collect()
+}
@@ -0,0 +1,102 @@
+//! Size and serialization time of every payload format.
+//!
+//! Measured on the state of a made up player with full top plays, see `fixtures`. Pass the
+//! state file of a tracking session to measure that instead:
+//! `cargo bench -p server --bench serialization -- /path/to/state.json`
+
+use std::{
+    hint::black_box,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use server::structs::{Format, Session, TrackedData, WsKind, encode};
+use tokio_tungstenite::tungstenite::Message;
+
+mod fixtures;
+
+const ITERATIONS: u32 = 200;
+
+/// The part of the state file we need
+#[derive(Deserialize)]
+struct Persisted {
+    data: TrackedData,
+}
+
+fn main() {
+    let path = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from);
+    let data = match &path {
+        Some(path) => {
+            let bytes = std::fs::read(path)
+                .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+            serde_json::from_slice::<Persisted>(&bytes)
+                .expect("invalid state file")
+                .data
+        }
+        None => fixture(),
+    };
+
+    println!(
+        "{:<12} {:<8} {:>10} {:>12}",
+        "format", "channel", "bytes", "time"
+    );
+    for format in Format::ALL {
+        let mut total_size = 0;
+        let mut total_time = Duration::ZERO;
+        for kind in WsKind::ALL {
+            let (size, time) = measure(kind, format, &data);
+            println!(
+                "{:<12} {:<8} {:>10} {:>12?}",
+                format!("{format:?}"),
+                kind.name(),
+                size,
+                time
+            );
+            total_size += size;
+            total_time += time;
+        }
+        println!(
+            "{:<12} {:<8} {:>10} {:>12?}\n",
+            format!("{format:?}"),
+            "total",
+            total_size,
+            total_time
+        );
+    }
+}
+
+/// A session with the most data the API sends for each channel
+fn fixture() -> TrackedData {
+    let user = fixtures::user();
+    let scores = fixtures::scores(100);
+    let mut data = TrackedData::new_with(
+        user.clone(),
+        scores.clone(),
+        scores[..10].to_vec(),
+        scores[..20].to_vec(),
+        None,
+    );
+    data.user_pinned = Some(scores[..5].to_vec());
+    data.user_most_played = Some(fixtures::most_played(50));
+    data.grind.record(&scores[..20]);
+    data.session = Some(Session::start(user, Some(scores[3..].to_vec())));
+    data
+}
+
+/// Encoded size and average encoding time of one payload
+fn measure(kind: WsKind, format: Format, data: &TrackedData) -> (usize, Duration) {
+    let size = match encode(kind, format, data).expect("failed to encode") {
+        Message::Text(text) => text.len(),
+        Message::Binary(bytes) => bytes.len(),
+        _ => 0,
+    };
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(encode(black_box(kind), format, black_box(data)).ok());
+    }
+    (size, start.elapsed() / ITERATIONS)
+}
@@ -0,0 +1,398 @@
+use http_body_util::Full;
+use hyper::{
+    Request, Response, StatusCode,
+    body::Bytes,
+    header::{
+        AUTHORIZATION, COOKIE, HOST, HeaderValue, ORIGIN, SEC_WEBSOCKET_PROTOCOL, WWW_AUTHENTICATE,
+    },
+};
+use tracing::debug;
+use types::Access;
+
+/// Prefix of the `Sec-WebSocket-Protocol` entry carrying a token,
+/// for browsers which can't set headers on websocket requests
+pub const TOKEN_PROTOCOL_PREFIX: &str = "token.";
+/// Cookie set by overlay pages, so the stylesheets and images they load are authorized too,
+/// and by the OAuth login for the callback osu! redirects to
+pub const TOKEN_COOKIE: &str = "rosu_tracker_token";
+
+/// A request which passed the access checks
+#[derive(Debug, Default)]
+pub(crate) struct Granted {
+    /// Subprotocol the token was sent in, which has to be echoed back
+    pub protocol: Option<HeaderValue>,
+}
+
+/// Where a token was found in the request
+enum Credential<'a> {
+    Header(&'a str),
+    Query(&'a str),
+    Cookie(&'a str),
+    Protocol(&'a str, &'a str),
+}
+
+impl<'a> Credential<'a> {
+    fn token(&self) -> &'a str {
+        match self {
+            Credential::Header(token) | Credential::Query(token) | Credential::Cookie(token) => {
+                token
+            }
+            Credential::Protocol(_, token) => token,
+        }
+    }
+}
+
+/// Reject requests coming from a browser page which isn't in `allowed_origins`.
+/// Requests without an `Origin` header (e.g. scripts or OBS) are allowed
+pub(crate) fn check_origin<B>(
+    access: &Access,
+    req: &Request<B>,
+) -> Result<(), Response<Full<Bytes>>> {
+    if access.allowed_origins.is_empty() {
+        return Ok(());
+    }
+    let Some(origin) = req.headers().get(ORIGIN) else {
+        return Ok(());
+    };
+    let allowed = origin.to_str().is_ok_and(|origin| {
+        access
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    });
+    if allowed {
+        Ok(())
+    } else {
+        debug!("Rejected origin {:?}", origin);
+        Err(reject(StatusCode::FORBIDDEN, "Origin not allowed"))
+    }
+}
+
+/// Check the origin and token of a request for `channel`.
+/// Every channel is public if no tokens are configured. Browsers send cookies along with
+/// requests from any page, so those are only accepted from the tracker's own pages
+pub(crate) fn authorize<B>(
+    access: &Access,
+    req: &Request<B>,
+    channel: &str,
+) -> Result<Granted, Response<Full<Bytes>>> {
+    check_origin(access, req)?;
+    if access.tokens.is_empty() {
+        return Ok(Granted::default());
+    }
+    let Some(credential) = find_credential(req) else {
+        return Err(unauthorized());
+    };
+    if matches!(credential, Credential::Cookie(_)) && !same_host(req) {
+        debug!("Rejected cookie sent from {:?}", req.headers().get(ORIGIN));
+        return Err(reject(
+            StatusCode::FORBIDDEN,
+            "Cookies are only accepted from the tracker's own pages",
+        ));
+    }
+    let Some(token) = access
+        .tokens
+        .iter()
+        .find(|token| tokens_match(credential.token(), &token.token))
+    else {
+        return Err(unauthorized());
+    };
+    let permitted = token
+        .channels
+        .as_ref()
+        .is_none_or(|channels| channels.iter().any(|c| c.eq_ignore_ascii_case(channel)));
+    if !permitted {
+        return Err(reject(
+            StatusCode::FORBIDDEN,
+            "Token has no access to this channel",
+        ));
+    }
+    let protocol = match credential {
+        Credential::Protocol(protocol, _) => HeaderValue::from_str(protocol).ok(),
+        _ => None,
+    };
+    Ok(Granted { protocol })
+}
+
+fn find_credential<B>(req: &Request<B>) -> Option<Credential<'_>> {
+    let headers = req.headers();
+    if let Some(token) = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(Credential::Header(token));
+    }
+    if let Some(token) = query_token(req) {
+        return Some(Credential::Query(token));
+    }
+    if let Some(token) = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(TOKEN_COOKIE)
+                .and_then(|rest| rest.strip_prefix('='))
+        })
+    {
+        return Some(Credential::Cookie(token));
+    }
+    headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .find_map(|protocol| {
+            protocol
+                .strip_prefix(TOKEN_PROTOCOL_PREFIX)
+                .map(|token| Credential::Protocol(protocol, token))
+        })
+}
+
+/// Whether the request has no `Origin`, or comes from a page served on the local address it
+/// is sent to (`http://127.0.0.1:<port>` or `http://localhost:<port>`)
+fn same_host<B>(req: &Request<B>) -> bool {
+    let Some(origin) = req.headers().get(ORIGIN) else {
+        return true;
+    };
+    let origin = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.strip_prefix("http://"));
+    let host = req.headers().get(HOST).and_then(|host| host.to_str().ok());
+    let (Some(origin), Some(host)) = (origin, host) else {
+        return false;
+    };
+    let name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    origin.eq_ignore_ascii_case(host)
+        && (name == constants::HOST || name.eq_ignore_ascii_case("localhost"))
+}
+
+/// Token passed as `?token=...`
+pub(crate) fn query_token<B>(req: &Request<B>) -> Option<&str> {
+    req.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    })
+}
+
+/// `Set-Cookie` value carrying `token` to later requests under `path`. `None` if the token can't be
+/// put in a cookie as it is
+pub(crate) fn token_cookie(token: &str, path: &str, same_site: &str) -> Option<HeaderValue> {
+    let cookie_safe = token
+        .bytes()
+        .all(|b| b.is_ascii_graphic() && !matches!(b, b';' | b',' | b'"' | b'\\'));
+    if !cookie_safe {
+        return None;
+    }
+    HeaderValue::from_str(&format!(
+        "{TOKEN_COOKIE}={token}; Path={path}; HttpOnly; SameSite={same_site}"
+    ))
+    .ok()
+}
+
+fn unauthorized() -> Response<Full<Bytes>> {
+    let mut res = reject(StatusCode::UNAUTHORIZED, "Invalid or missing token");
+    res.headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    res
+}
+
+pub(crate) fn reject(status: StatusCode, body: &'static str) -> Response<Full<Bytes>> {
+    let mut res = Response::new(Full::new(Bytes::from_static(body.as_bytes())));
+    *res.status_mut() = status;
+    res
+}
+
+/// Compare tokens without bailing out on the first mismatching byte
+pub(crate) fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::AccessToken;
+
+    fn access(tokens: &[(&str, Option<&[&str]>)]) -> Access {
+        Access {
+            tokens: tokens
+                .iter()
+                .map(|(token, channels)| AccessToken {
+                    token: (*token).to_owned(),
+                    channels: channels
+                        .map(|channels| channels.iter().map(|c| (*c).to_owned()).collect()),
+                })
+                .collect(),
+            allowed_origins: Vec::new(),
+        }
+    }
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut req = Request::builder().uri(uri).header(HOST, "127.0.0.1:7272");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(()).unwrap()
+    }
+
+    fn status(result: Result<Granted, Response<Full<Bytes>>>) -> StatusCode {
+        match result {
+            Ok(_) => StatusCode::OK,
+            Err(res) => res.status(),
+        }
+    }
+
+    #[test]
+    fn public_without_tokens() {
+        let access = access(&[]);
+        let req = request("/user", &[("origin", "https://example.com")]);
+        assert_eq!(status(authorize(&access, &req, "user")), StatusCode::OK);
+    }
+
+    #[test]
+    fn accepts_every_credential() {
+        let access = access(&[("secret", None)]);
+        let requests = [
+            request("/user", &[("authorization", "Bearer secret")]),
+            request("/user?mode=osu&token=secret", &[]),
+            request(
+                "/user",
+                &[("cookie", "theme=dark; rosu_tracker_token=secret")],
+            ),
+            request("/user", &[("sec-websocket-protocol", "json, token.secret")]),
+        ];
+        for req in requests {
+            assert_eq!(status(authorize(&access, &req, "user")), StatusCode::OK);
+        }
+    }
+
+    #[test]
+    fn echoes_token_protocol() {
+        let access = access(&[("secret", None)]);
+        let req = request("/user", &[("sec-websocket-protocol", "json, token.secret")]);
+        let granted = authorize(&access, &req, "user").unwrap();
+        assert_eq!(granted.protocol.unwrap(), "token.secret");
+
+        let req = request("/user", &[("authorization", "Bearer secret")]);
+        assert!(authorize(&access, &req, "user").unwrap().protocol.is_none());
+    }
+
+    #[test]
+    fn rejects_missing_and_wrong_tokens() {
+        let access = access(&[("secret", None)]);
+        for req in [
+            request("/user", &[]),
+            request("/user?token=secre", &[]),
+            request("/user", &[("authorization", "Basic secret")]),
+            request("/user", &[("sec-websocket-protocol", "token.other")]),
+        ] {
+            let res = authorize(&access, &req, "user").unwrap_err();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(res.headers()[WWW_AUTHENTICATE], "Bearer");
+        }
+    }
+
+    #[test]
+    fn limits_tokens_to_their_channels() {
+        let access = access(&[("overlay", Some(&["overlay", "User"])), ("all", None)]);
+        let req = request("/user?token=overlay", &[]);
+        assert_eq!(status(authorize(&access, &req, "user")), StatusCode::OK);
+        assert_eq!(status(authorize(&access, &req, "overlay")), StatusCode::OK);
+        assert_eq!(
+            status(authorize(&access, &req, "compare")),
+            StatusCode::FORBIDDEN
+        );
+        let req = request("/user?token=all", &[]);
+        assert_eq!(status(authorize(&access, &req, "compare")), StatusCode::OK);
+    }
+
+    #[test]
+    fn rejects_cross_site_cookies() {
+        let access = access(&[("secret", None)]);
+        let cookie = ("cookie", "rosu_tracker_token=secret");
+        for origin in ["http://127.0.0.1:7272", "http://LOCALHOST:7272"] {
+            let host = origin.trim_start_matches("http://");
+            let req = Request::builder()
+                .uri("/overlay/style.css")
+                .header(HOST, host)
+                .header(COOKIE, cookie.1)
+                .header(ORIGIN, origin)
+                .body(())
+                .unwrap();
+            assert_eq!(status(authorize(&access, &req, "overlay")), StatusCode::OK);
+        }
+        for origin in [
+            "https://example.com",
+            "http://127.0.0.1:8080",
+            "https://127.0.0.1:7272",
+            "null",
+        ] {
+            let req = request("/overlay/style.css", &[cookie, ("origin", origin)]);
+            assert_eq!(
+                status(authorize(&access, &req, "overlay")),
+                StatusCode::FORBIDDEN
+            );
+        }
+        // A rebound domain pointing at the tracker
+        let req = Request::builder()
+            .uri("/overlay/style.css")
+            .header(HOST, "evil.example:7272")
+            .header(COOKIE, cookie.1)
+            .header(ORIGIN, "http://evil.example:7272")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            status(authorize(&access, &req, "overlay")),
+            StatusCode::FORBIDDEN
+        );
+        // Tokens the page passes itself are fine from anywhere
+        let req = request(
+            "/user?token=secret",
+            &[cookie, ("origin", "https://example.com")],
+        );
+        assert_eq!(status(authorize(&access, &req, "user")), StatusCode::OK);
+    }
+
+    #[test]
+    fn checks_allowed_origins() {
+        let mut access = access(&[("secret", None)]);
+        access.allowed_origins = vec!["http://localhost:8080".to_owned()];
+        let allowed = request("/user?token=secret", &[("origin", "http://LocalHost:8080")]);
+        assert_eq!(status(authorize(&access, &allowed, "user")), StatusCode::OK);
+        let other = request("/user?token=secret", &[("origin", "https://example.com")]);
+        assert_eq!(
+            status(authorize(&access, &other, "user")),
+            StatusCode::FORBIDDEN
+        );
+        let no_origin = request("/user?token=secret", &[]);
+        assert_eq!(
+            status(authorize(&access, &no_origin, "user")),
+            StatusCode::OK
+        );
+        // The origin is checked before the token
+        let no_token = request("/user", &[("origin", "https://example.com")]);
+        assert_eq!(
+            status(authorize(&access, &no_token, "user")),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn compares_whole_tokens() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
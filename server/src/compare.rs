@@ -0,0 +1,389 @@
+//! The tracked user side by side with other players, e.g. for team scrims.
+//!
+//! Players from `compare_users` are fetched in the tracked user's mode, less often than the
+//! tracked user. Their session starts when they're first fetched and restarts with the tracked
+//! user's session
+
+use std::collections::HashMap;
+
+use rosu_v2::{
+    Osu,
+    prelude::{GameMode, Score, UserExtended},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+use types::{
+    Api,
+    payload::{
+        BeatmapSummary, CommonBeatmap, CompareSummary, ComparedPlayer, ComparedScore, Profile,
+        Rankings, ScoreSummary, SessionGains, Statistics,
+    },
+};
+
+use crate::structs::{Arm, TrackedData};
+
+/// A player from `compare_users`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ComparedUser {
+    pub user: UserExtended,
+    /// State of the user when their session started
+    pub initial: UserExtended,
+    pub top_scores: Option<Vec<Score>>,
+}
+
+/// Fetch the players from `user_ids` in the mode of the tracked user, keeping the previous data
+/// of players which fail. Returns `false` if the tracked user wasn't fetched yet or changed
+pub(crate) async fn fetch(
+    osu: &Osu,
+    tracked_data: &Arm<TrackedData>,
+    api_conf: &Api,
+    user_ids: &[u32],
+) -> bool {
+    let tracked = |tracked_data: &TrackedData| {
+        tracked_data.user_extended.as_ref().map(|user| {
+            let mode = api_conf.mode.map_or(user.mode, GameMode::from);
+            (user.user_id, mode)
+        })
+    };
+    let (tracked, previous) = {
+        let tracked_data = tracked_data.lock().await;
+        (tracked(&tracked_data), tracked_data.compared.clone())
+    };
+    let Some((tracked_id, mode)) = tracked else {
+        return false;
+    };
+    let mut compared: Vec<ComparedUser> = Vec::with_capacity(user_ids.len());
+    for &user_id in user_ids {
+        if user_id == tracked_id || compared.iter().any(|c| c.user.user_id == user_id) {
+            continue;
+        }
+        let old = previous.iter().find(|c| c.user.user_id == user_id);
+        let user = match osu.user(user_id).mode(mode).await {
+            Ok(user) => user,
+            Err(e) => {
+                error!("Failed to fetch compared user {user_id}: {e}");
+                compared.extend(old.cloned());
+                continue;
+            }
+        };
+        // Top plays only change with the statistics
+        let unchanged = old.filter(|old| old.user.statistics == user.statistics);
+        let top_scores = match unchanged.and_then(|old| old.top_scores.clone()) {
+            Some(top_scores) => Some(top_scores),
+            None => osu
+                .user_scores(user_id)
+                .mode(mode)
+                .limit(100)
+                .await
+                .inspect_err(|e| error!("Failed to fetch top plays of {user_id}: {e}"))
+                .ok()
+                .or_else(|| old.and_then(|old| old.top_scores.clone())),
+        };
+        let initial = old.map_or_else(|| user.clone(), |old| old.initial.clone());
+        compared.push(ComparedUser {
+            user,
+            initial,
+            top_scores,
+        });
+    }
+    let mut tracked_data = tracked_data.lock().await;
+    // The tracked user was reset while fetching
+    if tracked(&tracked_data) != Some((tracked_id, mode)) {
+        return false;
+    }
+    debug!("Fetched {} compared users", compared.len());
+    tracked_data.compared = compared;
+    true
+}
+
+/// Start the session of every compared player over
+pub(crate) fn restart_sessions(compared: &mut [ComparedUser]) {
+    for compared in compared {
+        compared.initial = compared.user.clone();
+    }
+}
+
+struct Player<'a> {
+    user: &'a UserExtended,
+    initial: Option<&'a UserExtended>,
+    top_scores: &'a [Score],
+    tracked: bool,
+}
+
+/// `None` until the tracked user and someone to compare with were fetched
+pub fn summary(data: &TrackedData) -> Option<CompareSummary> {
+    let user = data.user_extended.as_ref()?;
+    if data.compared.is_empty() {
+        return None;
+    }
+    let tracked = Player {
+        user,
+        initial: data.session.as_ref().map(|session| &session.user_extended),
+        top_scores: data.user_scores.as_deref().unwrap_or_default(),
+        tracked: true,
+    };
+    let others = data.compared.iter().map(|compared| Player {
+        user: &compared.user,
+        initial: Some(&compared.initial),
+        top_scores: compared.top_scores.as_deref().unwrap_or_default(),
+        tracked: false,
+    });
+    let players: Vec<Player> = std::iter::once(tracked).chain(others).collect();
+    let compared: Vec<ComparedPlayer> = players
+        .iter()
+        .map(|player| ComparedPlayer {
+            profile: Profile::from(player.user),
+            tracked: player.tracked,
+            session: session_gains(player.user, player.initial),
+        })
+        .collect();
+    Some(CompareSummary {
+        rankings: rankings(&compared),
+        common_beatmaps: common_beatmaps(&players),
+        players: compared,
+    })
+}
+
+fn session_gains(user: &UserExtended, initial: Option<&UserExtended>) -> SessionGains {
+    let statistics = user.statistics.as_ref();
+    let initial = initial.and_then(|initial| initial.statistics.as_ref());
+    let (Some(current), Some(initial)) = (statistics, initial) else {
+        return SessionGains::default();
+    };
+    SessionGains {
+        pp: current.pp - initial.pp,
+        accuracy: current.accuracy - initial.accuracy,
+        playcount: current.playcount as i64 - initial.playcount as i64,
+        ranked_score: current.ranked_score as i64 - initial.ranked_score as i64,
+        global_rank: current
+            .global_rank
+            .zip(initial.global_rank)
+            .map(|(current, initial)| initial as i64 - current as i64),
+    }
+}
+
+fn rankings(players: &[ComparedPlayer]) -> Rankings {
+    let ranked = |value: &dyn Fn(&ComparedPlayer) -> f64| {
+        let mut players: Vec<&ComparedPlayer> = players.iter().collect();
+        players.sort_by(|a, b| value(b).total_cmp(&value(a)));
+        players
+            .iter()
+            .map(|player| player.profile.user_id)
+            .collect()
+    };
+    // Players without statistics come last
+    let statistic = |value: fn(&Statistics) -> f64| {
+        move |player: &ComparedPlayer| player.profile.statistics.as_ref().map_or(-1.0, value)
+    };
+    Rankings {
+        pp: ranked(&statistic(|s| s.pp as f64)),
+        accuracy: ranked(&statistic(|s| s.accuracy as f64)),
+        session_pp_gain: ranked(&|p| p.session.pp as f64),
+        session_playcount: ranked(&|p| p.session.playcount as f64),
+    }
+}
+
+/// Beatmaps in the top plays of at least two players
+fn common_beatmaps(players: &[Player]) -> Vec<CommonBeatmap> {
+    let mut by_map: HashMap<u32, Vec<(&Player, usize, &Score)>> = HashMap::new();
+    for player in players {
+        for (position, score) in player.top_scores.iter().enumerate() {
+            by_map
+                .entry(score.map_id)
+                .or_default()
+                .push((player, position, score));
+        }
+    }
+    let pp = |score: &Score| score.pp.unwrap_or_default();
+    let mut common: Vec<Vec<(&Player, usize, &Score)>> = by_map
+        .into_values()
+        .filter(|scores| scores.len() > 1)
+        .collect();
+    for scores in &mut common {
+        scores.sort_by(|a, b| pp(b.2).total_cmp(&pp(a.2)));
+    }
+    common.sort_by(|a, b| {
+        b.len()
+            .cmp(&a.len())
+            .then_with(|| pp(b[0].2).total_cmp(&pp(a[0].2)))
+            .then_with(|| a[0].2.map_id.cmp(&b[0].2.map_id))
+    });
+    common
+        .into_iter()
+        .map(|scores| {
+            let (_, _, best) = scores[0];
+            CommonBeatmap {
+                beatmap: best
+                    .map
+                    .as_deref()
+                    .map(|map| BeatmapSummary::new(map, best.mapset.as_deref())),
+                scores: scores
+                    .into_iter()
+                    .map(|(player, position, score)| ComparedScore {
+                        username: player.user.username.to_string(),
+                        top_position: position as u32 + 1,
+                        score: ScoreSummary::from(score),
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, structs::Session};
+
+    fn user(user_id: u32, username: &str, pp: f32, accuracy: f32, playcount: u32) -> UserExtended {
+        let mut user = fixtures::user();
+        user.user_id = user_id;
+        user.username = username.into();
+        let statistics = user.statistics.as_mut().unwrap();
+        statistics.pp = pp;
+        statistics.accuracy = accuracy;
+        statistics.playcount = playcount;
+        user
+    }
+
+    /// Top plays on `map_ids`, best first
+    fn top_scores(user_id: u32, map_ids: &[u32], best_pp: f32) -> Vec<Score> {
+        let mut scores = fixtures::scores(map_ids.len() as u32);
+        for (i, (score, &map_id)) in scores.iter_mut().zip(map_ids).enumerate() {
+            score.user_id = user_id;
+            score.map_id = map_id;
+            score.pp = Some(best_pp - i as f32 * 10.0);
+            if let Some(map) = score.map.as_mut() {
+                map.map_id = map_id;
+            }
+        }
+        scores
+    }
+
+    fn compared(user: UserExtended, initial: UserExtended, top_scores: Vec<Score>) -> ComparedUser {
+        ComparedUser {
+            user,
+            initial,
+            top_scores: Some(top_scores),
+        }
+    }
+
+    /// The tracked user with a session started at `initial`
+    fn tracked(user: UserExtended, initial: UserExtended, top_scores: Vec<Score>) -> TrackedData {
+        let mut data = TrackedData::new_with(user, top_scores, None, None, None);
+        data.session = Some(Session::start(initial, None));
+        data
+    }
+
+    fn usernames(scores: &[ComparedScore]) -> Vec<(&str, u32)> {
+        scores
+            .iter()
+            .map(|score| (score.username.as_str(), score.top_position))
+            .collect()
+    }
+
+    #[test]
+    fn needs_someone_to_compare_with() {
+        let mut data = TrackedData::new();
+        assert_eq!(summary(&data), None);
+        data.compared = vec![compared(
+            user(2, "b", 100.0, 90.0, 10),
+            user(2, "b", 100.0, 90.0, 10),
+            Vec::new(),
+        )];
+        assert_eq!(summary(&data), None);
+        let data = TrackedData::new_with(user(1, "a", 100.0, 90.0, 10), None, None, None, None);
+        assert_eq!(summary(&data), None);
+    }
+
+    #[test]
+    fn ranks_players() {
+        let mut data = tracked(
+            user(1, "a", 5000.0, 98.0, 120),
+            user(1, "a", 4990.0, 98.0, 100),
+            Vec::new(),
+        );
+        data.compared = vec![
+            compared(
+                user(2, "b", 6000.0, 97.0, 50),
+                user(2, "b", 5950.0, 97.0, 45),
+                Vec::new(),
+            ),
+            compared(
+                user(3, "c", 5000.0, 99.0, 80),
+                user(3, "c", 5000.0, 99.0, 80),
+                Vec::new(),
+            ),
+        ];
+        let summary = summary(&data).unwrap();
+        let ids: Vec<u32> = summary.players.iter().map(|p| p.profile.user_id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        assert!(summary.players[0].tracked);
+        assert!(!summary.players[1].tracked);
+        assert_eq!(summary.players[0].session.pp, 10.0);
+        assert_eq!(summary.players[0].session.playcount, 20);
+        // Ties keep the order of the players
+        assert_eq!(summary.rankings.pp, [2, 1, 3]);
+        assert_eq!(summary.rankings.accuracy, [3, 1, 2]);
+        assert_eq!(summary.rankings.session_pp_gain, [2, 1, 3]);
+        assert_eq!(summary.rankings.session_playcount, [1, 2, 3]);
+    }
+
+    #[test]
+    fn players_without_session_or_statistics() {
+        // The tracked user's session didn't start yet
+        let mut data =
+            TrackedData::new_with(user(1, "a", 5000.0, 98.0, 120), None, None, None, None);
+        let mut unranked = user(3, "c", 0.0, 0.0, 0);
+        unranked.statistics = None;
+        data.compared = vec![
+            compared(
+                user(2, "b", 4000.0, 97.0, 50),
+                user(2, "b", 3990.0, 97.0, 40),
+                Vec::new(),
+            ),
+            compared(unranked.clone(), unranked, Vec::new()),
+        ];
+        let summary = summary(&data).unwrap();
+        assert_eq!(summary.players[0].session, SessionGains::default());
+        assert_eq!(summary.players[2].session, SessionGains::default());
+        assert_eq!(summary.players[2].profile.statistics, None);
+        assert_eq!(summary.rankings.pp, [1, 2, 3]);
+        assert_eq!(summary.rankings.accuracy, [1, 2, 3]);
+        assert_eq!(summary.rankings.session_pp_gain, [2, 1, 3]);
+        assert_eq!(summary.rankings.session_playcount, [2, 1, 3]);
+        assert!(summary.common_beatmaps.is_empty());
+    }
+
+    #[test]
+    fn no_common_beatmaps() {
+        let a = user(1, "a", 5000.0, 98.0, 120);
+        let b = user(2, "b", 4000.0, 97.0, 50);
+        let mut data = tracked(a.clone(), a, top_scores(1, &[10, 11, 12], 300.0));
+        data.compared = vec![compared(b.clone(), b, top_scores(2, &[20, 21], 250.0))];
+        assert!(summary(&data).unwrap().common_beatmaps.is_empty());
+    }
+
+    #[test]
+    fn groups_shared_beatmaps() {
+        let a = user(1, "a", 5000.0, 98.0, 120);
+        let b = user(2, "b", 4000.0, 97.0, 50);
+        let c = user(3, "c", 3000.0, 96.0, 30);
+        // 10 is in everyone's top plays, 11 only in a's and c's, 12 in a's alone
+        let mut data = tracked(a.clone(), a, top_scores(1, &[11, 10, 12], 300.0));
+        data.compared = vec![
+            compared(b.clone(), b, top_scores(2, &[20, 10], 250.0)),
+            compared(c.clone(), c, top_scores(3, &[10, 11], 320.0)),
+        ];
+        let common = summary(&data).unwrap().common_beatmaps;
+        assert_eq!(common.len(), 2);
+        // Shared by more players first
+        assert_eq!(common[0].beatmap.as_ref().unwrap().map_id, 10);
+        assert_eq!(usernames(&common[0].scores), [("c", 1), ("a", 2), ("b", 2)]);
+        assert_eq!(common[1].beatmap.as_ref().unwrap().map_id, 11);
+        // Best pp first, whatever the position in the player's top plays
+        assert_eq!(usernames(&common[1].scores), [("c", 2), ("a", 1)]);
+        let pp: Vec<Option<f32>> = common[1].scores.iter().map(|s| s.score.pp).collect();
+        assert_eq!(pp, [Some(310.0), Some(300.0)]);
+    }
+}
@@ -0,0 +1,192 @@
+use color_eyre::{Result, eyre::eyre};
+use constants::{
+    CONTROL_REFRESH_ENDPOINT, CONTROL_SESSION_RESET_ENDPOINT, CONTROL_TRACK_ENDPOINT,
+    CONTROL_UNTRACK_ENDPOINT,
+};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::Bytes,
+    header::{AUTHORIZATION, CONTENT_TYPE, HOST},
+};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, sync::mpsc};
+use tracing::{debug, info};
+use types::Config;
+
+use crate::auth::{reject, tokens_match};
+
+/// Commands that can be sent to a running tracker
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Re-read the configuration and apply it without dropping clients
+    Reload,
+    /// Close all clients, persist the state and stop every thread
+    Shutdown,
+    /// Start tracking another user instead of the configured one, also after reloads
+    Track(String),
+    /// Stop tracking anyone until the next `Track`, also after reloads
+    Untrack,
+    /// Start a new session from the current user state
+    ResetSession,
+    /// Refetch everything right away
+    Refresh,
+}
+
+/// Cloneable handle used to control a running tracker
+#[derive(Debug, Clone)]
+pub struct Handle(mpsc::UnboundedSender<Command>);
+
+pub type Receiver = mpsc::UnboundedReceiver<Command>;
+
+impl Handle {
+    /// Returns `false` if the tracker isn't running anymore
+    pub fn send(&self, command: Command) -> bool {
+        self.0.send(command).is_ok()
+    }
+}
+
+pub fn channel() -> (Handle, Receiver) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (Handle(tx), rx)
+}
+
+/// Translate process signals into commands.
+/// SIGTERM and SIGINT shut the tracker down, SIGHUP reloads the configuration
+#[cfg(unix)]
+pub async fn forward_signals(handle: Handle) -> std::io::Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    loop {
+        let command = tokio::select! {
+            _ = terminate.recv() => Command::Shutdown,
+            _ = interrupt.recv() => Command::Shutdown,
+            _ = hangup.recv() => Command::Reload,
+        };
+        info!("Received signal, sending {:?}", command);
+        let shutdown = command == Command::Shutdown;
+        if !handle.send(command) || shutdown {
+            debug!("Stopped forwarding signals");
+            return Ok(());
+        }
+    }
+}
+
+/// Translate Ctrl+C into a shutdown. There's no reload signal outside of unix
+#[cfg(not(unix))]
+pub async fn forward_signals(handle: Handle) -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await?;
+    info!("Received Ctrl+C, shutting down");
+    handle.send(Command::Shutdown);
+    Ok(())
+}
+
+/// Body of `POST /control/track`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrackRequest {
+    pub username: String,
+}
+
+/// Largest accepted control request body, in bytes
+const MAX_BODY_SIZE: usize = 1024;
+
+/// Serve one of the `/control/*` endpoints
+pub(crate) async fn serve_control(
+    handle: Handle,
+    config: &Config,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<Full<Bytes>>> {
+    let Some(token) = config.control_token.as_deref() else {
+        return Ok(reject(StatusCode::FORBIDDEN, "Control API is disabled"));
+    };
+    let authorized = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| tokens_match(given, token));
+    if !authorized {
+        return Ok(reject(StatusCode::UNAUTHORIZED, "Invalid or missing token"));
+    }
+    if req.method() != Method::POST {
+        return Ok(reject(StatusCode::METHOD_NOT_ALLOWED, "Use POST"));
+    }
+
+    let command = match req.uri().path() {
+        CONTROL_TRACK_ENDPOINT => {
+            let body = Limited::new(req.into_body(), MAX_BODY_SIZE)
+                .collect()
+                .await
+                .map_err(|e| eyre!("Failed to read request body: {e}"))?
+                .to_bytes();
+            match serde_json::from_slice::<TrackRequest>(&body) {
+                Ok(request) if !request.username.trim().is_empty() => {
+                    Command::Track(request.username.trim().to_owned())
+                }
+                _ => {
+                    return Ok(reject(
+                        StatusCode::BAD_REQUEST,
+                        r#"Expected {"username": "<name>"}"#,
+                    ));
+                }
+            }
+        }
+        CONTROL_UNTRACK_ENDPOINT => Command::Untrack,
+        CONTROL_SESSION_RESET_ENDPOINT => Command::ResetSession,
+        CONTROL_REFRESH_ENDPOINT => Command::Refresh,
+        _ => return Ok(reject(StatusCode::NOT_FOUND, "Unknown control endpoint")),
+    };
+    debug!("Control request: {:?}", command);
+    if !handle.send(command) {
+        return Ok(reject(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Tracker is shutting down",
+        ));
+    }
+    Ok(reject(StatusCode::ACCEPTED, "Accepted"))
+}
+
+/// Send a command to a tracker running on this machine through its control API
+pub async fn send_remote(token: &str, port: u16, command: Command) -> Result<()> {
+    let (path, body) = match &command {
+        Command::Track(username) => (
+            CONTROL_TRACK_ENDPOINT,
+            serde_json::to_vec(&TrackRequest {
+                username: username.clone(),
+            })?,
+        ),
+        Command::Untrack => (CONTROL_UNTRACK_ENDPOINT, Vec::new()),
+        Command::ResetSession => (CONTROL_SESSION_RESET_ENDPOINT, Vec::new()),
+        Command::Refresh => (CONTROL_REFRESH_ENDPOINT, Vec::new()),
+        Command::Reload | Command::Shutdown => {
+            return Err(eyre!("{command:?} can only be sent with a signal"));
+        }
+    };
+    let host = format!("{}:{port}", constants::HOST);
+    let stream = TcpStream::connect(&host).await?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            debug!("Connection failed: {:?}", err);
+        }
+    });
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(path)
+        .header(HOST, host)
+        .header(AUTHORIZATION, format!("Bearer {token}"))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+    let res = sender.send_request(req).await?;
+    let status = res.status();
+    let body = res.collect().await?.to_bytes();
+    if !status.is_success() {
+        return Err(eyre!("{status}: {}", String::from_utf8_lossy(&body)));
+    }
+    Ok(())
+}
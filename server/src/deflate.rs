@@ -0,0 +1,347 @@
+//! permessage-deflate (RFC 7692).
+//!
+//! Every message sent is compressed on its own, as negotiated with `server_no_context_takeover`.
+//! Clients may compress any data message they send, which [`Inflate`] undoes before tungstenite
+//! sees it, as tungstenite rejects frames with RSV1 set
+
+use std::{
+    io::{self, Cursor},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::{
+    Message,
+    protocol::frame::{
+        Frame, FrameHeader,
+        coding::{Data, OpCode},
+    },
+};
+
+/// Value of `Sec-WebSocket-Extensions` once the extension is negotiated
+pub(crate) const RESPONSE: &str = "permessage-deflate; server_no_context_takeover";
+
+/// Smaller messages are sent uncompressed, which the extension allows
+const MIN_SIZE: usize = 128;
+
+/// Ends every sync flush, the RFC has it removed from the message
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Largest frame and inflated frame accepted from clients, which have nothing big to send
+const MAX_CLIENT_FRAME: usize = 1 << 20;
+
+/// Compress a text or binary message into a single frame with RSV1 set.
+/// Anything else is returned as it is
+pub(crate) fn compress(message: &Message) -> Message {
+    let (data, opcode) = match message {
+        Message::Text(text) => (text.as_bytes(), OpCode::Data(Data::Text)),
+        Message::Binary(data) => (&data[..], OpCode::Data(Data::Binary)),
+        _ => return message.clone(),
+    };
+    if data.len() < MIN_SIZE {
+        return message.clone();
+    }
+    let Some(compressed) = deflate(data) else {
+        return message.clone();
+    };
+    let mut frame = Frame::message(compressed, opcode, true);
+    frame.header_mut().rsv1 = true;
+    Message::Frame(frame)
+}
+
+/// Raw deflate with a sync flush, without the trailing empty block
+fn deflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut compress = Compress::new(Compression::fast(), false);
+    let mut out = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        let consumed = compress.total_in() as usize;
+        compress
+            .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+            .ok()?;
+        // The flush is complete once it left room in the output
+        if compress.total_in() as usize == data.len() && out.len() < out.capacity() {
+            break;
+        }
+        out.reserve(out.capacity());
+    }
+    if !out.ends_with(&TAIL) {
+        return None;
+    }
+    out.truncate(out.len() - TAIL.len());
+    Some(out)
+}
+
+/// Client connection which inflates the compressed messages read from it. Written data is
+/// passed through
+pub(crate) struct Inflate<S> {
+    inner: S,
+    /// `None` unless the extension was negotiated, frames are then read as they are
+    decompress: Option<Decompress>,
+    /// Whether the data message being read is compressed, its continuation frames don't say
+    compressed: bool,
+    /// Read from `inner`, but not a complete frame yet
+    input: Vec<u8>,
+    /// Frames ready to be read, from `read` on
+    output: Vec<u8>,
+    read: usize,
+}
+
+impl<S> Inflate<S> {
+    pub(crate) fn new(inner: S, negotiated: bool) -> Self {
+        Self {
+            inner,
+            decompress: negotiated.then(|| Decompress::new(false)),
+            compressed: false,
+            input: Vec::new(),
+            output: Vec::new(),
+            read: 0,
+        }
+    }
+
+    /// Move every complete frame from `input` to `output`, inflating compressed ones
+    fn process(&mut self) -> io::Result<()> {
+        let Some(decompress) = self.decompress.as_mut() else {
+            return Ok(());
+        };
+        loop {
+            let mut cursor = Cursor::new(&self.input);
+            let Some((mut header, len)) = FrameHeader::parse(&mut cursor).map_err(invalid)? else {
+                return Ok(());
+            };
+            if len > MAX_CLIENT_FRAME as u64 {
+                return Err(invalid("Frame too large"));
+            }
+            let start = cursor.position() as usize;
+            let end = start + len as usize;
+            if self.input.len() < end {
+                return Ok(());
+            }
+            let compressed = match header.opcode {
+                OpCode::Data(Data::Continue) => self.compressed,
+                OpCode::Data(_) => {
+                    self.compressed = header.rsv1;
+                    header.rsv1
+                }
+                OpCode::Control(_) => false,
+            };
+            if compressed {
+                let mut payload = self.input[start..end].to_vec();
+                if let Some(mask) = header.mask {
+                    payload
+                        .iter_mut()
+                        .zip(mask.iter().cycle())
+                        .for_each(|(byte, mask)| *byte ^= mask);
+                }
+                if header.is_final {
+                    payload.extend_from_slice(&TAIL);
+                }
+                let inflated = inflate(decompress, &payload)?;
+                // Masked again with the same key when formatted
+                header.rsv1 = false;
+                Frame::from_payload(header, inflated.into())
+                    .format(&mut self.output)
+                    .map_err(invalid)?;
+            } else {
+                self.output.extend_from_slice(&self.input[..end]);
+            }
+            self.input.drain(..end);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Inflate<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.decompress.is_none() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        loop {
+            if this.read < this.output.len() {
+                let ready = &this.output[this.read..];
+                let len = ready.len().min(buf.remaining());
+                buf.put_slice(&ready[..len]);
+                this.read += len;
+                return Poll::Ready(Ok(()));
+            }
+            this.output.clear();
+            this.read = 0;
+            let mut chunk = [0; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                // Closed, possibly in the middle of a frame, which tungstenite reports
+                this.output.append(&mut this.input);
+                if this.output.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                continue;
+            }
+            this.input.extend_from_slice(chunk.filled());
+            this.process()?;
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Inflate<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Inflate one frame of a message, keeping the window for the next ones
+fn inflate(decompress: &mut Decompress, input: &[u8]) -> io::Result<Vec<u8>> {
+    let start = decompress.total_in();
+    let mut out = Vec::with_capacity(input.len() * 4);
+    loop {
+        let consumed = (decompress.total_in() - start) as usize;
+        let status = decompress
+            .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+            .map_err(invalid)?;
+        if status == Status::StreamEnd {
+            // The client ended the message with a final block, the next one starts over
+            decompress.reset(false);
+            return Ok(out);
+        }
+        let consumed = (decompress.total_in() - start) as usize;
+        if consumed == input.len() && out.len() < out.capacity() {
+            return Ok(out);
+        }
+        if out.len() >= MAX_CLIENT_FRAME {
+            return Err(invalid("Inflated frame too large"));
+        }
+        out.reserve(out.capacity());
+    }
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio_tungstenite::{WebSocketStream, tungstenite::protocol::Role};
+
+    fn inflate_message(compressed: &[u8]) -> Vec<u8> {
+        let mut input = compressed.to_vec();
+        input.extend_from_slice(&TAIL);
+        let mut out = Vec::with_capacity(64 * 1024);
+        Decompress::new(false)
+            .decompress_vec(&input, &mut out, FlushDecompress::Sync)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn compresses_large_messages() {
+        let text = r#"{"version":1,"data":{"pp":727.0}}"#.repeat(100);
+        let Message::Frame(frame) = compress(&Message::text(text.clone())) else {
+            panic!("the message should be compressed");
+        };
+        assert!(frame.header().rsv1);
+        assert_eq!(frame.header().opcode, OpCode::Data(Data::Text));
+        assert!(frame.payload().len() < text.len());
+        assert_eq!(inflate_message(frame.payload()), text.as_bytes());
+    }
+
+    #[test]
+    fn sends_small_messages_as_they_are() {
+        let message = Message::binary(vec![1, 2, 3]);
+        assert_eq!(compress(&message), message);
+        assert_eq!(
+            compress(&Message::Ping(Default::default())),
+            Message::Ping(Default::default())
+        );
+    }
+
+    /// A frame as a browser sends it
+    fn client_frame(payload: &[u8], opcode: OpCode, is_final: bool, rsv1: bool) -> Vec<u8> {
+        let mut frame = Frame::message(payload.to_vec(), opcode, is_final);
+        frame.header_mut().rsv1 = rsv1;
+        frame.header_mut().mask = Some([0x12, 0x34, 0x56, 0x78]);
+        let mut out = Vec::new();
+        frame.format(&mut out).unwrap();
+        out
+    }
+
+    /// Compress like a browser, keeping the window from one message to the next
+    fn client_deflate(compress: &mut Compress, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + 64);
+        compress
+            .compress_vec(data, &mut out, FlushCompress::Sync)
+            .unwrap();
+        assert!(out.ends_with(&TAIL));
+        out.truncate(out.len() - TAIL.len());
+        out
+    }
+
+    #[tokio::test]
+    async fn inflates_client_messages() {
+        let first = r#"{"subscribe":"user"}"#.repeat(20);
+        let second = r#"{"subscribe":"tops"}"#.repeat(20);
+        let mut compress = Compress::new(Compression::default(), false);
+        let text = OpCode::Data(Data::Text);
+        let continuation = OpCode::Data(Data::Continue);
+
+        let mut wire = Vec::new();
+        // Fragmented, with a ping in between
+        let compressed = client_deflate(&mut compress, first.as_bytes());
+        let (start, end) = compressed.split_at(compressed.len() / 2);
+        wire.extend(client_frame(start, text, false, true));
+        let mut ping = Frame::ping(b"ping".to_vec());
+        ping.header_mut().mask = Some([1, 2, 3, 4]);
+        ping.format(&mut wire).unwrap();
+        wire.extend(client_frame(end, continuation, true, false));
+        // Referring back to the first message
+        let compressed = client_deflate(&mut compress, second.as_bytes());
+        wire.extend(client_frame(&compressed, text, true, true));
+        // Small messages may be sent uncompressed
+        wire.extend(client_frame(b"hi", text, true, false));
+
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        client.write_all(&wire).await.unwrap();
+        let mut server =
+            WebSocketStream::from_raw_socket(Inflate::new(server, true), Role::Server, None).await;
+        let mut next = async || server.next().await.unwrap().unwrap();
+        assert_eq!(next().await, Message::Ping(b"ping".to_vec().into()));
+        assert_eq!(next().await, Message::text(first));
+        assert_eq!(next().await, Message::text(second));
+        assert_eq!(next().await, Message::text("hi"));
+    }
+
+    #[tokio::test]
+    async fn passes_frames_through_without_extension() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let text = OpCode::Data(Data::Text);
+        client
+            .write_all(&client_frame(b"hello", text, true, false))
+            .await
+            .unwrap();
+        let mut server =
+            WebSocketStream::from_raw_socket(Inflate::new(server, false), Role::Server, None).await;
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::text("hello")
+        );
+    }
+}
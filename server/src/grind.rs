@@ -0,0 +1,301 @@
+//! Retries of one beatmap, for "attempt #37, best so far 97.2%" overlays.
+//!
+//! Recent scores are folded in as they're fetched, so the count keeps going past the recent score
+//! limit of the API. Playing another beatmap or mode, or a break of half an hour, starts over
+
+use rosu_v2::prelude::{GameMode, Score};
+use serde::{Deserialize, Serialize};
+use types::payload::{AttemptSummary, BeatmapSummary, GrindSummary, completion};
+
+/// Longest gap between two plays of the same grind, in seconds
+const BREAK: i64 = 30 * 60;
+/// Plays on each side of the trend
+const TREND_WINDOW: usize = 5;
+/// Plays kept for the history, older ones only count towards the totals
+const HISTORY: usize = 100;
+
+/// Plays of the current grind, persisted with the rest of the tracked data
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Grind {
+    /// Beatmap id and mode of the plays
+    key: Option<(u32, GameMode)>,
+    beatmap: Option<BeatmapSummary>,
+    /// The last `HISTORY` plays, oldest first
+    attempts: Vec<AttemptSummary>,
+    /// Plays which were dropped from `attempts`
+    #[serde(default)]
+    dropped: Dropped,
+}
+
+/// Running totals of the plays dropped from the history
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Dropped {
+    attempts: u32,
+    passes: u32,
+    /// Unix timestamp of the first play
+    started_at: Option<i64>,
+    best_accuracy: Option<f32>,
+    best_combo: u32,
+    best_pp: Option<f32>,
+    best_completion: Option<f32>,
+}
+
+impl Dropped {
+    fn add(&mut self, attempt: &AttemptSummary) {
+        self.attempts += 1;
+        self.passes += attempt.passed as u32;
+        self.started_at.get_or_insert(attempt.ended_at);
+        self.best_accuracy = max(
+            self.best_accuracy,
+            attempt.passed.then_some(attempt.accuracy),
+        );
+        self.best_combo = self.best_combo.max(attempt.max_combo);
+        self.best_pp = max(self.best_pp, attempt.pp);
+        self.best_completion = max(self.best_completion, attempt.completion);
+    }
+}
+
+impl Grind {
+    /// Add the plays which are newer than the last attempt. `recent` is newest first, as the API
+    /// sends it
+    pub fn record(&mut self, recent: &[Score]) {
+        for score in recent.iter().rev() {
+            let ended_at = score.ended_at.unix_timestamp();
+            let last = self.attempts.last().map(|attempt| attempt.ended_at);
+            if last.is_some_and(|last| ended_at <= last) {
+                continue;
+            }
+            let key = (score.map_id, score.mode);
+            if self.key != Some(key) || last.is_none_or(|last| ended_at - last > BREAK) {
+                self.key = Some(key);
+                self.beatmap = score
+                    .map
+                    .as_deref()
+                    .map(|map| BeatmapSummary::new(map, score.mapset.as_deref()));
+                self.attempts.clear();
+                self.dropped = Dropped::default();
+            }
+            if self.attempts.len() == HISTORY {
+                let oldest = self.attempts.remove(0);
+                self.dropped.add(&oldest);
+            }
+            self.attempts.push(AttemptSummary {
+                attempt: self.dropped.attempts + self.attempts.len() as u32 + 1,
+                score_id: score.id,
+                accuracy: score.accuracy,
+                max_combo: score.max_combo,
+                pp: score.pp,
+                grade: format!("{:?}", score.grade),
+                passed: score.passed,
+                completion: completion(score),
+                ended_at,
+            });
+        }
+    }
+
+    /// `None` until a play was recorded
+    pub fn summary(&self) -> Option<GrindSummary> {
+        let first = self.attempts.first()?;
+        let dropped = &self.dropped;
+        let attempts = dropped.attempts + self.attempts.len() as u32;
+        let best = |dropped: Option<f32>, value: fn(&AttemptSummary) -> Option<f32>| {
+            self.attempts
+                .iter()
+                .filter_map(value)
+                .chain(dropped)
+                .reduce(f32::max)
+        };
+        Some(GrindSummary {
+            beatmap: self.beatmap.clone(),
+            attempts,
+            retries: attempts - 1,
+            passes: dropped.passes + self.attempts.iter().filter(|a| a.passed).count() as u32,
+            started_at: dropped.started_at.unwrap_or(first.ended_at),
+            best_accuracy: best(dropped.best_accuracy, |a| a.passed.then_some(a.accuracy)),
+            best_combo: self
+                .attempts
+                .iter()
+                .map(|a| a.max_combo)
+                .fold(dropped.best_combo, u32::max),
+            best_pp: best(dropped.best_pp, |a| a.pp),
+            best_completion: best(dropped.best_completion, |a| a.completion),
+            trend: trend(&self.attempts),
+            history: self.attempts.clone(),
+        })
+    }
+}
+
+/// The larger value, `None` only if both are
+fn max(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    a.into_iter().chain(b).reduce(f32::max)
+}
+
+/// Mean accuracy of the last plays minus that of the plays before them
+fn trend(attempts: &[AttemptSummary]) -> Option<f32> {
+    let window = TREND_WINDOW.min(attempts.len() / 2);
+    if window == 0 {
+        return None;
+    }
+    let mean = |attempts: &[AttemptSummary]| {
+        attempts.iter().map(|a| a.accuracy).sum::<f32>() / attempts.len() as f32
+    };
+    let (rest, last) = attempts.split_at(attempts.len() - window);
+    Some(mean(last) - mean(&rest[rest.len() - window..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::fixtures;
+
+    /// A play of `map_id`, `minutes` after the first one
+    fn play(map_id: u32, minutes: u64, accuracy: f32, passed: bool) -> Score {
+        let mut score = fixtures::scores(1).remove(0);
+        score.id = minutes;
+        score.map_id = map_id;
+        score.ended_at += Duration::from_secs(minutes * 60);
+        score.accuracy = accuracy;
+        score.passed = passed;
+        score.pp = passed.then_some(accuracy * 5.0);
+        score.max_combo = accuracy as u32 * 10;
+        score
+    }
+
+    /// Record `plays`, given oldest first
+    fn record(grind: &mut Grind, plays: &[Score]) {
+        let recent: Vec<Score> = plays.iter().rev().cloned().collect();
+        grind.record(&recent);
+    }
+
+    #[test]
+    fn counts_retries_of_one_beatmap() {
+        let mut grind = Grind::default();
+        assert_eq!(grind.summary(), None);
+        let plays = [
+            play(1, 0, 90.0, false),
+            play(1, 2, 92.0, true),
+            play(1, 4, 91.0, true),
+        ];
+        record(&mut grind, &plays);
+        let summary = grind.summary().unwrap();
+        assert_eq!(summary.attempts, 3);
+        assert_eq!(summary.retries, 2);
+        assert_eq!(summary.passes, 2);
+        assert_eq!(summary.started_at, plays[0].ended_at.unix_timestamp());
+        let numbers: Vec<u32> = summary.history.iter().map(|a| a.attempt).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+    }
+
+    #[test]
+    fn starts_over_on_another_beatmap_or_mode() {
+        let mut grind = Grind::default();
+        record(
+            &mut grind,
+            &[play(1, 0, 90.0, true), play(2, 2, 95.0, true)],
+        );
+        let summary = grind.summary().unwrap();
+        assert_eq!(summary.attempts, 1);
+        assert_eq!(summary.best_accuracy, Some(95.0));
+
+        let mut taiko = play(2, 4, 97.0, true);
+        taiko.mode = GameMode::Taiko;
+        record(&mut grind, &[taiko]);
+        assert_eq!(grind.summary().unwrap().attempts, 1);
+        assert_eq!(grind.key, Some((2, GameMode::Taiko)));
+    }
+
+    #[test]
+    fn starts_over_after_a_break() {
+        let mut grind = Grind::default();
+        record(
+            &mut grind,
+            &[play(1, 0, 90.0, true), play(1, 30, 91.0, true)],
+        );
+        assert_eq!(grind.summary().unwrap().attempts, 2);
+        record(&mut grind, &[play(1, 61, 92.0, true)]);
+        assert_eq!(grind.summary().unwrap().attempts, 1);
+    }
+
+    #[test]
+    fn skips_plays_already_recorded() {
+        let mut grind = Grind::default();
+        let plays = [
+            play(1, 0, 90.0, true),
+            play(1, 2, 91.0, true),
+            play(1, 4, 92.0, true),
+        ];
+        record(&mut grind, &plays[..2]);
+        // The API sends the recent plays again on every fetch
+        record(&mut grind, &plays);
+        record(&mut grind, &plays);
+        // Older than the last attempt
+        record(&mut grind, &[play(1, 3, 99.0, true)]);
+        let summary = grind.summary().unwrap();
+        assert_eq!(summary.attempts, 3);
+        assert_eq!(summary.best_accuracy, Some(92.0));
+    }
+
+    #[test]
+    fn bests_only_count_passes() {
+        let mut grind = Grind::default();
+        record(&mut grind, &[play(1, 0, 99.0, false)]);
+        let summary = grind.summary().unwrap();
+        assert_eq!(summary.best_accuracy, None);
+        assert_eq!(summary.best_pp, None);
+
+        record(&mut grind, &[play(1, 2, 90.0, true)]);
+        let summary = grind.summary().unwrap();
+        assert_eq!(summary.best_accuracy, Some(90.0));
+        assert_eq!(summary.best_pp, Some(450.0));
+        // Combo and completion count every play
+        assert_eq!(summary.best_combo, 990);
+        assert_eq!(summary.best_completion, Some(100.0));
+    }
+
+    #[test]
+    fn keeps_totals_past_the_history() {
+        let mut grind = Grind::default();
+        let mut plays = vec![play(1, 0, 99.0, true), play(1, 1, 50.0, false)];
+        plays.extend((2..105).map(|minutes| play(1, minutes, 80.0, true)));
+        record(&mut grind, &plays);
+        let summary = grind.summary().unwrap();
+        assert_eq!(summary.attempts, 105);
+        assert_eq!(summary.retries, 104);
+        assert_eq!(summary.passes, 104);
+        assert_eq!(summary.started_at, plays[0].ended_at.unix_timestamp());
+        assert_eq!(summary.best_accuracy, Some(99.0));
+        assert_eq!(summary.best_pp, Some(495.0));
+        assert_eq!(summary.history.len(), HISTORY);
+        assert_eq!(summary.history[0].attempt, 6);
+
+        record(&mut grind, &[play(1, 105, 85.0, true)]);
+        let summary = grind.summary().unwrap();
+        assert_eq!(summary.attempts, 106);
+        assert_eq!(summary.history.last().unwrap().attempt, 106);
+        assert_eq!(summary.history.len(), HISTORY);
+    }
+
+    #[test]
+    fn trend_compares_the_last_plays() {
+        let attempts = |accuracies: &[f32]| {
+            let mut grind = Grind::default();
+            let plays: Vec<Score> = accuracies
+                .iter()
+                .enumerate()
+                .map(|(i, accuracy)| play(1, i as u64, *accuracy, true))
+                .collect();
+            record(&mut grind, &plays);
+            grind.attempts
+        };
+        assert_eq!(trend(&attempts(&[90.0])), None);
+        assert_eq!(trend(&attempts(&[90.0, 95.0])), Some(5.0));
+        assert_eq!(trend(&attempts(&[90.0, 94.0, 95.0])), Some(1.0));
+        // Only the five plays on each side count
+        let mut accuracies = vec![10.0; 3];
+        accuracies.extend([90.0; 5]);
+        accuracies.extend([92.0; 5]);
+        assert_eq!(trend(&attempts(&accuracies)), Some(2.0));
+    }
+}
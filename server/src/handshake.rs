@@ -0,0 +1,513 @@
+use crate::auth::reject;
+use http_body_util::Full;
+use hyper::{
+    Method, Request, Response, StatusCode, Version,
+    body::Bytes,
+    header::{
+        CONNECTION, HeaderMap, HeaderName, HeaderValue, SEC_WEBSOCKET_EXTENSIONS,
+        SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
+    },
+};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+
+/// Subprotocols understood by the server, see `Format::from_protocol`
+pub const SUPPORTED_PROTOCOLS: &[&str] = &["rosu-tracker.json", "rosu-tracker.msgpack"];
+
+/// The only websocket version defined by RFC 6455
+const WEBSOCKET_VERSION: &str = "13";
+
+/// A validated websocket upgrade request
+#[derive(Debug)]
+pub(crate) struct Handshake {
+    /// Value of `Sec-WebSocket-Accept`
+    pub accept: HeaderValue,
+    /// Subprotocol picked from the ones offered by the client
+    pub protocol: Option<HeaderValue>,
+    /// Whether messages are sent with permessage-deflate, see `deflate::RESPONSE`
+    pub deflate: bool,
+}
+
+/// Check that `req` is a well-formed RFC 6455 opening handshake
+pub(crate) fn validate<B>(req: &Request<B>) -> Result<Handshake, Response<Full<Bytes>>> {
+    if req.method() != Method::GET {
+        return Err(reject(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Websocket handshakes have to use GET",
+        ));
+    }
+    if req.version() < Version::HTTP_11 {
+        return Err(reject(
+            StatusCode::BAD_REQUEST,
+            "Websockets require HTTP/1.1",
+        ));
+    }
+    let headers = req.headers();
+    if !has_token(headers, UPGRADE, "websocket") {
+        let mut res = reject(
+            StatusCode::UPGRADE_REQUIRED,
+            "This endpoint only serves websockets",
+        );
+        res.headers_mut()
+            .insert(UPGRADE, HeaderValue::from_static("websocket"));
+        res.headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+        return Err(res);
+    }
+    if !has_token(headers, CONNECTION, "upgrade") {
+        return Err(reject(
+            StatusCode::BAD_REQUEST,
+            "Connection header has to contain Upgrade",
+        ));
+    }
+    let version = headers
+        .get(SEC_WEBSOCKET_VERSION)
+        .and_then(|v| v.to_str().ok());
+    if version.map(str::trim) != Some(WEBSOCKET_VERSION) {
+        let mut res = reject(
+            StatusCode::UPGRADE_REQUIRED,
+            "Unsupported Sec-WebSocket-Version",
+        );
+        res.headers_mut().insert(
+            SEC_WEBSOCKET_VERSION,
+            HeaderValue::from_static(WEBSOCKET_VERSION),
+        );
+        return Err(res);
+    }
+    let Some(key) = headers
+        .get(SEC_WEBSOCKET_KEY)
+        .filter(|key| is_valid_key(key))
+    else {
+        return Err(reject(
+            StatusCode::BAD_REQUEST,
+            "Missing or malformed Sec-WebSocket-Key",
+        ));
+    };
+    let accept =
+        HeaderValue::from_str(&derive_accept_key(key.as_bytes())).expect("accept keys are base64");
+
+    Ok(Handshake {
+        accept,
+        protocol: negotiate_protocol(headers),
+        deflate: negotiate_deflate(headers),
+    })
+}
+
+/// Pick the supported subprotocol the client prefers, i.e. offered first
+fn negotiate_protocol(headers: &HeaderMap) -> Option<HeaderValue> {
+    tokens(headers, SEC_WEBSOCKET_PROTOCOL)
+        .find_map(|offered| {
+            SUPPORTED_PROTOCOLS
+                .iter()
+                .find(|supported| **supported == offered)
+        })
+        .map(|protocol| HeaderValue::from_static(protocol))
+}
+
+/// Accept the first permessage-deflate offer whose parameters can be honored
+fn negotiate_deflate(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(SEC_WEBSOCKET_EXTENSIONS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|offer| {
+            let mut params = offer.split(';').map(str::trim);
+            params
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case("permessage-deflate"))
+                && supports_deflate_params(params)
+        })
+}
+
+/// Parameters of a permessage-deflate offer, which are all declined if one isn't understood
+fn supports_deflate_params<'a>(params: impl Iterator<Item = &'a str>) -> bool {
+    let mut seen = Vec::new();
+    for param in params {
+        let (name, value) = match param.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (param, None),
+        };
+        if seen.contains(&name) {
+            return false;
+        }
+        seen.push(name);
+        let supported = match (name, value) {
+            ("server_no_context_takeover" | "client_no_context_takeover", None) => true,
+            // Messages from the client are inflated with the full window, which fits any size
+            ("client_max_window_bits", None) => true,
+            ("client_max_window_bits", Some(bits)) => window_bits(bits).is_some(),
+            // Messages are compressed with the full window
+            ("server_max_window_bits", Some(bits)) => window_bits(bits) == Some(15),
+            _ => false,
+        };
+        if !supported {
+            return false;
+        }
+    }
+    true
+}
+
+fn window_bits(bits: &str) -> Option<u8> {
+    bits.parse().ok().filter(|bits| (8..=15).contains(bits))
+}
+
+/// Comma separated values of every `name` header, with parameters stripped
+fn tokens(headers: &HeaderMap, name: HeaderName) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|token| token.split(';').next())
+        .map(str::trim)
+}
+
+fn has_token(headers: &HeaderMap, name: HeaderName, token: &str) -> bool {
+    tokens(headers, name).any(|t| t.eq_ignore_ascii_case(token))
+}
+
+/// The key has to be 16 random bytes in base64, i.e. 22 characters and two `=` of padding
+fn is_valid_key(key: &HeaderValue) -> bool {
+    let key = key.as_bytes();
+    key.len() == 24
+        && key.ends_with(b"==")
+        && key[..22]
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || *b == b'+' || *b == b'/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Example key of RFC 6455
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    fn request() -> hyper::http::request::Builder {
+        Request::get("/user")
+            .header(UPGRADE, "websocket")
+            .header(CONNECTION, "keep-alive, Upgrade")
+            .header(SEC_WEBSOCKET_VERSION, WEBSOCKET_VERSION)
+            .header(SEC_WEBSOCKET_KEY, KEY)
+    }
+
+    fn request_without(name: HeaderName) -> Request<()> {
+        let mut req = request().body(()).unwrap();
+        req.headers_mut().remove(name);
+        req
+    }
+
+    fn status(req: &Request<()>) -> StatusCode {
+        validate(req)
+            .expect_err("the handshake should be rejected")
+            .status()
+    }
+
+    #[test]
+    fn derives_the_accept_key_of_the_rfc() {
+        let handshake = validate(&request().body(()).unwrap()).unwrap();
+        assert_eq!(handshake.accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(handshake.protocol, None);
+        assert!(!handshake.deflate);
+    }
+
+    #[test]
+    fn rejects_missing_key() {
+        assert_eq!(
+            status(&request_without(SEC_WEBSOCKET_KEY)),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        for key in [
+            "",
+            "dGhlIHNhbXBsZSBub25jZQ",
+            "dGhlIHNhbXBsZSBub25jZQ=",
+            "dGhlIHNhbXBsZSBub25jZQ===",
+            "dGhlIHNhbXBsZSBub25j!Q==",
+        ] {
+            let mut req = request().body(()).unwrap();
+            req.headers_mut()
+                .insert(SEC_WEBSOCKET_KEY, HeaderValue::from_static(key));
+            assert_eq!(status(&req), StatusCode::BAD_REQUEST, "{key:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        for version in [None, Some("8"), Some("14")] {
+            let mut req = request().body(()).unwrap();
+            match version {
+                Some(version) => req
+                    .headers_mut()
+                    .insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static(version)),
+                None => req.headers_mut().remove(SEC_WEBSOCKET_VERSION),
+            };
+            let res = validate(&req).expect_err("the version should be rejected");
+            assert_eq!(res.status(), StatusCode::UPGRADE_REQUIRED);
+            assert_eq!(res.headers()[SEC_WEBSOCKET_VERSION], WEBSOCKET_VERSION);
+        }
+    }
+
+    #[test]
+    fn rejects_missing_upgrade() {
+        let res =
+            validate(&request_without(UPGRADE)).expect_err("plain requests should be rejected");
+        assert_eq!(res.status(), StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(res.headers()[UPGRADE], "websocket");
+    }
+
+    #[test]
+    fn rejects_missing_connection_upgrade() {
+        assert_eq!(
+            status(&request_without(CONNECTION)),
+            StatusCode::BAD_REQUEST
+        );
+        let mut req = request().body(()).unwrap();
+        req.headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+        assert_eq!(status(&req), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn rejects_other_methods_and_versions() {
+        let mut req = request().body(()).unwrap();
+        *req.method_mut() = Method::POST;
+        assert_eq!(status(&req), StatusCode::METHOD_NOT_ALLOWED);
+        let mut req = request().body(()).unwrap();
+        *req.version_mut() = Version::HTTP_10;
+        assert_eq!(status(&req), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn picks_the_first_supported_protocol() {
+        let req = request()
+            .header(SEC_WEBSOCKET_PROTOCOL, "token.abc, rosu-tracker.msgpack")
+            .header(SEC_WEBSOCKET_PROTOCOL, "rosu-tracker.json")
+            .body(())
+            .unwrap();
+        let handshake = validate(&req).unwrap();
+        assert_eq!(handshake.protocol.unwrap(), "rosu-tracker.msgpack");
+    }
+
+    #[test]
+    fn negotiates_deflate() {
+        let deflate = |offer: &'static str| {
+            let req = request()
+                .header(SEC_WEBSOCKET_EXTENSIONS, offer)
+                .body(())
+                .unwrap();
+            validate(&req).unwrap().deflate
+        };
+        // What browsers offer
+        assert!(deflate("permessage-deflate; client_max_window_bits"));
+        assert!(deflate(
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        ));
+        assert!(deflate("permessage-deflate; server_max_window_bits=15"));
+        assert!(deflate("x-webkit-deflate-frame, permessage-deflate"));
+        // The second offer can be honored
+        assert!(deflate(
+            "permessage-deflate; server_max_window_bits=10, permessage-deflate"
+        ));
+        assert!(!deflate("permessage-deflate; server_max_window_bits=10"));
+        assert!(!deflate("permessage-deflate; client_max_window_bits=16"));
+        assert!(!deflate("permessage-deflate; unknown_param"));
+        assert!(!deflate(
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover"
+        ));
+        assert!(!deflate("x-webkit-deflate-frame"));
+    }
+
+    /// Handshakes through `server_thread`, with a real client
+    mod served {
+        use std::{io::Cursor, sync::Arc, time::Duration};
+
+        use constants::{HOST, USER_ENDPOINT};
+        use flate2::{Decompress, FlushDecompress};
+        use futures_util::StreamExt;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::{TcpListener, TcpStream},
+            sync::{Mutex, mpsc, watch},
+        };
+        use tokio_tungstenite::{
+            connect_async,
+            tungstenite::{Message, client::IntoClientRequest, protocol::frame::FrameHeader},
+        };
+        use types::Config;
+
+        use super::*;
+        use crate::{
+            control, fixtures,
+            oauth::OAuth,
+            overlay::Overlays,
+            server_thread,
+            structs::{ServerContext, Snapshot, TrackedData},
+        };
+
+        /// A server for a tracked user on a free port, stopped when dropped
+        struct TestServer {
+            port: u16,
+            shutdown: watch::Sender<bool>,
+            _snapshots: watch::Sender<Arc<Snapshot>>,
+            _config: watch::Sender<Config>,
+            _overlays: watch::Sender<Arc<Overlays>>,
+            _alive: mpsc::Sender<()>,
+        }
+
+        impl Drop for TestServer {
+            fn drop(&mut self) {
+                let _ = self.shutdown.send(true);
+            }
+        }
+
+        async fn serve() -> TestServer {
+            // Found by binding port 0, then bound again by the server
+            let port = TcpListener::bind((HOST, 0))
+                .await
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let data =
+                TrackedData::new_with(fixtures::user(), fixtures::scores(10), None, None, None);
+            let (snapshots_tx, snapshots) = watch::channel(Arc::new(Snapshot::new(data)));
+            let (config_tx, config) = watch::channel(Config {
+                port: Some(port),
+                ..Config::default()
+            });
+            let (overlays_tx, overlays) = watch::channel(Arc::default());
+            let (shutdown_tx, shutdown) = watch::channel(false);
+            let (alive, _) = mpsc::channel(1);
+            let (logins, _) = watch::channel(None);
+            let ctx = ServerContext {
+                values: Arc::new(Mutex::new(TrackedData::new())),
+                snapshots,
+                overlays,
+                config,
+                control: control::channel().0,
+                oauth: OAuth::new(logins),
+                shutdown,
+                alive: alive.downgrade(),
+            };
+            tokio::spawn(server_thread(ctx));
+            while TcpStream::connect((HOST, port)).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            TestServer {
+                port,
+                shutdown: shutdown_tx,
+                _snapshots: snapshots_tx,
+                _config: config_tx,
+                _overlays: overlays_tx,
+                _alive: alive,
+            }
+        }
+
+        /// Send an upgrade request to `/` with `headers` added, returns the response head
+        async fn raw_request(port: u16, headers: &str) -> (String, TcpStream) {
+            let mut stream = TcpStream::connect((HOST, port)).await.unwrap();
+            let request = format!(
+                "GET {USER_ENDPOINT} HTTP/1.1\r\nHost: {HOST}:{port}\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\n{headers}\r\n"
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            let head = String::from_utf8(head).unwrap().to_ascii_lowercase();
+            (head, stream)
+        }
+
+        #[tokio::test]
+        async fn upgrades_valid_handshakes() {
+            let server = serve().await;
+            let url = format!("ws://{HOST}:{}{USER_ENDPOINT}", server.port);
+            let (mut socket, response) = connect_async(url).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+            assert!(response.headers().get(SEC_WEBSOCKET_EXTENSIONS).is_none());
+            let Message::Text(text) = socket.next().await.unwrap().unwrap() else {
+                panic!("the user should be sent as text");
+            };
+            assert!(text.contains("fixture"));
+        }
+
+        #[tokio::test]
+        async fn negotiates_subprotocols() {
+            let server = serve().await;
+            let mut request = format!("ws://{HOST}:{}{USER_ENDPOINT}", server.port)
+                .into_client_request()
+                .unwrap();
+            request.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static("chat, rosu-tracker.msgpack, rosu-tracker.json"),
+            );
+            let (mut socket, response) = connect_async(request).await.unwrap();
+            assert_eq!(
+                response.headers()[SEC_WEBSOCKET_PROTOCOL],
+                "rosu-tracker.msgpack"
+            );
+            let message = socket.next().await.unwrap().unwrap();
+            assert!(matches!(message, Message::Binary(_)));
+        }
+
+        #[tokio::test]
+        async fn compresses_for_deflate_offers() {
+            let server = serve().await;
+            let (head, mut stream) = raw_request(
+                server.port,
+                &format!(
+                    "Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {KEY}\r\n\
+                     Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n"
+                ),
+            )
+            .await;
+            assert!(head.starts_with("http/1.1 101"));
+            assert!(head.contains(
+                "sec-websocket-extensions: permessage-deflate; server_no_context_takeover"
+            ));
+
+            let mut read = Vec::new();
+            let (header, mut payload) = loop {
+                let mut cursor = Cursor::new(&read);
+                if let Some((header, len)) = FrameHeader::parse(&mut cursor).unwrap() {
+                    let start = cursor.position() as usize;
+                    if let Some(payload) = read.get(start..start + len as usize) {
+                        break (header, payload.to_vec());
+                    }
+                }
+                stream.read_buf(&mut read).await.unwrap();
+            };
+            assert!(header.rsv1);
+            payload.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+            let mut text = Vec::with_capacity(1 << 20);
+            Decompress::new(false)
+                .decompress_vec(&payload, &mut text, FlushDecompress::Sync)
+                .unwrap();
+            assert!(String::from_utf8(text).unwrap().contains("fixture"));
+        }
+
+        #[tokio::test]
+        async fn rejects_missing_keys() {
+            let server = serve().await;
+            let (head, _) = raw_request(server.port, "Sec-WebSocket-Version: 13\r\n").await;
+            assert!(head.starts_with("http/1.1 400"));
+        }
+
+        #[tokio::test]
+        async fn rejects_other_versions() {
+            let server = serve().await;
+            let (head, _) = raw_request(
+                server.port,
+                &format!("Sec-WebSocket-Version: 8\r\nSec-WebSocket-Key: {KEY}\r\n"),
+            )
+            .await;
+            assert!(head.starts_with("http/1.1 426"));
+            assert!(head.contains("sec-websocket-version: 13"));
+        }
+    }
+}
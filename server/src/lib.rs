@@ -1,191 +1,656 @@
-use futures_lite::future::*;
 use futures_util::{SinkExt, StreamExt};
-use http_body_util::Full;
+use http_body_util::{BodyExt, Full};
 use hyper::{
+    Request, Response, StatusCode,
     body::Bytes,
-    header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE},
+    header::{
+        ACCESS_CONTROL_ALLOW_ORIGIN, CONNECTION, CONTENT_TYPE, HeaderValue, SEC_WEBSOCKET_ACCEPT,
+        SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+    },
     server::conn::http1,
     service::service_fn,
-    Request, Response, StatusCode,
 };
 use hyper_util::rt::tokio::TokioIo;
-use rosu_v2::Osu;
-use std::{sync::Arc, time::Duration};
-use tokio::time::sleep;
+use oauth::Login;
+use rosu_v2::{Osu, prelude::Token};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{Notify, watch},
+    time::{MissedTickBehavior, sleep, timeout},
+};
 use tokio_tungstenite::{
-    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
     WebSocketStream,
+    tungstenite::{
+        Message,
+        protocol::{CloseFrame, Role, frame::coding::CloseCode},
+    },
 };
-use tracing::{debug, error};
-pub mod structs;
+use tracing::{debug, error, info, warn};
+mod auth;
+pub mod compare;
+pub mod control;
+mod deflate;
+#[cfg(test)]
+#[path = "../benches/fixtures/mod.rs"]
+mod fixtures;
+pub mod grind;
+pub mod handshake;
+pub mod oauth;
+pub mod overlay;
 pub mod setup;
+pub mod sinks;
+mod sse;
+pub mod state;
+pub mod structs;
+pub mod template;
 
-use constants::{BASE_IP, FIRSTS_ENDPOINT, RECENT_ENDPOINT, TOPS_ENDPOINT, USER_ENDPOINT};
-use types::Api;
-use color_eyre::{eyre::Error, Result};
-use tokio_tungstenite::tungstenite::Utf8Bytes;
+use color_eyre::{
+    Result,
+    eyre::{Error, eyre},
+};
+use constants::{
+    COMPARE_ENDPOINT, EVENTS_ENDPOINT, FIRSTS_ENDPOINT, GRIND_ENDPOINT, HOST, MOST_PLAYED_ENDPOINT,
+    OAUTH_CALLBACK_ENDPOINT, OAUTH_LOGIN_ENDPOINT, OVERLAY_ENDPOINT, PINNED_ENDPOINT,
+    RECENT_ENDPOINT, SCHEMA_ENDPOINT, TOPS_ENDPOINT, USER_ENDPOINT,
+};
+use handshake::Handshake;
+use state::StateFile;
 use structs::*;
+use tokio_tungstenite::tungstenite::Utf8Bytes;
+use types::{Api, Config, Mode, payload::SchemaDocument};
 
-#[tracing::instrument(name = "handle_clients", skip_all)]
-pub async fn handle_clients(clients: Clients, values: Arm<TrackedData>) {
-    let user_lock = values.lock().await;
-    if user_lock.user_extended.is_none() {
-        return;
-    }
-    let (ser_profile, ser_tops, ser_firsts, ser_recent) = {
-        let data = &*user_lock;
-        (
-            serde_json::to_string(&data.user_extended).unwrap(),
-            serde_json::to_string(&data.user_scores).unwrap(),
-            serde_json::to_string(&data.user_firsts).unwrap(),
-            serde_json::to_string(&data.user_recent).unwrap(),
-        )
-    };
-    drop(user_lock);
-    debug!("Constructed serialized data");
-    let mut clients = clients.lock().await;
-    clients.retain_mut(|socket| {
-        block_on(async {
-            let next = socket.client.next();
-
-            let message = futures_lite::future::poll_once(next);
-
-            let msg = match message.await {
-                Some(Some(Ok(v))) => Some(v),
-                Some(Some(Err(_))) => return false,
-                Some(None) | None => None,
-            };
+/// How often the tracked data is checked for changes
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+/// How often pinned scores and most played beatmaps are fetched, they rarely change
+const SLOW_FETCH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often the players from `compare_users` are fetched
+const COMPARE_FETCH_INTERVAL: Duration = Duration::from_secs(60);
+/// Most played beatmaps fetched, the API returns at most 100
+const MOST_PLAYED_LIMIT: usize = 50;
+/// Clients that don't accept a message within this time are disconnected
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often clients are pinged. Clients that didn't answer the previous ping are disconnected
+const PING_INTERVAL: Duration = Duration::from_secs(30);
 
-            if let Some(Message::Close(_)) = msg {
+/// Serialize the tracked data into a new snapshot whenever it changes
+#[tracing::instrument(name = "publish_thread", skip_all)]
+pub async fn publish_thread(
+    values: Arm<TrackedData>,
+    snapshots: watch::Sender<Arc<Snapshot>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(PUBLISH_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+        // Serialized by the clients, without holding up the fetch thread
+        let data = values.lock().await.clone();
+        let mut snapshot = Snapshot::new(data);
+        let published = snapshots.send_if_modified(|current| {
+            if !snapshot.succeed(current) {
                 return false;
             }
-
-            let res = match socket.kind {
-                WsKind::User => socket.client.send(Message::Text(Utf8Bytes::from(ser_profile.clone()))).await,
-                WsKind::Tops => socket.client.send(Message::Text(Utf8Bytes::from(ser_tops.clone()))).await,
-                WsKind::Firsts => socket.client.send(Message::Text(Utf8Bytes::from(ser_firsts.clone()))).await,
-                WsKind::Recent => socket.client.send(Message::Text(Utf8Bytes::from(ser_recent.clone()))).await,
-            };
-            debug!("Sent data to {:?}", socket);
-            // Close the connection on error
-            // Panics are bad, leaving it as it is even worse
-            if let Err(e) = res {
-                println!("{:?}", e);
-
-                let _ = socket.client.send(Message::Close(None)).await;
-                return false;
-            };
-
+            *current = Arc::new(snapshot);
             true
-        })
-    });
-    drop(clients);
-    tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+        if published {
+            debug!("Published new snapshot");
+        }
+    }
 }
-#[tracing::instrument(name = "server_thread")]
-pub async fn server_thread(ctx_clients: Clients, values: Arm<TrackedData>) {
-    let tcp = tokio::net::TcpListener::bind(BASE_IP).await.unwrap();
+#[tracing::instrument(name = "server_thread", skip_all)]
+pub async fn server_thread(ctx: ServerContext) {
+    let mut shutdown = ctx.shutdown.clone();
+    let mut config = ctx.config.clone();
+    let mut port = config.borrow_and_update().port();
+    let mut tcp = bind(port).await;
     loop {
-        let (stream, _) = tcp.accept().await.unwrap();
+        let accept = async {
+            match &tcp {
+                Some(tcp) => tcp.accept().await,
+                // Wait for a port that works
+                None => std::future::pending().await,
+            }
+        };
+        let (stream, _) = tokio::select! {
+            accepted = accept => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept connection: {e}");
+                    continue;
+                }
+            },
+            _ = config.changed() => {
+                let new_port = config.borrow_and_update().port();
+                if new_port != port {
+                    // Connected clients stay on the old listener until they disconnect
+                    port = new_port;
+                    tcp = None;
+                    tcp = bind(port).await;
+                }
+                continue;
+            }
+            _ = shutdown.changed() => {
+                debug!("Stopped accepting connections");
+                return;
+            }
+        };
 
         let io = TokioIo::new(stream);
 
-        let ctx_clients = ctx_clients.clone();
-        let _ctx_values = values.clone();
-        let service = service_fn(move |req| {
-            let ctx_clients = ctx_clients.clone();
-            // let ctx_values = ctx_values.clone();
-            // serve(ctx_clients, ctx_values, req)
-            serve(ctx_clients, req)
-        });
-        tokio::spawn(async {
-            if let Err(err) = http1::Builder::new()
+        let ctx = ctx.clone();
+        let mut shutdown = ctx.shutdown.clone();
+        let service = service_fn(move |req| serve(ctx.clone(), req));
+        tokio::spawn(async move {
+            let conn = http1::Builder::new()
                 .serve_connection(io, service)
-                .with_upgrades()
-                .await
-            {
+                .with_upgrades();
+            tokio::pin!(conn);
+            let served = tokio::select! {
+                served = conn.as_mut() => served,
+                _ = shutdown.changed() => {
+                    // Idle keep-alive connections close right away, busy ones after the response
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(err) = served {
                 println!("Error serving connection: {:?}", err);
             }
         });
     }
 }
+async fn bind(port: u16) -> Option<TcpListener> {
+    match TcpListener::bind((HOST, port)).await {
+        Ok(tcp) => {
+            info!("Listening on {HOST}:{port}");
+            Some(tcp)
+        }
+        Err(e) => {
+            error!("Failed to listen on {HOST}:{port}: {e}");
+            None
+        }
+    }
+}
 #[tracing::instrument(name = "fetch_thread", skip_all)]
-pub async fn fetch_thread(osu: Arc<Osu>, tracked_data: Arm<TrackedData>, api_conf: Api) {
+pub async fn fetch_thread(
+    tracked_data: Arm<TrackedData>,
+    mut config: watch::Receiver<Config>,
+    mut logins: watch::Receiver<Option<Login>>,
+    state_file: StateFile,
+    refresh: Arc<Notify>,
+    clients: watch::Sender<Option<Arc<Osu>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut api_conf = config.borrow_and_update().api.clone();
+    let mut login = logins.borrow_and_update().clone();
+    let mut osu: Option<Arc<Osu>> = None;
+    let mut force = false;
+    let mut slow_fetched: Option<Instant> = None;
     loop {
-        let fetched_recent = osu.user_scores(&api_conf.username).recent().limit(20);
-        let fetched_user = osu.user(&api_conf.username).await;
-        match &fetched_user {
-            Ok(u) => debug!("Fetched: {}", u.username),
-            Err(e) => error!("Error: {e}"),
+        // Pick up a reloaded config
+        let (new_conf, options) = {
+            let config = config.borrow_and_update();
+            (config.api.clone(), FetchOptions::new(&config))
         };
-        let fetched_user = fetched_user.unwrap();
-
-        let mut tracked_data = tracked_data.lock().await;
-        if let Some(ref tracked_data_user) = tracked_data.user_extended {
-            if tracked_data_user.statistics != fetched_user.statistics {
-                debug!("User data changed, fetching new data");
-                let fetched_tops = osu.user_scores(&api_conf.username).limit(100);
-                let fetched_firsts = osu.user_scores(&api_conf.username).firsts();
-                tracked_data.user_scores = fetched_tops
-                    .await
-                    .inspect_err(|e| tracing::error!("{e}"))
-                    .ok();
-                tracked_data.user_firsts = fetched_firsts
+        if new_conf != api_conf {
+            if new_conf.id != api_conf.id || new_conf.secret != api_conf.secret {
+                debug!("Credentials changed, recreating osu client");
+                osu = None;
+            }
+            if new_conf.id != api_conf.id {
+                // Logins belong to the client they were made with
+                login = Login::load(&new_conf.id)
+                    .inspect_err(|e| error!("Failed to load the login: {e}"))
+                    .ok()
+                    .flatten();
+            }
+            if !new_conf.username.eq_ignore_ascii_case(&api_conf.username)
+                || new_conf.mode != api_conf.mode
+            {
+                info!("Now tracking {}", new_conf.username);
+                let restored = state_file
+                    .load(&new_conf)
                     .await
-                    .inspect_err(|e| tracing::error!("{e}"))
-                    .ok();
+                    .inspect_err(|e| error!("Failed to load saved state: {e}"))
+                    .ok()
+                    .flatten();
+                if restored.is_some() {
+                    info!("Restored the session of {}", new_conf.username);
+                }
+                let previous = std::mem::replace(
+                    &mut *tracked_data.lock().await,
+                    restored.unwrap_or_else(TrackedData::new),
+                );
+                if let Err(e) = state_file.save(&api_conf, &previous).await {
+                    error!("Failed to save state: {e}");
+                }
+                slow_fetched = None;
+            }
+            api_conf = new_conf;
+        }
+        if let Some(stale) = login.as_ref().filter(|login| login.needs_refresh()) {
+            let refreshed = refresh_login(stale, &api_conf).await;
+            if refreshed.as_ref() != Some(stale) {
+                login = refreshed;
+                osu = None;
+            }
+        }
+        // Share the client with the compare thread
+        clients.send_if_modified(|shared| {
+            let changed = shared.as_ref().map(Arc::as_ptr) != osu.as_ref().map(Arc::as_ptr);
+            if changed {
+                *shared = osu.clone();
+            }
+            changed
+        });
+        let delay = match &osu {
+            // Nobody is tracked, wait until someone is
+            _ if api_conf.username.is_empty() => Duration::MAX,
+            Some(osu) => {
+                let delay =
+                    fetch_once(osu, &tracked_data, &api_conf, &state_file, force, &options).await;
+                let slow_due =
+                    force || slow_fetched.is_none_or(|at| at.elapsed() >= SLOW_FETCH_INTERVAL);
+                if slow_due && fetch_slow(osu, &tracked_data, &api_conf).await {
+                    slow_fetched = Some(Instant::now());
+                }
+                force = false;
+                delay
+            }
+            None => match new_osu(&api_conf, login.as_ref()).await {
+                Ok(client) => {
+                    osu = Some(Arc::new(client));
+                    continue;
+                }
+                Err(e) => {
+                    error!("{e}");
+                    Duration::from_secs(30)
+                }
+            },
+        };
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = config.changed() => {}
+            Ok(()) = logins.changed() => {
+                debug!("Logged in, recreating osu client");
+                login = logins.borrow_and_update().clone();
+                osu = None;
+            }
+            _ = refresh.notified() => {
+                debug!("Refresh requested");
+                force = true;
+            }
+            _ = shutdown.changed() => {
+                debug!("Stopped fetching");
+                return;
+            }
+        }
+    }
+}
+
+/// Fetch the players from `compare_users` on their own schedule, so fetching many players doesn't
+/// hold up polling the tracked user. Refetches right away when the tracked user or the players
+/// change, using the client of the fetch thread
+#[tracing::instrument(name = "compare_thread", skip_all)]
+pub async fn compare_thread(
+    tracked_data: Arm<TrackedData>,
+    mut config: watch::Receiver<Config>,
+    mut clients: watch::Receiver<Option<Arc<Osu>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    // When the players were last fetched, and for which tracked user, mode and players
+    let mut fetched: Option<(Instant, (Option<u32>, Option<Mode>, Vec<u32>))> = None;
+    loop {
+        let (api_conf, user_ids, interval) = {
+            let config = config.borrow_and_update();
+            let interval = Duration::from_secs(config.poll_interval());
+            (config.api.clone(), config.compare_users.clone(), interval)
+        };
+        let osu = clients.borrow_and_update().clone();
+        if let Some(osu) = osu {
+            let (tracked, missing) = {
+                let data = tracked_data.lock().await;
+                let tracked = data.user_extended.as_ref().map(|user| user.user_id);
+                // Cleared when the tracked user is reset
+                (tracked, data.compared.is_empty() && !user_ids.is_empty())
+            };
+            let key = (tracked, api_conf.mode, user_ids);
+            let due = missing
+                || fetched.as_ref().is_none_or(|(at, fetched_for)| {
+                    *fetched_for != key || at.elapsed() >= COMPARE_FETCH_INTERVAL
+                });
+            if due {
+                let done = tokio::select! {
+                    done = compare::fetch(&osu, &tracked_data, &api_conf, &key.2) => done,
+                    _ = shutdown.changed() => return,
+                };
+                if done {
+                    fetched = Some((Instant::now(), key));
+                }
+            }
+        }
+        tokio::select! {
+            _ = sleep(interval) => {}
+            _ = config.changed() => {}
+            Ok(()) = clients.changed() => {}
+            _ = shutdown.changed() => {
+                debug!("Stopped fetching compared users");
+                return;
             }
-            tracked_data.user_extended = Some(fetched_user);
-            tracked_data.user_recent = Some(fetched_recent.await.unwrap_or_default());
-            drop(tracked_data);
-            let _ = sleep(Duration::from_secs(5)).await;
-        } else {
-            tracing::debug!("Tracked user has no data");
-            let _ = sleep(Duration::from_secs(1)).await;
         }
     }
 }
+
+/// Client acting as the logged in user, or with client credentials if nobody logged in
+async fn new_osu(api_conf: &Api, login: Option<&Login>) -> Result<Osu> {
+    let client_id = api_conf
+        .id
+        .parse()
+        .map_err(|e| eyre!("Invalid client id {}: {e}", api_conf.id))?;
+    let builder = Osu::builder()
+        .client_id(client_id)
+        .client_secret(&api_conf.secret);
+    let builder = match login {
+        // Refreshed by the fetch thread, which has to store the rotated refresh token
+        Some(login) => {
+            let expires_in = login.expires_at.saturating_sub(oauth::now()) as i64;
+            builder.with_token(Token::new(&login.access_token, None), Some(expires_in))
+        }
+        None => builder,
+    };
+    builder
+        .build()
+        .await
+        .map_err(|e| eyre!("Failed to initialise osu client: {e}"))
+}
+
+/// Refresh and store a login. Falls back to client credentials if that fails and the old
+/// token expired
+async fn refresh_login(login: &Login, api_conf: &Api) -> Option<Login> {
+    match login.refresh(api_conf).await {
+        Ok(refreshed) => {
+            debug!("Refreshed the login");
+            if let Err(e) = refreshed.store(&api_conf.id) {
+                error!("Failed to store the refreshed login: {e}");
+            }
+            Some(refreshed)
+        }
+        Err(e) if login.expired() => {
+            error!("Failed to refresh the login, using client credentials: {e}");
+            if let Err(e) = Login::delete(&api_conf.id) {
+                error!("Failed to delete the login: {e}");
+            }
+            None
+        }
+        Err(e) => {
+            warn!("Failed to refresh the login, retrying: {e}");
+            Some(login.clone())
+        }
+    }
+}
+
+/// Settings of the fetches which can change on reload
+struct FetchOptions {
+    interval: Duration,
+    recent_limit: usize,
+    include_fails: bool,
+}
+
+impl FetchOptions {
+    fn new(config: &Config) -> Self {
+        Self {
+            interval: Duration::from_secs(config.poll_interval()),
+            recent_limit: config.recent_limit(),
+            include_fails: config.include_fails(),
+        }
+    }
+}
+
+/// Fetch the tracked user once, refetching scores if the statistics changed or `force` is set.
+/// Returns how long to wait until the next fetch, the poll interval unless fetching failed
+async fn fetch_once(
+    osu: &Osu,
+    tracked_data: &Arm<TrackedData>,
+    api_conf: &Api,
+    state_file: &StateFile,
+    force: bool,
+    options: &FetchOptions,
+) -> Duration {
+    let user_scores = || {
+        let scores = osu.user_scores(&api_conf.username);
+        match api_conf.mode {
+            Some(mode) => scores.mode(mode.into()),
+            None => scores,
+        }
+    };
+    let user = osu.user(&api_conf.username);
+    let user = match api_conf.mode {
+        Some(mode) => user.mode(mode.into()),
+        None => user,
+    };
+    let fetched_recent = user_scores()
+        .recent()
+        .include_fails(options.include_fails)
+        .limit(options.recent_limit);
+    let fetched_user = match user.await {
+        Ok(u) => {
+            debug!("Fetched: {}", u.username);
+            u
+        }
+        Err(e) => {
+            error!("Error: {e}");
+            return Duration::from_secs(5);
+        }
+    };
+
+    let changed = match &tracked_data.lock().await.user_extended {
+        Some(tracked_data_user) => tracked_data_user.statistics != fetched_user.statistics,
+        None => true,
+    } || force;
+    let fetched_recent = fetched_recent.await.unwrap_or_default();
+    if changed {
+        debug!("User data changed, fetching new data");
+        let fetched_tops = user_scores()
+            .limit(100)
+            .await
+            .inspect_err(|e| tracing::error!("{e}"))
+            .ok();
+        let fetched_firsts = user_scores()
+            .firsts()
+            .await
+            .inspect_err(|e| tracing::error!("{e}"))
+            .ok();
+        let mut tracked_data = tracked_data.lock().await;
+        tracked_data.grind.record(&fetched_recent);
+        tracked_data.insert(fetched_user, fetched_tops, fetched_firsts, fetched_recent);
+        let snapshot = tracked_data.clone();
+        drop(tracked_data);
+        if let Err(e) = state_file.save(api_conf, &snapshot).await {
+            error!("Failed to save state: {e}");
+        }
+    } else {
+        let mut tracked_data = tracked_data.lock().await;
+        tracked_data.grind.record(&fetched_recent);
+        tracked_data.user_extended = Some(fetched_user);
+        tracked_data.user_recent = Some(fetched_recent);
+    }
+    options.interval
+}
+
+/// Fetch the pinned scores and most played beatmaps of the tracked user, keeping the previous
+/// ones if that fails. Returns `false` if the user wasn't fetched yet
+async fn fetch_slow(osu: &Osu, tracked_data: &Arm<TrackedData>, api_conf: &Api) -> bool {
+    let user_id = tracked_data
+        .lock()
+        .await
+        .user_extended
+        .as_ref()
+        .map(|user| user.user_id);
+    let Some(user_id) = user_id else {
+        return false;
+    };
+    let pinned = osu.user_scores(user_id).pinned().limit(100);
+    let pinned = match api_conf.mode {
+        Some(mode) => pinned.mode(mode.into()),
+        None => pinned,
+    };
+    let fetched_pinned = pinned
+        .await
+        .inspect_err(|e| error!("Failed to fetch pinned scores: {e}"))
+        .ok();
+    // Not split by mode
+    let fetched_most_played = osu
+        .user_most_played(user_id)
+        .limit(MOST_PLAYED_LIMIT)
+        .await
+        .inspect_err(|e| error!("Failed to fetch most played beatmaps: {e}"))
+        .ok();
+    debug!("Fetched pinned scores and most played beatmaps");
+    let mut tracked_data = tracked_data.lock().await;
+    if fetched_pinned.is_some() {
+        tracked_data.user_pinned = fetched_pinned;
+    }
+    if fetched_most_played.is_some() {
+        tracked_data.user_most_played = fetched_most_played;
+    }
+    true
+}
+
 async fn serve(
-    clients: Clients,
-    // values: Arm<TrackedData>,
+    ctx: ServerContext,
     req: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>> {
+) -> Result<Response<ResponseBody>> {
     debug!("Called with uri {}", req.uri());
-    match req.uri().path() {
-        FIRSTS_ENDPOINT => serve_ws(clients, req, WsKind::Firsts).await,
-        TOPS_ENDPOINT => serve_ws(clients, req, WsKind::Tops).await,
-        USER_ENDPOINT => serve_ws(clients, req, WsKind::User).await,
-        RECENT_ENDPOINT => serve_ws(clients, req, WsKind::Recent).await,
+    if let Some(channel) = req.uri().path().strip_prefix(EVENTS_ENDPOINT) {
+        let channel = channel.to_owned();
+        return Ok(sse::serve_events(ctx, req, &channel));
+    }
+    if let Some(path) = req.uri().path().strip_prefix(OVERLAY_ENDPOINT) {
+        let path = path.to_owned();
+        return Ok(overlay::serve_overlay(ctx, req, &path).await);
+    }
+    route(ctx, req)
+        .await
+        .map(|res| res.map(BodyExt::boxed_unsync))
+}
+
+/// Serve every endpoint which responds with a complete body
+async fn route(
+    ctx: ServerContext,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<Full<Bytes>>> {
+    let config = ctx.config.borrow().clone();
+    let kind = match req.uri().path() {
+        FIRSTS_ENDPOINT => WsKind::Firsts,
+        TOPS_ENDPOINT => WsKind::Tops,
+        USER_ENDPOINT => WsKind::User,
+        RECENT_ENDPOINT => WsKind::Recent,
+        PINNED_ENDPOINT => WsKind::Pinned,
+        MOST_PLAYED_ENDPOINT => WsKind::MostPlayed,
+        GRIND_ENDPOINT => WsKind::Grind,
+        COMPARE_ENDPOINT => WsKind::Compare,
+        SCHEMA_ENDPOINT => return serve_schema(),
+        path @ (OAUTH_LOGIN_ENDPOINT | OAUTH_CALLBACK_ENDPOINT) => {
+            if let Err(rejection) = auth::authorize(&config.access, &req, oauth::OAUTH_CHANNEL) {
+                debug!("Rejected OAuth request with {}", rejection.status());
+                return Ok(rejection);
+            }
+            if path == OAUTH_LOGIN_ENDPOINT {
+                return Ok(oauth::serve_login(&ctx, auth::query_token(&req)));
+            }
+            let query = req.uri().query().unwrap_or_default();
+            return Ok(oauth::serve_callback(&ctx, query).await);
+        }
+        path if path.starts_with("/control/") => {
+            if let Err(rejection) = auth::check_origin(&config.access, &req) {
+                return Ok(rejection);
+            }
+            return control::serve_control(ctx.control, &config, req).await;
+        }
         _ => {
             println!("This URI doesn't exist");
-            Err(Error::msg("This URI doesn't exist"))
+            return Err(Error::msg("This URI doesn't exist"));
+        }
+    };
+    let handshake = match handshake::validate(&req) {
+        Ok(handshake) => handshake,
+        Err(rejection) => {
+            debug!("Invalid handshake for {:?}: {}", kind, rejection.status());
+            return Ok(rejection);
+        }
+    };
+    match auth::authorize(&config.access, &req, kind.name()) {
+        Ok(granted) => {
+            // A negotiated subprotocol wins over the query
+            let format = handshake
+                .protocol
+                .as_ref()
+                .and_then(|protocol| protocol.to_str().ok())
+                .and_then(Format::from_protocol)
+                .unwrap_or_else(|| Format::from_query(req.uri().query()));
+            serve_ws(ctx, req, kind, format, handshake, granted.protocol).await
+        }
+        Err(rejection) => {
+            debug!("Rejected {:?} client with {}", kind, rejection.status());
+            Ok(rejection)
         }
     }
 }
-#[tracing::instrument(name = "serve_ws")]
+/// JSON Schema of the versioned payloads, for overlay authors
+fn serve_schema() -> Result<Response<Full<Bytes>>> {
+    let schema = serde_json::to_vec_pretty(&SchemaDocument::new())?;
+    let mut res = Response::new(Full::new(Bytes::from(schema)));
+    res.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/schema+json"),
+    );
+    res.headers_mut()
+        .insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    Ok(res)
+}
+
+#[tracing::instrument(name = "serve_ws", skip(ctx, req))]
 async fn serve_ws(
-    clients: Clients,
+    ctx: ServerContext,
     mut req: Request<hyper::body::Incoming>,
     kind: WsKind,
+    format: Format,
+    handshake: Handshake,
+    token_protocol: Option<HeaderValue>,
 ) -> Result<Response<Full<Bytes>>> {
-    println!("websockets::serve_ws()");
-    let headers = req.headers();
-    let key = headers.get(SEC_WEBSOCKET_KEY);
-    let derived = key.map(|k| derive_accept_key(k.as_bytes()));
     let ver = req.version();
+    let compress = handshake.deflate;
+    // Shutdown waits for this client until it sent its close frame
+    let Some(alive) = ctx.alive.upgrade() else {
+        return Ok(auth::reject(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server is shutting down",
+        ));
+    };
 
     tokio::spawn(async move {
-        let upgraded = hyper::upgrade::on(&mut req).await.expect("Upgrade failed!");
+        let upgraded = match hyper::upgrade::on(&mut req).await {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                error!("Upgrade failed: {e}");
+                return;
+            }
+        };
 
-        let upgraded = TokioIo::new(upgraded);
+        let upgraded = deflate::Inflate::new(TokioIo::new(upgraded), compress);
 
         let client = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
 
-        let ws_client = WsClient { client, kind };
-
-        let mut clients = clients.lock().await;
-
-        clients.push(ws_client);
+        serve_client(client, kind, format, compress, ctx).await;
+        drop(alive);
     });
 
     let mut res = Response::new(Full::new(Bytes::default()));
@@ -199,10 +664,115 @@ async fn serve_ws(
     res.headers_mut()
         .append(UPGRADE, HeaderValue::from_static("websocket"));
 
-    res.headers_mut().append(
-        SEC_WEBSOCKET_ACCEPT,
-        derived.unwrap().parse().unwrap(), //TODO remove unwraps
-    );
+    res.headers_mut()
+        .append(SEC_WEBSOCKET_ACCEPT, handshake.accept);
+
+    // Browsers fail the connection unless one of the offered protocols is echoed back
+    if let Some(protocol) = handshake.protocol.or(token_protocol) {
+        res.headers_mut().append(SEC_WEBSOCKET_PROTOCOL, protocol);
+    }
+    if compress {
+        res.headers_mut().append(
+            SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static(deflate::RESPONSE),
+        );
+    }
 
     Ok(res)
 }
+
+/// Push snapshots of `kind` to a single client until it disconnects,
+/// falls behind or the server shuts down
+#[tracing::instrument(name = "client", skip(socket, ctx))]
+async fn serve_client(
+    socket: WsStream,
+    kind: WsKind,
+    format: Format,
+    compress: bool,
+    ctx: ServerContext,
+) {
+    let ServerContext {
+        mut snapshots,
+        mut shutdown,
+        ..
+    } = ctx;
+    let (mut sink, mut stream) = socket.split();
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    // The first tick completes immediately
+    ping.tick().await;
+    let mut awaiting_pong = false;
+    let mut last_sent: Option<Message> = None;
+    // Send the current data right away
+    snapshots.mark_changed();
+
+    let close_frame = loop {
+        tokio::select! {
+            changed = snapshots.changed() => {
+                if changed.is_err() {
+                    break None;
+                }
+                // Serializing the payload doesn't block the publish thread
+                let snapshot = snapshots.borrow_and_update().clone();
+                let Some(payload) = snapshot.get(kind, format).cloned() else {
+                    continue;
+                };
+                if last_sent.as_ref() == Some(&payload) {
+                    continue;
+                }
+                let message = if compress {
+                    deflate::compress(&payload)
+                } else {
+                    payload.clone()
+                };
+                match timeout(SEND_TIMEOUT, sink.send(message)).await {
+                    Ok(Ok(())) => last_sent = Some(payload),
+                    Ok(Err(e)) => {
+                        debug!("Failed to send: {e}");
+                        break None;
+                    }
+                    Err(_) => {
+                        debug!("Client is too slow, disconnecting");
+                        break Some(CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: Utf8Bytes::from_static("Client is too slow"),
+                        });
+                    }
+                }
+            }
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | None => break None,
+                Some(Ok(Message::Pong(_))) => awaiting_pong = false,
+                // Pings are answered by tungstenite, anything else is ignored
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    debug!("Failed to receive: {e}");
+                    break None;
+                }
+            },
+            _ = ping.tick() => {
+                if awaiting_pong {
+                    debug!("Client stopped answering pings");
+                    break None;
+                }
+                awaiting_pong = true;
+                let sent = timeout(SEND_TIMEOUT, sink.send(Message::Ping(Default::default()))).await;
+                if !matches!(sent, Ok(Ok(()))) {
+                    break None;
+                }
+            }
+            _ = shutdown.changed() => break Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: Utf8Bytes::from_static("Server is shutting down"),
+            }),
+        }
+    };
+
+    let closed = match close_frame {
+        Some(frame) => timeout(SEND_TIMEOUT, sink.send(Message::Close(Some(frame)))).await,
+        None => timeout(SEND_TIMEOUT, sink.close()).await,
+    };
+    if let Ok(Err(e)) = closed {
+        debug!("Failed to close: {e}");
+    }
+    debug!("Client disconnected");
+}
@@ -0,0 +1,463 @@
+//! OAuth authorization code login, for endpoints which need a user token.
+//!
+//! `/oauth/login` sends the browser to osu!, which redirects back to `/oauth/callback` with a code.
+//! The code is exchanged for a token, which is kept in the secret store and refreshed by the fetch
+//! thread before it expires. Without a login the tracker uses client credentials
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{Result, eyre::eyre};
+use constants::{HOST, OAUTH_CALLBACK_ENDPOINT};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    Request, Response, StatusCode,
+    body::Bytes,
+    header::{ACCEPT, CONTENT_TYPE, HeaderValue, LOCATION, SET_COOKIE},
+};
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+use tracing::{error, info};
+use types::Api;
+use utils::secret;
+
+use crate::{
+    auth::{reject, token_cookie, tokens_match},
+    structs::ServerContext,
+};
+
+/// Channel name tokens need access to for the login and its callback
+pub const OAUTH_CHANNEL: &str = "oauth";
+const AUTHORIZE_URL: &str = "https://osu.ppy.sh/oauth/authorize";
+const TOKEN_URL: &str = "https://osu.ppy.sh/oauth/token";
+const SCOPES: &str = "public identify";
+/// Tokens are refreshed this long before they expire
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Login state shared by the request handlers and the fetch thread
+#[derive(Clone)]
+pub struct OAuth {
+    /// `state` of the login in progress, checked by the callback
+    pending: Arc<Mutex<Option<String>>>,
+    /// New logins for the fetch thread
+    logins: Arc<watch::Sender<Option<Login>>>,
+}
+
+impl OAuth {
+    pub fn new(logins: watch::Sender<Option<Login>>) -> Self {
+        Self {
+            pending: Arc::default(),
+            logins: Arc::new(logins),
+        }
+    }
+}
+
+/// Token of a logged in user
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Login {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Unix timestamp, in seconds
+    pub expires_at: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+    /// In seconds
+    expires_in: u64,
+}
+
+impl From<TokenResponse> for Login {
+    fn from(res: TokenResponse) -> Self {
+        Self {
+            access_token: res.access_token,
+            refresh_token: res.refresh_token,
+            expires_at: now() + res.expires_in,
+        }
+    }
+}
+
+impl Login {
+    /// The stored login for a client, if anyone logged in
+    pub fn load(client_id: &str) -> Result<Option<Self>> {
+        secret::load_login(client_id)?
+            .map(|login| serde_json::from_str(&login).map_err(Into::into))
+            .transpose()
+    }
+
+    pub fn store(&self, client_id: &str) -> Result<()> {
+        secret::store_login(client_id, &serde_json::to_string(self)?)
+    }
+
+    pub fn delete(client_id: &str) -> Result<()> {
+        secret::delete_login(client_id)
+    }
+
+    pub fn needs_refresh(&self) -> bool {
+        now() + REFRESH_MARGIN.as_secs() >= self.expires_at
+    }
+
+    pub fn expired(&self) -> bool {
+        now() >= self.expires_at
+    }
+
+    /// A new token for the same user. osu! rotates refresh tokens, this one can't be used again
+    pub async fn refresh(&self, api: &Api) -> Result<Self> {
+        request_token(json!({
+            "client_id": api.id,
+            "client_secret": api.secret,
+            "grant_type": "refresh_token",
+            "refresh_token": self.refresh_token,
+            "scope": SCOPES,
+        }))
+        .await
+    }
+
+    async fn exchange(api: &Api, code: &str, redirect_uri: &str) -> Result<Self> {
+        request_token(json!({
+            "client_id": api.id,
+            "client_secret": api.secret,
+            "grant_type": "authorization_code",
+            "code": code,
+            "redirect_uri": redirect_uri,
+        }))
+        .await
+    }
+}
+
+async fn request_token(body: serde_json::Value) -> Result<Login> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_native_roots()?
+        .https_only()
+        .enable_http1()
+        .build();
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+    let req = Request::post(TOKEN_URL)
+        .header(CONTENT_TYPE, "application/json")
+        .header(ACCEPT, "application/json")
+        .body(Full::new(Bytes::from(serde_json::to_vec(&body)?)))?;
+    let res = client.request(req).await?;
+    let status = res.status();
+    let body = Limited::new(res.into_body(), MAX_RESPONSE_SIZE)
+        .collect()
+        .await
+        .map_err(|e| eyre!("Failed to read the token response: {e}"))?
+        .to_bytes();
+    if !status.is_success() {
+        return Err(eyre!(
+            "osu! refused the token request with {status}: {}",
+            String::from_utf8_lossy(&body)
+        ));
+    }
+    Ok(serde_json::from_slice::<TokenResponse>(&body)?.into())
+}
+
+/// Where osu! sends the browser back to, has to match the callback URL of the OAuth application
+pub fn redirect_uri(port: u16) -> String {
+    format!("http://{HOST}:{port}{OAUTH_CALLBACK_ENDPOINT}")
+}
+
+/// Send the browser to the osu! authorization page. The `?token=` the login was opened with is
+/// kept in a cookie, osu! doesn't pass it on to the callback
+pub(crate) fn serve_login(ctx: &ServerContext, token: Option<&str>) -> Response<Full<Bytes>> {
+    let config = ctx.config.borrow().clone();
+    if config.api.id.is_empty() {
+        return reject(StatusCode::CONFLICT, "Configure a client ID first");
+    }
+    let state = match new_state() {
+        Ok(state) => state,
+        Err(e) => {
+            error!("{e}");
+            return reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start a login");
+        }
+    };
+    *ctx.oauth
+        .pending
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = Some(state.clone());
+    let url = format!(
+        "{AUTHORIZE_URL}?client_id={}&redirect_uri={}&response_type=code&scope={}&state={state}",
+        encode(&config.api.id),
+        encode(&redirect_uri(config.port())),
+        encode(SCOPES),
+    );
+    let mut res = reject(StatusCode::FOUND, "Redirecting to osu!");
+    match HeaderValue::from_str(&url) {
+        Ok(location) => {
+            res.headers_mut().insert(LOCATION, location);
+            // Lax, the callback is a navigation coming from osu!
+            let cookie = token
+                .filter(|_| !config.access.tokens.is_empty())
+                .and_then(|token| token_cookie(token, OAUTH_CALLBACK_ENDPOINT, "Lax"));
+            if let Some(cookie) = cookie {
+                res.headers_mut().insert(SET_COOKIE, cookie);
+            }
+            res
+        }
+        Err(_) => reject(StatusCode::BAD_REQUEST, "Invalid client ID"),
+    }
+}
+
+/// Exchange the code osu! sent the browser back with for a token
+pub(crate) async fn serve_callback(ctx: &ServerContext, query: &str) -> Response<Full<Bytes>> {
+    let param = |name: &str| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            .map(decode)
+    };
+    if param("error").is_some() {
+        return reject(StatusCode::BAD_REQUEST, "Login was cancelled");
+    }
+    let (Some(code), Some(state)) = (param("code"), param("state")) else {
+        return reject(StatusCode::BAD_REQUEST, "Missing code or state");
+    };
+    let expected = ctx
+        .oauth
+        .pending
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    if !expected.is_some_and(|expected| tokens_match(&state, &expected)) {
+        return reject(
+            StatusCode::FORBIDDEN,
+            "Unknown login attempt, start over at /oauth/login",
+        );
+    }
+    let config = ctx.config.borrow().clone();
+    let login = match Login::exchange(&config.api, &code, &redirect_uri(config.port())).await {
+        Ok(login) => login,
+        Err(e) => {
+            error!("Failed to log in: {e}");
+            return reject(
+                StatusCode::BAD_GATEWAY,
+                "Failed to log in, see the tracker's log",
+            );
+        }
+    };
+    if let Err(e) = login.store(&config.api.id) {
+        error!("Failed to store the login, it only lasts until the tracker stops: {e}");
+    }
+    info!("Logged in");
+    ctx.oauth.logins.send_replace(Some(login));
+    reject(StatusCode::OK, "Logged in, you can close this tab")
+}
+
+/// 128 bits from the OS random number generator, hex encoded
+fn new_state() -> Result<String> {
+    let mut bytes = [0; 16];
+    getrandom::fill(&mut bytes).map_err(|e| eyre!("Failed to generate a login state: {e}"))?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Percent-encode everything but unreserved characters
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(escaped) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(if byte == b'+' { b' ' } else { byte });
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{Mutex, mpsc};
+    use types::{Access, AccessToken, Config};
+
+    use super::*;
+    use crate::{control, structs::TrackedData};
+
+    fn context(config: Config) -> ServerContext {
+        let (logins, _) = watch::channel(None);
+        let (alive, _) = mpsc::channel(1);
+        ServerContext {
+            values: Arc::new(Mutex::new(TrackedData::new())),
+            snapshots: watch::channel(Arc::default()).1,
+            overlays: watch::channel(Arc::default()).1,
+            config: watch::channel(config).1,
+            control: control::channel().0,
+            oauth: OAuth::new(logins),
+            shutdown: watch::channel(false).1,
+            alive: alive.downgrade(),
+        }
+    }
+
+    fn configured(tokens: &[&str]) -> Config {
+        Config {
+            api: Api {
+                id: "1234".to_owned(),
+                ..Api::default()
+            },
+            access: Access {
+                tokens: tokens
+                    .iter()
+                    .map(|token| AccessToken {
+                        token: (*token).to_owned(),
+                        channels: None,
+                    })
+                    .collect(),
+                ..Access::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    fn pending(ctx: &ServerContext) -> Option<String> {
+        ctx.oauth.pending.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn encodes_reserved_characters() {
+        assert_eq!(encode("public identify"), "public%20identify");
+        assert_eq!(
+            encode("http://127.0.0.1:7272/oauth/callback"),
+            "http%3A%2F%2F127.0.0.1%3A7272%2Foauth%2Fcallback"
+        );
+        assert_eq!(encode("a-z_0.9~é"), "a-z_0.9~%C3%A9");
+        assert_eq!(decode(&encode("a b&c=d/é+")), "a b&c=d/é+");
+    }
+
+    #[test]
+    fn decodes_leniently() {
+        assert_eq!(decode("%41%62c"), "Abc");
+        assert_eq!(decode("%e2%9c%93"), "✓");
+        assert_eq!(decode("a+b"), "a b");
+        assert_eq!(decode("%2B"), "+");
+        // Invalid or truncated escapes are kept as they are
+        assert_eq!(decode("%zz"), "%zz");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%4"), "%4");
+        assert_eq!(decode("%%41"), "%A");
+        assert_eq!(decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn states_are_random_hex() {
+        let state = new_state().unwrap();
+        assert_eq!(state.len(), 32);
+        assert!(
+            state
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        );
+        assert_ne!(state, new_state().unwrap());
+    }
+
+    #[test]
+    fn login_redirects_to_osu() {
+        let ctx = context(configured(&[]));
+        let res = serve_login(&ctx, None);
+        assert_eq!(res.status(), StatusCode::FOUND);
+        let state = pending(&ctx).unwrap();
+        let location = res.headers()[LOCATION].to_str().unwrap();
+        assert_eq!(
+            location,
+            format!(
+                "{AUTHORIZE_URL}?client_id=1234&redirect_uri={}&response_type=code\
+                 &scope=public%20identify&state={state}",
+                encode(&redirect_uri(Config::default().port()))
+            )
+        );
+        assert!(!res.headers().contains_key(SET_COOKIE));
+
+        // Starting over replaces the pending login
+        serve_login(&ctx, None);
+        assert_ne!(pending(&ctx).unwrap(), state);
+    }
+
+    #[test]
+    fn login_keeps_token_for_callback() {
+        let ctx = context(configured(&["secret"]));
+        let res = serve_login(&ctx, Some("secret"));
+        assert_eq!(
+            res.headers()[SET_COOKIE],
+            "rosu_tracker_token=secret; Path=/oauth/callback; HttpOnly; SameSite=Lax"
+        );
+        // Public without tokens, nothing to keep
+        let ctx = context(configured(&[]));
+        let res = serve_login(&ctx, Some("secret"));
+        assert!(!res.headers().contains_key(SET_COOKIE));
+    }
+
+    #[test]
+    fn login_needs_client_id() {
+        let ctx = context(Config::default());
+        assert_eq!(serve_login(&ctx, None).status(), StatusCode::CONFLICT);
+        assert!(pending(&ctx).is_none());
+    }
+
+    #[tokio::test]
+    async fn callback_needs_code_and_state() {
+        let ctx = context(configured(&[]));
+        serve_login(&ctx, None);
+        let state = pending(&ctx).unwrap();
+        for query in [
+            String::new(),
+            "code=abc".to_owned(),
+            format!("state={state}"),
+            format!("code=&state={state}&error=access_denied"),
+        ] {
+            let res = serve_callback(&ctx, &query).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+        // Rejected before the state was looked at
+        assert_eq!(pending(&ctx), Some(state));
+    }
+
+    #[tokio::test]
+    async fn callback_checks_state() {
+        let ctx = context(configured(&[]));
+        let res = serve_callback(&ctx, "code=abc&state=0123").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        serve_login(&ctx, None);
+        let state = pending(&ctx).unwrap();
+        let res = serve_callback(&ctx, "code=abc&state=0123").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // A state is only good for one attempt
+        assert!(pending(&ctx).is_none());
+        let res = serve_callback(&ctx, &format!("code=abc&state={state}")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,11 @@
+// Injected into every overlay page by rosu-tracker.
+// Swaps in the re-rendered page whenever the data changes and reloads when the template does
+(() => {
+  const path = location.pathname.replace(/\/$/, "");
+  const events = new EventSource(path + "/events" + location.search);
+  events.addEventListener("render", (event) => {
+    const page = new DOMParser().parseFromString(event.data, "text/html");
+    document.body.replaceWith(page.body);
+  });
+  events.addEventListener("reload", () => location.reload());
+})();
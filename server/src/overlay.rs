@@ -0,0 +1,363 @@
+//! HTML/CSS overlays rendered from the files in the overlay directory.
+//!
+//! Every file is served as `/overlay/<file>`. `.html` and `.css` files are templates using
+//! `{{name:spec}}` placeholders, anything else (images, fonts, ...) is served as is.
+//! Pages get a small script injected which listens on `/overlay/<file>/events`, swapping in
+//! the re-rendered page when the data changes and reloading it when the template changes
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use constants::OVERLAY_ENDPOINT;
+use directories::ProjectDirs;
+use futures_util::{StreamExt, stream};
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Frame},
+    header::{CACHE_CONTROL, CONTENT_TYPE, HeaderValue, SET_COOKIE},
+};
+use tokio::{
+    sync::watch,
+    time::{MissedTickBehavior, sleep},
+};
+use tracing::{debug, error, info};
+use types::Config;
+
+use crate::{
+    auth,
+    sse::{Event, KEEPALIVE, KEEPALIVE_INTERVAL, RETRY, format_event},
+    structs::{ResponseBody, ServerContext},
+    template::{Syntax, Template, variables},
+};
+
+/// Channel name tokens need access to for overlays
+pub const OVERLAY_CHANNEL: &str = "overlay";
+/// How often the overlay directory is checked for changes
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+/// Larger files are not served
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+const CLIENT_SCRIPT: &str = include_str!("overlay.js");
+
+/// Contents of the overlay directory
+#[derive(Debug, Default)]
+pub struct Overlays {
+    files: HashMap<String, OverlayFile>,
+}
+
+#[derive(Debug, Clone)]
+struct OverlayFile {
+    modified: SystemTime,
+    content: Content,
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Html(Template),
+    Css(Template),
+    Raw(Bytes),
+    /// The file couldn't be loaded, served as an error so template authors see what's wrong
+    Invalid(String),
+}
+
+impl Overlays {
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        self.files.get(name).map(|file| file.modified)
+    }
+
+    fn same_files(&self, other: &Overlays) -> bool {
+        self.files.len() == other.files.len()
+            && self
+                .files
+                .iter()
+                .all(|(name, file)| other.modified(name) == Some(file.modified))
+    }
+}
+
+/// Directory overlays are loaded from, `overlays` in the config directory by default
+pub fn overlay_dir(config: &Config) -> Option<PathBuf> {
+    match &config.overlay_dir {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => ProjectDirs::from("com", "chiffa", "rosuTracker")
+            .map(|dirs| dirs.config_dir().join("overlays")),
+    }
+}
+
+/// Keep the overlays in sync with the files on disk
+#[tracing::instrument(name = "overlay_thread", skip_all)]
+pub async fn overlay_thread(
+    overlays: watch::Sender<Arc<Overlays>>,
+    mut config: watch::Receiver<Config>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut dir = overlay_dir(&config.borrow_and_update());
+    let mut interval = tokio::time::interval(SCAN_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        let current = overlays.borrow().clone();
+        let scanned = match &dir {
+            Some(dir) => scan(dir, &current).await,
+            None => Overlays::default(),
+        };
+        if !scanned.same_files(&current) {
+            info!("Loaded {} overlay files", scanned.files.len());
+            overlays.send_replace(Arc::new(scanned));
+        }
+        tokio::select! {
+            _ = interval.tick() => {}
+            changed = config.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                dir = overlay_dir(&config.borrow_and_update());
+            }
+            _ = shutdown.changed() => return,
+        }
+    }
+}
+
+/// Read the files in `dir`, reusing the ones which didn't change since `previous`
+async fn scan(dir: &Path, previous: &Overlays) -> Overlays {
+    let mut files = HashMap::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Can't read {}: {e}", dir.display());
+            return Overlays::default();
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        // Skip hidden files and editor backups
+        if name.starts_with('.') || name.ends_with('~') {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        let Ok(modified) = metadata.modified() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let file = match previous.files.get(&name) {
+            Some(file) if file.modified == modified => file.clone(),
+            _ => OverlayFile {
+                modified,
+                content: load(&entry.path(), &name, metadata.len()).await,
+            },
+        };
+        files.insert(name, file);
+    }
+    Overlays { files }
+}
+
+async fn load(path: &Path, name: &str, len: u64) -> Content {
+    if len > MAX_FILE_SIZE {
+        return Content::Invalid(format!("File is larger than {MAX_FILE_SIZE} bytes"));
+    }
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) => return Content::Invalid(format!("Failed to read file: {e}")),
+    };
+    let template = |bytes: Vec<u8>| {
+        String::from_utf8(bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|source| {
+                Template::parse_with(&source, Syntax::Double).map_err(|e| e.to_string())
+            })
+    };
+    let content = match extension(name).as_deref() {
+        Some("html" | "htm") => template(bytes).map(Content::Html),
+        Some("css") => template(bytes).map(Content::Css),
+        _ => Ok(Content::Raw(Bytes::from(bytes))),
+    };
+    content.unwrap_or_else(|e| {
+        error!("Invalid overlay {name}: {e}");
+        Content::Invalid(e)
+    })
+}
+
+/// Serve `/overlay/<file>` and `/overlay/<file>/events`
+pub(crate) async fn serve_overlay(
+    ctx: ServerContext,
+    req: Request<hyper::body::Incoming>,
+    path: &str,
+) -> Response<ResponseBody> {
+    if req.method() != Method::GET {
+        return auth::reject(StatusCode::METHOD_NOT_ALLOWED, "Use GET").map(BodyExt::boxed_unsync);
+    }
+    let config = ctx.config.borrow().clone();
+    if let Err(rejection) = auth::authorize(&config.access, &req, OVERLAY_CHANNEL) {
+        debug!("Rejected overlay request with {}", rejection.status());
+        return rejection.map(BodyExt::boxed_unsync);
+    }
+    if let Some(name) = path.strip_suffix("/events") {
+        return serve_events(ctx, name);
+    }
+
+    let overlays = ctx.overlays.borrow().clone();
+    let Some(file) = overlays.files.get(path) else {
+        return auth::reject(StatusCode::NOT_FOUND, "Unknown overlay file")
+            .map(BodyExt::boxed_unsync);
+    };
+    let mut res = match &file.content {
+        Content::Html(template) => {
+            let vars = variables(&*ctx.values.lock().await);
+            respond(
+                StatusCode::OK,
+                "text/html; charset=utf-8",
+                inject_script(template.render_html(&vars)),
+            )
+        }
+        Content::Css(template) => {
+            let vars = variables(&*ctx.values.lock().await);
+            respond(
+                StatusCode::OK,
+                "text/css; charset=utf-8",
+                template.render(&vars),
+            )
+        }
+        Content::Raw(bytes) => respond(StatusCode::OK, content_type(path), bytes.clone()),
+        Content::Invalid(e) => respond(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "text/plain; charset=utf-8",
+            format!("{path}: {e}"),
+        ),
+    };
+    // Browsers don't pass `?token=` on to the stylesheets and images a page loads
+    if !config.access.tokens.is_empty() {
+        let path = OVERLAY_ENDPOINT.trim_end_matches('/');
+        if let Some(cookie) =
+            auth::query_token(&req).and_then(|token| auth::token_cookie(token, path, "Strict"))
+        {
+            res.headers_mut().insert(SET_COOKIE, cookie);
+        }
+    }
+    res
+}
+
+fn serve_events(ctx: ServerContext, name: &str) -> Response<ResponseBody> {
+    let Some(revision) = ctx
+        .overlays
+        .borrow()
+        .files
+        .get(name)
+        .filter(|file| matches!(file.content, Content::Html(_)))
+        .map(|file| file.modified)
+    else {
+        return auth::reject(StatusCode::NOT_FOUND, "Unknown overlay page")
+            .map(BodyExt::boxed_unsync);
+    };
+    debug!("Streaming overlay {name}");
+    let retry = stream::once(async { Ok(Frame::data(Bytes::from_static(RETRY))) });
+    let body = StreamBody::new(retry.chain(events(ctx, name.to_owned(), revision))).boxed_unsync();
+    let mut res = Response::new(body);
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    res
+}
+
+/// Send the page whenever its rendering changes, and `reload` once the template changed on disk
+fn events(
+    ctx: ServerContext,
+    name: String,
+    revision: SystemTime,
+) -> impl futures_util::Stream<Item = Event> + Send + 'static {
+    stream::unfold(
+        (ctx, name, Some(revision), None::<String>),
+        |(mut ctx, name, mut revision, last)| async move {
+            loop {
+                let overlays = ctx.overlays.borrow_and_update().clone();
+                let file = overlays.files.get(&name);
+                let modified = file.map(|file| file.modified);
+                if modified != revision {
+                    revision = modified;
+                    let frame = Frame::data(format_event("reload", None, &name));
+                    return Some((Ok(frame), (ctx, name, revision, last)));
+                }
+                if let Some(Content::Html(template)) = file.map(|file| &file.content) {
+                    let vars = variables(&*ctx.values.lock().await);
+                    let rendered = template.render_html(&vars);
+                    if last.as_ref() != Some(&rendered) {
+                        let frame = Frame::data(format_event("render", None, &rendered));
+                        return Some((Ok(frame), (ctx, name, revision, Some(rendered))));
+                    }
+                }
+                tokio::select! {
+                    changed = ctx.snapshots.changed() => {
+                        if changed.is_err() {
+                            return None;
+                        }
+                    }
+                    changed = ctx.overlays.changed() => {
+                        if changed.is_err() {
+                            return None;
+                        }
+                    }
+                    _ = sleep(KEEPALIVE_INTERVAL) => {
+                        let frame = Frame::data(Bytes::from_static(KEEPALIVE));
+                        return Some((Ok(frame), (ctx, name, revision, last)));
+                    }
+                    _ = ctx.shutdown.changed() => return None,
+                }
+            }
+        },
+    )
+}
+
+/// Add the client script right before `</body>`, or at the end of the page
+fn inject_script(mut page: String) -> String {
+    let script = format!("<script>{CLIENT_SCRIPT}</script>");
+    match page.to_ascii_lowercase().rfind("</body>") {
+        Some(end) => page.insert_str(end, &script),
+        None => page.push_str(&script),
+    }
+    page
+}
+
+fn respond(
+    status: StatusCode,
+    content_type: &'static str,
+    body: impl Into<Bytes>,
+) -> Response<ResponseBody> {
+    let mut res = Response::new(Full::new(body.into()).boxed_unsync());
+    *res.status_mut() = status;
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    res
+}
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+fn content_type(name: &str) -> &'static str {
+    match extension(name).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
@@ -1,86 +1,330 @@
-use color_eyre::eyre::{Report, eyre};
-use color_eyre::{Result, eyre};
-// use cosmic::cosmic_config::{self, ConfigGet};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use directories::ProjectDirs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify, mpsc, watch};
 
-use rosu_v2::Osu;
-use serde::{Deserialize, Serialize};
-use tracing::{debug, error};
+use tracing::{debug, error, info};
 
-use crate::structs::{Arm, Clients, TrackedData};
-use crate::{fetch_thread, handle_clients, server_thread};
-use types::Api;
-use types::Either;
+use crate::compare;
+use crate::control::{self, Command};
+use crate::oauth::{Login, OAuth};
+use crate::overlay::{Overlays, overlay_thread};
+use crate::sinks::sink_thread;
+use crate::state::StateFile;
+use crate::structs::{Arm, ServerContext, Session, Snapshot, TrackedData};
+use crate::{compare_thread, fetch_thread, publish_thread, server_thread};
+use types::{Api, Config};
+use utils::secret;
 
-pub async fn thread_init(config: Either<Api, fn() -> Result<Api>>) -> Result<()> {
+/// Where the tracker configuration comes from
+pub enum ConfigSource {
+    /// Configuration given on the command line. Reloading it is a no-op
+    Fixed(Config),
+    /// Path to a `config.toml`
+    File(PathBuf),
+    /// Custom loader, e.g. for the cosmic config
+    Loader(fn() -> Result<Config>),
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config> {
+        match self {
+            ConfigSource::Fixed(config) => Ok(config.clone()),
+            ConfigSource::File(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| eyre!("Failed to read {}: {e}", path.display()))?;
+                let mut config: Config = toml::from_str(&contents)?;
+                if config.api.secret.is_empty() {
+                    config.api.secret = secret::load(&config.api.id)?.unwrap_or_default();
+                } else {
+                    migrate_secret(path, &contents, &config.api);
+                }
+                Ok(config)
+            }
+            ConfigSource::Loader(fun) => fun(),
+        }
+    }
+}
+
+/// Move a secret left in plain text in `config.toml` into the secret store and blank it in the
+/// file, keeping the rest of the file as it is
+fn migrate_secret(path: &Path, contents: &str, api: &Api) {
+    if let Err(e) = secret::store(&api.id, &api.secret) {
+        error!(
+            "Failed to store the client secret, leaving it in {}: {e}",
+            path.display()
+        );
+        return;
+    }
+    match blank_secret(path, contents) {
+        Ok(()) => info!(
+            "Moved the client secret from {} into the secret store",
+            path.display()
+        ),
+        Err(e) => error!(
+            "Failed to remove the client secret from {}: {e}",
+            path.display()
+        ),
+    }
+}
+
+fn blank_secret(path: &Path, contents: &str) -> Result<()> {
+    let mut document: toml_edit::DocumentMut = contents.parse()?;
+    document["secret"] = toml_edit::value("");
+    let tmp = path.with_extension("toml.tmp");
+    std::fs::write(&tmp, document.to_string())?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// The config from its source, with the user picked through the control API on top
+struct Configs {
+    source: ConfigSource,
+    /// Set by `Track` and `Untrack`, kept over reloads
+    username: Option<String>,
+}
+
+impl Configs {
+    fn load(&self) -> Result<Config> {
+        let mut config = self.source.load()?;
+        if let Some(username) = &self.username {
+            config.api.username.clone_from(username);
+        }
+        Ok(config)
+    }
+
+    /// Track `username` until the next `Track` or `Untrack`, nobody if it's empty
+    fn track(&mut self, username: String, config: &mut Config) {
+        config.api.username.clone_from(&username);
+        self.username = Some(username);
+    }
+}
+
+/// Default location of `config.toml`
+pub fn default_config_path() -> Option<PathBuf> {
+    ProjectDirs::from("com", "chiffa", "rosuTracker")
+        .map(|dirs| dirs.config_dir().join("config.toml"))
+}
+
+pub async fn thread_init(
+    source: ConfigSource,
+    handle: control::Handle,
+    mut commands: control::Receiver,
+) -> Result<()> {
     // Setup tracing
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_target(false)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let clients = Clients::default();
-    let api_conf = match config {
-        Either::Left(api) => api,
-        Either::Right(fun) => fun()?,
+    let mut configs = Configs {
+        source,
+        username: None,
     };
-    // Prep empty websocket clients
-    debug!("Constructed clients");
-    let osu = Arc::new(
-        Osu::new(api_conf.id.parse().unwrap(), &api_conf.secret)
-            .await
-            .map_err(|e| eyre!("Failed to initialise osu client: {e}"))?,
-    );
+    let config = configs.load()?;
+    let state_file = StateFile::new(&config)?;
+    let restored = state_file
+        .load(&config.api)
+        .await
+        .inspect_err(|e| error!("failed to load saved state: {}", e))
+        .ok()
+        .flatten();
+    if restored.is_some() {
+        info!("Restored the previous session");
+    }
+    let tracked_data: Arm<TrackedData> =
+        Arc::new(Mutex::new(restored.unwrap_or_else(TrackedData::new)));
+    let login = Login::load(&config.api.id)
+        .inspect_err(|e| error!("failed to load the login: {}", e))
+        .ok()
+        .flatten();
+    if login.is_some() {
+        info!("Logged in, fetching with the user's token");
+    }
+    let (login_tx, login_rx) = watch::channel(login);
+    let (config_tx, config_rx) = watch::channel(config);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let refresh = Arc::new(Notify::new());
 
-    let osu_user = osu.user(&api_conf.username);
-    let osu_user_scores = osu.user_scores(&api_conf.username).limit(100);
-    let osu_user_firsts = osu.user_scores(&api_conf.username).firsts();
-    let osu_user_recent = osu.user_scores(&api_conf.username).recent().limit(20);
+    let (snapshot_tx, snapshot_rx) = watch::channel(Arc::new(Snapshot::default()));
+    let (alive_tx, mut alive_rx) = mpsc::channel::<()>(1);
 
-    let tracked_data: Arm<TrackedData> = Arc::new(Mutex::new(TrackedData::new()));
-    let alt_clients = clients.clone();
-    // Setup a thread to actually serve the data
+    // Setup a thread to serialize the data for clients
     let tracker = tracked_data.clone();
-    let client_thread = tokio::spawn(async move {
-        loop {
-            handle_clients(alt_clients.clone(), tracker.clone()).await;
-        }
-    });
-    debug!("Spawned client thread");
+    let shutdown = shutdown_rx.clone();
+    let publish_thread =
+        tokio::spawn(async move { publish_thread(tracker, snapshot_tx, shutdown).await });
+    debug!("Spawned publish thread");
+    // Setup a thread to render the file sinks
+    let tracker = tracked_data.clone();
+    let sink_snapshots = snapshot_rx.clone();
+    let sink_config = config_rx.clone();
+    let shutdown = shutdown_rx.clone();
+    let sink_thread =
+        tokio::spawn(
+            async move { sink_thread(tracker, sink_snapshots, sink_config, shutdown).await },
+        );
+    debug!("Spawned sink thread");
+    // Setup a thread to watch the overlay directory
+    let (overlay_tx, overlay_rx) = watch::channel(Arc::new(Overlays::default()));
+    let overlay_config = config_rx.clone();
+    let shutdown = shutdown_rx.clone();
+    let overlay_thread =
+        tokio::spawn(async move { overlay_thread(overlay_tx, overlay_config, shutdown).await });
+    debug!("Spawned overlay thread");
     // Setup thread to fetch data from osu api
     let tracker = tracked_data.clone();
-    let osu_ref = osu.clone();
+    let fetch_config = config_rx.clone();
+    let fetch_state = state_file.clone();
+    let fetch_refresh = refresh.clone();
+    let (client_tx, client_rx) = watch::channel(None);
+    let shutdown = shutdown_rx.clone();
     let fetch_thread = tokio::spawn(async move {
-        let tracker = tracker.clone();
-        let config = api_conf.clone();
-        fetch_thread(osu_ref, tracker, config).await;
+        fetch_thread(
+            tracker,
+            fetch_config,
+            login_rx,
+            fetch_state,
+            fetch_refresh,
+            client_tx,
+            shutdown,
+        )
+        .await;
     });
     debug!("Spawned fetch thread");
-    // Setup a thread to run the server
+    // Setup a thread to fetch the players from `compare_users`
     let tracker = tracked_data.clone();
-    let server_thread = tokio::spawn(async { server_thread(clients, tracker).await });
-    let user = osu_user
-        .await
-        .inspect_err(|e| error!("failed to fetch user: {}", e))
-        .ok();
-    let scores = osu_user_scores
-        .await
-        .inspect_err(|e| error!("failed to fetch top scores: {}", e))
-        .ok();
-    let firsts = osu_user_firsts
-        .await
-        .inspect_err(|e| error!("failed to fetch first place scores: {}", e))
-        .ok();
-    let recent = osu_user_recent
-        .await
-        .inspect_err(|e| error!("failed to fetch recent scores: {}", e))
-        .ok();
-    tracked_data
-        .lock()
-        .await
-        .insert(user, scores, firsts, recent);
+    let compare_config = config_rx.clone();
+    let shutdown = shutdown_rx.clone();
+    let compare_thread = tokio::spawn(async move {
+        compare_thread(tracker, compare_config, client_rx, shutdown).await;
+    });
+    debug!("Spawned compare thread");
+    // Setup a thread to run the server
+    let ctx = ServerContext {
+        values: tracked_data.clone(),
+        snapshots: snapshot_rx,
+        overlays: overlay_rx,
+        config: config_rx,
+        control: handle,
+        oauth: OAuth::new(login_tx),
+        shutdown: shutdown_rx,
+        alive: alive_tx.downgrade(),
+    };
+    let server_thread = tokio::spawn(async move { server_thread(ctx).await });
     debug!("Spawned server thread");
-    let _ = tokio::join!(fetch_thread, server_thread, client_thread);
+
+    loop {
+        match commands.recv().await {
+            Some(Command::Reload) => match configs.load() {
+                Ok(config) => {
+                    info!("Reloaded configuration");
+                    config_tx.send_replace(config);
+                }
+                Err(e) => error!("Failed to reload configuration, keeping the old one: {e}"),
+            },
+            Some(Command::Track(username)) => {
+                info!("Switching to {username}");
+                config_tx.send_modify(|config| configs.track(username, config));
+            }
+            Some(Command::Untrack) => {
+                info!("Stopped tracking");
+                config_tx.send_modify(|config| configs.track(String::new(), config));
+            }
+            Some(Command::ResetSession) => {
+                let mut data = tracked_data.lock().await;
+                data.session = data
+                    .user_extended
+                    .clone()
+                    .map(|user| Session::start(user, data.user_scores.clone()));
+                compare::restart_sessions(&mut data.compared);
+                let snapshot = data.clone();
+                drop(data);
+                info!("Started a new session");
+                let api_conf = config_tx.borrow().api.clone();
+                if let Err(e) = state_file.save(&api_conf, &snapshot).await {
+                    error!("Failed to save state: {e}");
+                }
+            }
+            Some(Command::Refresh) => refresh.notify_one(),
+            Some(Command::Shutdown) | None => break,
+        }
+    }
+
+    info!("Shutting down");
+    let _ = shutdown_tx.send(true);
+    let _ = tokio::join!(
+        fetch_thread,
+        compare_thread,
+        server_thread,
+        publish_thread,
+        sink_thread,
+        overlay_thread
+    );
+    // Every client task holds a sender, this returns once all of them sent their close frames
+    drop(alive_tx);
+    if tokio::time::timeout(Duration::from_secs(15), alive_rx.recv())
+        .await
+        .is_err()
+    {
+        error!("Timed out waiting for clients to disconnect");
+    }
+    let api_conf = config_tx.borrow().api.clone();
+    let data = tracked_data.lock().await;
+    state_file.save(&api_conf, &data).await?;
+    info!("Saved state, bye");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configs(username: &str) -> Configs {
+        let mut config = Config::default();
+        config.api.username = username.to_owned();
+        Configs {
+            source: ConfigSource::Fixed(config),
+            username: None,
+        }
+    }
+
+    #[test]
+    fn reload_keeps_tracked_user() {
+        let mut configs = configs("config user");
+        let mut config = configs.load().unwrap();
+        assert_eq!(config.api.username, "config user");
+        configs.track("other".to_owned(), &mut config);
+        assert_eq!(config.api.username, "other");
+        assert_eq!(configs.load().unwrap().api.username, "other");
+    }
+
+    #[test]
+    fn reload_keeps_untracked() {
+        let mut configs = configs("config user");
+        let mut config = configs.load().unwrap();
+        configs.track("other".to_owned(), &mut config);
+        configs.track(String::new(), &mut config);
+        assert_eq!(config.api.username, "");
+        assert_eq!(configs.load().unwrap().api.username, "");
+        configs.track("third".to_owned(), &mut config);
+        assert_eq!(configs.load().unwrap().api.username, "third");
+    }
+
+    #[test]
+    fn reload_applies_other_changes() {
+        let mut configs = configs("config user");
+        let mut config = configs.load().unwrap();
+        configs.track("other".to_owned(), &mut config);
+        let ConfigSource::Fixed(source) = &mut configs.source else {
+            unreachable!()
+        };
+        source.poll_interval = Some(30);
+        let config = configs.load().unwrap();
+        assert_eq!(config.api.username, "other");
+        assert_eq!(config.poll_interval(), 30);
+    }
+}
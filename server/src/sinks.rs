@@ -0,0 +1,92 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use color_eyre::Result;
+use tokio::sync::watch;
+use tracing::{debug, error};
+use types::{Config, FileSink};
+
+use crate::{
+    structs::{Arm, Snapshot, TrackedData},
+    template::{Template, variables},
+};
+
+/// A file sink with its template parsed
+struct Output {
+    path: PathBuf,
+    template: Template,
+}
+
+fn parse_sinks(sinks: &[FileSink]) -> Vec<Output> {
+    sinks
+        .iter()
+        .filter_map(|sink| match Template::parse(&sink.template) {
+            Ok(template) => Some(Output {
+                path: PathBuf::from(&sink.path),
+                template,
+            }),
+            Err(e) => {
+                error!("Invalid template for {}: {e}", sink.path);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Render every configured file sink whenever the data or the config changes
+#[tracing::instrument(name = "sink_thread", skip_all)]
+pub async fn sink_thread(
+    values: Arm<TrackedData>,
+    mut snapshots: watch::Receiver<Arc<Snapshot>>,
+    mut config: watch::Receiver<Config>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut outputs = parse_sinks(&config.borrow_and_update().sinks);
+    // Last content written to each file, to skip rewriting unchanged files
+    let mut written: HashMap<PathBuf, String> = HashMap::new();
+    loop {
+        if !outputs.is_empty() {
+            let vars = variables(&*values.lock().await);
+            for output in &outputs {
+                let rendered = output.template.render(&vars);
+                if written.get(&output.path) == Some(&rendered) {
+                    continue;
+                }
+                match write_atomic(&output.path, &rendered).await {
+                    Ok(()) => {
+                        debug!("Wrote {}", output.path.display());
+                        written.insert(output.path.clone(), rendered);
+                    }
+                    Err(e) => error!("Failed to write {}: {e}", output.path.display()),
+                }
+            }
+        }
+        tokio::select! {
+            changed = snapshots.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            changed = config.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                outputs = parse_sinks(&config.borrow_and_update().sinks);
+                written.clear();
+            }
+            _ = shutdown.changed() => return,
+        }
+    }
+}
+
+/// Write to a temporary file next to `path` and move it in place,
+/// so OBS never reads a half written file
+async fn write_atomic(path: &PathBuf, contents: &str) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{file_name}.tmp"));
+    tokio::fs::write(&tmp, contents).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
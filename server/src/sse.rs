@@ -0,0 +1,179 @@
+use std::{
+    convert::Infallible,
+    sync::LazyLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{StreamExt, stream};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Frame},
+    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONTENT_TYPE, HeaderValue, ORIGIN},
+};
+use tokio::time::sleep;
+use tracing::debug;
+
+use crate::{
+    auth,
+    structs::{Format, ResponseBody, ServerContext, WsKind},
+};
+
+/// Name of the header browsers send when an `EventSource` reconnects
+const LAST_EVENT_ID: &str = "last-event-id";
+/// Comments are sent this often when nothing changes, so dead connections get noticed
+pub(crate) const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+pub(crate) const KEEPALIVE: &[u8] = b": keepalive\n\n";
+/// How long clients wait before reconnecting, in milliseconds
+pub(crate) const RETRY: &[u8] = b"retry: 5000\n\n";
+
+pub(crate) type Event = Result<Frame<Bytes>, Infallible>;
+
+/// Unique to the process, in milliseconds since the unix epoch. Revisions start over with every
+/// process, so event ids are `<epoch>-<revision>` to tell them apart
+static EPOCH: LazyLock<u64> = LazyLock::new(|| {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+});
+
+/// Stream every change of `channel` as `text/event-stream`.
+/// Event ids are payload revisions, so a client reconnecting with `Last-Event-ID`
+/// only gets the payload again if it changed in the meantime or the server restarted
+pub(crate) fn serve_events(
+    ctx: ServerContext,
+    req: Request<hyper::body::Incoming>,
+    channel: &str,
+) -> Response<ResponseBody> {
+    let Some(kind) = WsKind::from_name(channel) else {
+        return auth::reject(StatusCode::NOT_FOUND, "Unknown channel").map(BodyExt::boxed_unsync);
+    };
+    if req.method() != Method::GET {
+        return auth::reject(StatusCode::METHOD_NOT_ALLOWED, "Use GET").map(BodyExt::boxed_unsync);
+    }
+    let config = ctx.config.borrow().clone();
+    if let Err(rejection) = auth::authorize(&config.access, &req, kind.name()) {
+        debug!(
+            "Rejected {:?} event stream with {}",
+            kind,
+            rejection.status()
+        );
+        return rejection.map(BodyExt::boxed_unsync);
+    }
+    let last_event_id = req
+        .headers()
+        .get(LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(parse_event_id);
+    debug!("Streaming {:?} events from {:?}", kind, last_event_id);
+
+    let format = Format::from_query(req.uri().query());
+    if format == Format::MessagePack {
+        return auth::reject(
+            StatusCode::BAD_REQUEST,
+            "Event streams can't carry MessagePack",
+        )
+        .map(BodyExt::boxed_unsync);
+    }
+    let retry = stream::once(async { Ok(Frame::data(Bytes::from_static(RETRY))) });
+    let body =
+        StreamBody::new(retry.chain(events(ctx, kind, format, last_event_id))).boxed_unsync();
+
+    let mut res = Response::new(body);
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    // The origin already passed the allowlist, let cross-origin `EventSource`s read the stream
+    if let Some(origin) = req.headers().get(ORIGIN) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+    }
+    res
+}
+
+fn events(
+    ctx: ServerContext,
+    kind: WsKind,
+    format: Format,
+    last_event_id: Option<u64>,
+) -> impl futures_util::Stream<Item = Event> + Send + 'static {
+    stream::unfold(
+        (ctx, last_event_id),
+        move |(mut ctx, mut last_sent)| async move {
+            loop {
+                let event = {
+                    let snapshot = ctx.snapshots.borrow_and_update().clone();
+                    snapshot
+                        .revision(kind, format)
+                        .filter(|revision| last_sent.is_none_or(|last| *revision > last))
+                        .and_then(|revision| {
+                            Some((revision, snapshot.get_text(kind, format)?.clone()))
+                        })
+                };
+                if let Some((revision, text)) = event {
+                    last_sent = Some(revision);
+                    let frame =
+                        Frame::data(format_event(kind.name(), Some(revision), text.as_str()));
+                    return Some((Ok(frame), (ctx, last_sent)));
+                }
+                tokio::select! {
+                    changed = ctx.snapshots.changed() => {
+                        if changed.is_err() {
+                            return None;
+                        }
+                    }
+                    _ = sleep(KEEPALIVE_INTERVAL) => {
+                        let frame = Frame::data(Bytes::from_static(KEEPALIVE));
+                        return Some((Ok(frame), (ctx, last_sent)));
+                    }
+                    _ = ctx.shutdown.changed() => return None,
+                }
+            }
+        },
+    )
+}
+
+/// Revision of a `Last-Event-ID` sent by this process, ids of a previous one are ignored
+fn parse_event_id(id: &str) -> Option<u64> {
+    let (epoch, revision) = id.trim().split_once('-')?;
+    if epoch.parse::<u64>().ok()? != *EPOCH {
+        return None;
+    }
+    revision.parse().ok()
+}
+
+/// Event with the id of `revision`, if any
+pub(crate) fn format_event(name: &str, revision: Option<u64>, data: &str) -> Bytes {
+    let mut event = String::new();
+    if let Some(revision) = revision {
+        event.push_str(&format!("id: {}-{revision}\n", *EPOCH));
+    }
+    event.push_str(&format!("event: {name}\n"));
+    for line in data.lines() {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+    Bytes::from(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_ids_round_trip() {
+        let event = format_event("user", Some(42), "{}");
+        let event = std::str::from_utf8(&event).unwrap();
+        let id = event.lines().next().unwrap().strip_prefix("id: ").unwrap();
+        assert_eq!(parse_event_id(id), Some(42));
+    }
+
+    #[test]
+    fn ignores_ids_of_other_processes() {
+        assert_eq!(parse_event_id("42"), None);
+        assert_eq!(parse_event_id(&format!("{}-42", *EPOCH - 1)), None);
+        assert_eq!(parse_event_id(&format!("{}-", *EPOCH)), None);
+    }
+}
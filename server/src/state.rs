@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+
+use color_eyre::{Result, eyre::eyre};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use types::{Api, Config, Mode};
+
+use crate::structs::TrackedData;
+
+/// Tracked data as it is written to disk
+#[derive(Serialize, Deserialize)]
+struct Persisted {
+    /// Username from the config this data was fetched for
+    username: String,
+    /// Mode from the config this data was fetched in
+    #[serde(default)]
+    mode: Option<Mode>,
+    data: TrackedData,
+}
+
+/// Location of the persisted tracking sessions. Every user gets their own file next to the
+/// configured one, so switching users and back keeps both sessions
+#[derive(Debug, Clone)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(config: &Config) -> Result<Self> {
+        let path = match &config.state_file {
+            Some(path) => PathBuf::from(path),
+            None => ProjectDirs::from("com", "chiffa", "rosuTracker")
+                .ok_or_else(|| eyre!("Failed to find the data directory"))?
+                .data_dir()
+                .join("state.json"),
+        };
+        Ok(Self { path })
+    }
+
+    /// `state.json` becomes `state-username.json`
+    fn user_path(&self, username: &str) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .map_or("state".into(), |stem| stem.to_string_lossy());
+        let username: String = username
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '-' => c,
+                'A'..='Z' => c.to_ascii_lowercase(),
+                _ => '_',
+            })
+            .collect();
+        let mut path = self.path.with_file_name(format!("{stem}-{username}"));
+        if let Some(extension) = self.path.extension() {
+            path.set_extension(extension);
+        }
+        path
+    }
+
+    /// Load the state saved for the user and mode of `api`, if there is any. Falls back to the
+    /// single file written by older versions
+    pub async fn load(&self, api: &Api) -> Result<Option<TrackedData>> {
+        for path in [self.user_path(&api.username), self.path.clone()] {
+            let bytes = match tokio::fs::read(&path).await {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let persisted: Persisted = serde_json::from_slice(&bytes)?;
+            if !persisted.username.eq_ignore_ascii_case(&api.username) || persisted.mode != api.mode
+            {
+                debug!(
+                    "State in {} belongs to {} in {:?}, ignoring",
+                    path.display(),
+                    persisted.username,
+                    persisted.mode
+                );
+                continue;
+            }
+            return Ok(Some(persisted.data));
+        }
+        Ok(None)
+    }
+
+    /// Atomically replace the state saved for the user of `api`. Nothing is saved while nobody
+    /// is tracked
+    pub async fn save(&self, api: &Api, data: &TrackedData) -> Result<()> {
+        if api.username.is_empty() {
+            return Ok(());
+        }
+        let persisted = Persisted {
+            username: api.username.clone(),
+            mode: api.mode,
+            data: data.clone(),
+        };
+        let bytes = serde_json::to_vec(&persisted)?;
+        let path = self.user_path(&api.username);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        debug!("Saved state to {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn api(username: &str, mode: Option<Mode>) -> Api {
+        Api {
+            username: username.to_owned(),
+            mode,
+            ..Api::default()
+        }
+    }
+
+    #[test]
+    fn one_file_per_user() {
+        let state = StateFile {
+            path: PathBuf::from("/data/state.json"),
+        };
+        assert_eq!(
+            state.user_path("Cookiezi"),
+            PathBuf::from("/data/state-cookiezi.json")
+        );
+        assert_eq!(
+            state.user_path("[Tony] x/y"),
+            PathBuf::from("/data/state-_tony__x_y.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_every_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateFile {
+            path: dir.path().join("state.json"),
+        };
+        let data = TrackedData::new();
+        state.save(&api("a", None), &data).await.unwrap();
+        state.save(&api("b", None), &data).await.unwrap();
+        assert!(state.load(&api("A", None)).await.unwrap().is_some());
+        assert!(state.load(&api("b", None)).await.unwrap().is_some());
+        assert!(
+            state
+                .load(&api("b", Some(Mode::Taiko)))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(state.load(&api("c", None)).await.unwrap().is_none());
+        // Nobody tracked
+        state.save(&api("", None), &data).await.unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    /// The fixtures of the benchmarks still make a valid state file
+    #[tokio::test]
+    async fn fixture_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateFile {
+            path: dir.path().join("state.json"),
+        };
+        let scores = fixtures::scores(3);
+        let mut data = TrackedData::new_with(
+            fixtures::user(),
+            scores.clone(),
+            scores.clone(),
+            scores.clone(),
+            None,
+        );
+        data.user_most_played = Some(fixtures::most_played(3));
+        data.grind.record(&scores);
+        state.save(&api("fixture", None), &data).await.unwrap();
+        let loaded = state.load(&api("fixture", None)).await.unwrap();
+        assert_eq!(loaded, Some(data));
+    }
+}
@@ -1,36 +1,368 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, OnceLock},
+};
 
-use hyper::upgrade::Upgraded;
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::{body::Bytes, upgrade::Upgraded};
 use hyper_util::rt::TokioIo;
-use rosu_v2::prelude::{Score, UserExtended};
-use tokio::sync::Mutex;
-use tokio_tungstenite::WebSocketStream;
+use rosu_v2::prelude::{MostPlayedMap, Score, UserExtended};
+use serde::Serialize;
+use tokio::sync::{Mutex, mpsc, watch};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{Message, Utf8Bytes},
+};
+use types::{
+    Config,
+    payload::{
+        ComparePayload, GrindPayload, MostPlayedPayload, MostPlayedSummary, Payload as Versioned,
+        Profile, ScoreSummary, ScoresPayload, SessionSummary, UserPayload,
+    },
+};
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+use crate::{
+    compare::{self, ComparedUser},
+    control,
+    deflate::Inflate,
+    grind::Grind,
+    oauth::OAuth,
+    overlay::Overlays,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 #[repr(u8)]
 pub enum WsKind {
     User,
     Tops,
     Firsts,
     Recent,
+    Pinned,
+    MostPlayed,
+    Grind,
+    Compare,
 }
-#[derive(Debug)]
-pub struct WsClient {
-    pub kind: WsKind,
-    pub client: WebSocketStream<TokioIo<Upgraded>>,
+
+impl WsKind {
+    pub const ALL: [WsKind; 8] = [
+        WsKind::User,
+        WsKind::Tops,
+        WsKind::Firsts,
+        WsKind::Recent,
+        WsKind::Pinned,
+        WsKind::MostPlayed,
+        WsKind::Grind,
+        WsKind::Compare,
+    ];
+
+    /// Channel name, as used in access token permissions
+    pub fn name(&self) -> &'static str {
+        match self {
+            WsKind::User => "user",
+            WsKind::Tops => "tops",
+            WsKind::Firsts => "firsts",
+            WsKind::Recent => "recent",
+            WsKind::Pinned => "pinned",
+            WsKind::MostPlayed => "most-played",
+            WsKind::Grind => "grind",
+            WsKind::Compare => "compare",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// How a channel's payloads are serialized
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Format {
+    /// Versioned payloads from `types::payload`, described at `/schema`
+    #[default]
+    Versioned,
+    /// The versioned payloads as MessagePack, sent in binary messages
+    MessagePack,
+    /// rosu-v2 types as they are, for the bundled GUI. These change with rosu-v2 updates
+    Raw,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Versioned, Format::MessagePack, Format::Raw];
+
+    /// `?format=msgpack` or `?format=raw`, the versioned JSON payloads otherwise
+    pub fn from_query(query: Option<&str>) -> Self {
+        let format = query.and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("format="))
+        });
+        match format {
+            Some("msgpack") => Format::MessagePack,
+            Some("raw") => Format::Raw,
+            _ => Format::Versioned,
+        }
+    }
+
+    /// Format selected by a negotiated websocket subprotocol
+    pub fn from_protocol(protocol: &str) -> Option<Self> {
+        match protocol {
+            "rosu-tracker.json" => Some(Format::Versioned),
+            "rosu-tracker.msgpack" => Some(Format::MessagePack),
+            _ => None,
+        }
+    }
 }
 
+/// Clients connected over a websocket, see `deflate::Inflate`
+pub(crate) type WsStream = WebSocketStream<Inflate<TokioIo<Upgraded>>>;
+
+/// Body of every server response, either complete or streamed
+pub type ResponseBody = UnsyncBoxBody<Bytes, Infallible>;
+
 pub type Arm<T> = Arc<Mutex<T>>;
 // pub type ArRwlock<T> = Arc<RwLock<T>>;
-pub type Clients = Arm<Vec<WsClient>>;
-#[derive(Clone, Debug, PartialEq)]
+
+/// Tracked data as published to clients, serialized on first use and shared by all of them
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// Increases with every published snapshot
+    id: u64,
+    /// `None` until the tracked user is fetched
+    data: Option<Arc<TrackedData>>,
+    payloads: HashMap<(WsKind, Format), Payload>,
+}
+
+#[derive(Debug, Default)]
+struct Payload {
+    /// Id of the snapshot this payload last changed in
+    revision: u64,
+    /// `None` once serializing failed
+    message: OnceLock<Option<Message>>,
+}
+
+impl Snapshot {
+    /// Wrap `data` without serializing anything. The snapshot is empty until the tracked user is
+    /// fetched
+    pub fn new(data: TrackedData) -> Self {
+        if data.user_extended.is_none() {
+            return Self::default();
+        }
+        let payloads = WsKind::ALL
+            .into_iter()
+            .flat_map(|kind| Format::ALL.map(|format| (kind, format)))
+            .map(|key| (key, Payload::default()))
+            .collect();
+        Self {
+            id: 0,
+            data: Some(Arc::new(data)),
+            payloads,
+        }
+    }
+
+    /// Number this snapshot as the one following `previous`, keeping the serialized payloads of
+    /// unchanged channels. Returns `false` if no channel changed
+    pub fn succeed(&mut self, previous: &Snapshot) -> bool {
+        self.id = previous.id + 1;
+        let mut changed = self.payloads.len() != previous.payloads.len();
+        for (&(kind, format), payload) in self.payloads.iter_mut() {
+            let unchanged = previous.payloads.get(&(kind, format)).filter(|_| {
+                self.data
+                    .as_deref()
+                    .zip(previous.data.as_deref())
+                    .is_some_and(|(data, old)| same_payload(kind, old, data))
+            });
+            match unchanged {
+                Some(old) => {
+                    payload.revision = old.revision;
+                    if let Some(message) = old.message.get() {
+                        payload.message = OnceLock::from(message.clone());
+                    }
+                }
+                None => {
+                    payload.revision = self.id;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Payload of `kind` in `format`, serialized by the first client asking for it
+    pub fn get(&self, kind: WsKind, format: Format) -> Option<&Message> {
+        let data = self.data.as_deref()?;
+        self.payloads
+            .get(&(kind, format))?
+            .message
+            .get_or_init(|| {
+                encode(kind, format, data)
+                    .inspect_err(|e| {
+                        tracing::error!("Failed to serialize {:?} as {:?}: {e}", kind, format)
+                    })
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// Payload of a text format
+    pub fn get_text(&self, kind: WsKind, format: Format) -> Option<&Utf8Bytes> {
+        match self.get(kind, format)? {
+            Message::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Id of the snapshot the payload of `kind` last changed in
+    pub fn revision(&self, kind: WsKind, format: Format) -> Option<u64> {
+        self.payloads
+            .get(&(kind, format))
+            .map(|payload| payload.revision)
+    }
+}
+
+/// Whether the payloads of `kind` are built from the same data
+fn same_payload(kind: WsKind, old: &TrackedData, new: &TrackedData) -> bool {
+    match kind {
+        WsKind::User => old.user_extended == new.user_extended && old.session == new.session,
+        WsKind::Tops => old.user_scores == new.user_scores,
+        WsKind::Firsts => old.user_firsts == new.user_firsts,
+        WsKind::Recent => old.user_recent == new.user_recent,
+        WsKind::Pinned => old.user_pinned == new.user_pinned,
+        WsKind::MostPlayed => old.user_most_played == new.user_most_played,
+        WsKind::Grind => old.grind == new.grind,
+        WsKind::Compare => {
+            old.compared == new.compared
+                && old.user_extended == new.user_extended
+                && old.user_scores == new.user_scores
+                && old.session == new.session
+        }
+    }
+}
+
+/// Serialize the payload of `kind` in `format`
+pub fn encode(kind: WsKind, format: Format, data: &TrackedData) -> color_eyre::Result<Message> {
+    let message = match format {
+        Format::Versioned => Message::Text(serde_json::to_string(&versioned(kind, data))?.into()),
+        Format::MessagePack => {
+            Message::Binary(rmp_serde::to_vec_named(&versioned(kind, data))?.into())
+        }
+        Format::Raw => Message::Text(serialize_raw(kind, data)?.into()),
+    };
+    Ok(message)
+}
+
+fn serialize_raw(kind: WsKind, data: &TrackedData) -> serde_json::Result<String> {
+    match kind {
+        WsKind::User => serde_json::to_string(&data.user_extended),
+        WsKind::Tops => serde_json::to_string(&data.user_scores),
+        WsKind::Firsts => serde_json::to_string(&data.user_firsts),
+        WsKind::Recent => serde_json::to_string(&data.user_recent),
+        WsKind::Pinned => serde_json::to_string(&data.user_pinned),
+        WsKind::MostPlayed => serde_json::to_string(&data.user_most_played),
+        // Not rosu-v2 types, the same as the versioned data
+        WsKind::Grind => serde_json::to_string(&data.grind.summary()),
+        WsKind::Compare => serde_json::to_string(&compare::summary(data)),
+    }
+}
+
+/// Versioned payload of any channel
+#[derive(Serialize)]
+#[serde(untagged)]
+enum VersionedPayload {
+    User(Versioned<Option<UserPayload>>),
+    Scores(Versioned<ScoresPayload>),
+    MostPlayed(Versioned<MostPlayedPayload>),
+    Grind(Versioned<GrindPayload>),
+    Compare(Versioned<ComparePayload>),
+}
+
+fn versioned(kind: WsKind, data: &TrackedData) -> VersionedPayload {
+    let scores = |scores: &Option<Vec<Score>>| {
+        let scores = scores.iter().flatten().map(ScoreSummary::from).collect();
+        VersionedPayload::Scores(Versioned::new(scores))
+    };
+    match kind {
+        WsKind::User => {
+            let user = data.user_extended.as_ref().map(|user| UserPayload {
+                profile: Profile::from(user),
+                session: data.session.as_ref().map(|session| SessionSummary {
+                    started_at: session.started_at,
+                    initial: Profile::from(&session.user_extended),
+                }),
+            });
+            VersionedPayload::User(Versioned::new(user))
+        }
+        WsKind::Tops => scores(&data.user_scores),
+        WsKind::Firsts => scores(&data.user_firsts),
+        WsKind::Recent => scores(&data.user_recent),
+        WsKind::Pinned => scores(&data.user_pinned),
+        WsKind::MostPlayed => {
+            let most_played = data.user_most_played.iter().flatten();
+            VersionedPayload::MostPlayed(Versioned::new(
+                most_played.map(MostPlayedSummary::from).collect(),
+            ))
+        }
+        WsKind::Grind => VersionedPayload::Grind(Versioned::new(data.grind.summary())),
+        WsKind::Compare => VersionedPayload::Compare(Versioned::new(compare::summary(data))),
+    }
+}
+
+/// State shared by every request handler
+#[derive(Clone)]
+pub struct ServerContext {
+    pub values: Arm<TrackedData>,
+    pub snapshots: watch::Receiver<Arc<Snapshot>>,
+    pub overlays: watch::Receiver<Arc<Overlays>>,
+    pub config: watch::Receiver<Config>,
+    pub control: control::Handle,
+    pub oauth: OAuth,
+    pub shutdown: watch::Receiver<bool>,
+    /// Upgraded by every websocket client task, so shutdown can wait for all of them to close.
+    /// Weak so idle connections don't hold up shutdown
+    pub alive: mpsc::WeakSender<()>,
+}
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TrackedData {
     pub user_extended: Option<UserExtended>,
     pub user_scores: Option<Vec<Score>>,
     pub user_firsts: Option<Vec<Score>>,
     pub user_recent: Option<Vec<Score>>,
+    /// Fetched less often than the rest, see `SLOW_FETCH_INTERVAL`
+    pub user_pinned: Option<Vec<Score>>,
+    pub user_most_played: Option<Vec<MostPlayedMap>>,
+    /// Retries of the beatmap played last, missing in state saved before it was tracked
+    #[serde(default)]
+    pub grind: Grind,
+    /// Players from `compare_users`, without the tracked user
+    #[serde(default)]
+    pub compared: Vec<ComparedUser>,
+    #[serde(skip)]
     pub score_rank: Option<RespektiveUser>,
     // TODO: Add scorefarm api
+    /// State of the user when the tracking session started
+    pub session: Option<Session>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// Unix timestamp, in seconds
+    pub started_at: u64,
+    pub user_extended: UserExtended,
+    pub user_scores: Option<Vec<Score>>,
+}
+
+impl Session {
+    pub fn start(user_extended: UserExtended, user_scores: Option<Vec<Score>>) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            started_at,
+            user_extended,
+            user_scores,
+        }
+    }
 }
 
 impl TrackedData {
@@ -40,7 +372,12 @@ impl TrackedData {
             user_scores: None,
             user_firsts: None,
             user_recent: None,
+            user_pinned: None,
+            user_most_played: None,
+            grind: Grind::default(),
+            compared: Vec::new(),
             score_rank: None,
+            session: None,
         }
     }
     #[allow(dead_code)]
@@ -56,7 +393,12 @@ impl TrackedData {
             user_scores: user_scores.into(),
             user_firsts: user_firsts.into(),
             user_recent: user_recent.into(),
+            user_pinned: None,
+            user_most_played: None,
+            grind: Grind::default(),
+            compared: Vec::new(),
             score_rank: score_rank.into(),
+            session: None,
         }
     }
     pub fn insert(
@@ -70,10 +412,19 @@ impl TrackedData {
         self.user_scores = user_scores.into();
         self.user_firsts = user_firsts.into();
         self.user_recent = user_recent.into();
+        if self.session.is_none() {
+            if let Some(user) = &self.user_extended {
+                self.session = Some(Session::start(user.clone(), self.user_scores.clone()));
+            }
+        }
     }
 }
 
-use std::{num::NonZeroU32, vec::IntoIter};
+use std::{
+    num::NonZeroU32,
+    time::{SystemTime, UNIX_EPOCH},
+    vec::IntoIter,
+};
 
 use serde::{Deserialize, Deserializer};
 
@@ -0,0 +1,443 @@
+//! Tiny template language shared by the file sinks and overlays.
+//!
+//! Placeholders look like `{name}` or `{name:spec}`, `{{` and `}}` are literal braces.
+//! Overlays use [`Syntax::Double`] instead, i.e. `{{name:spec}}` with single braces left alone,
+//! so CSS and scripts don't need escaping. The spec is any combination of `+` (always show the
+//! sign), `,` (group thousands) and `.N` (N decimal places), e.g. `{pp:,.2}` or
+//! `{session_pp_gain:+.1}`
+
+use std::collections::HashMap;
+
+use color_eyre::{Result, eyre::eyre};
+use rosu_v2::prelude::{Score, UserExtended};
+use types::payload::GrindSummary;
+
+use crate::structs::TrackedData;
+
+/// Decimal places of floats without an explicit precision
+const DEFAULT_PRECISION: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// Values available to templates, by name
+pub type Variables = HashMap<&'static str, Value>;
+
+/// How placeholders are delimited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    /// `{name}`, with `{{` and `}}` as literal braces
+    Single,
+    /// `{{name}}`, everything else is literal
+    Double,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder { name: String, spec: Spec },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Spec {
+    sign: bool,
+    group: bool,
+    precision: Option<usize>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self> {
+        Self::parse_with(source, Syntax::Single)
+    }
+
+    pub fn parse_with(source: &str, syntax: Syntax) -> Result<Self> {
+        let parts = match syntax {
+            Syntax::Single => parse_single(source)?,
+            Syntax::Double => parse_double(source)?,
+        };
+        Ok(Self { parts })
+    }
+
+    /// Render the template. Unknown or missing values render as nothing
+    pub fn render(&self, variables: &Variables) -> String {
+        self.render_inner(variables, false)
+    }
+
+    /// Render the template with values escaped for HTML
+    pub fn render_html(&self, variables: &Variables) -> String {
+        self.render_inner(variables, true)
+    }
+
+    fn render_inner(&self, variables: &Variables, html: bool) -> String {
+        let mut output = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => output.push_str(text),
+                Part::Placeholder { name, spec } => {
+                    let Some(value) = variables.get(name.as_str()) else {
+                        continue;
+                    };
+                    let formatted = format_value(value, spec);
+                    if html {
+                        escape_html(&mut output, &formatted);
+                    } else {
+                        output.push_str(&formatted);
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+fn parse_single(source: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '{' if chars.next_if(|(_, c)| *c == '{').is_some() => literal.push('{'),
+            '}' if chars.next_if(|(_, c)| *c == '}').is_some() => literal.push('}'),
+            '{' => {
+                let end = source[start..]
+                    .find('}')
+                    .map(|end| start + end)
+                    .ok_or_else(|| eyre!("Unclosed placeholder at byte {start}"))?;
+                let placeholder = &source[start + 1..end];
+                while chars.next_if(|(i, _)| *i <= end).is_some() {}
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(parse_placeholder(placeholder)?);
+            }
+            '}' => {
+                return Err(eyre!(
+                    "Unmatched }} at byte {start}, use }}}} for a literal brace"
+                ));
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+fn parse_double(source: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut rest = source;
+    let mut offset = 0;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .map(|end| start + end)
+            .ok_or_else(|| eyre!("Unclosed placeholder at byte {}", offset + start))?;
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_owned()));
+        }
+        parts.push(parse_placeholder(&rest[start + 2..end])?);
+        offset += end + 2;
+        rest = &rest[end + 2..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_owned()));
+    }
+    Ok(parts)
+}
+
+fn escape_html(output: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part> {
+    let (name, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(eyre!("Invalid placeholder name {name:?}"));
+    }
+    let mut parsed = Spec::default();
+    let mut rest = spec.trim();
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '+' => parsed.sign = true,
+            ',' => parsed.group = true,
+            '.' => {
+                let digits = rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(&rest[1..], |end| &rest[1..end + 1]);
+                parsed.precision = Some(
+                    digits
+                        .parse()
+                        .map_err(|_| eyre!("Invalid precision in {{{placeholder}}}"))?,
+                );
+                rest = &rest[1 + digits.len()..];
+                continue;
+            }
+            _ => return Err(eyre!("Invalid format {spec:?} in {{{placeholder}}}")),
+        }
+        rest = &rest[1..];
+    }
+    Ok(Part::Placeholder {
+        name: name.to_owned(),
+        spec: parsed,
+    })
+}
+
+fn format_value(value: &Value, spec: &Spec) -> String {
+    let (formatted, negative) = match value {
+        Value::Text(text) => return text.clone(),
+        Value::Int(int) if spec.precision.is_none() => (int.unsigned_abs().to_string(), *int < 0),
+        Value::Int(int) => {
+            let precision = spec.precision.unwrap_or_default();
+            (
+                format!("{:.*}", precision, int.unsigned_abs() as f64),
+                *int < 0,
+            )
+        }
+        Value::Float(float) => {
+            let precision = spec.precision.unwrap_or(DEFAULT_PRECISION);
+            let formatted = format!("{:.*}", precision, float.abs());
+            // Don't render -0.00
+            let negative = *float < 0.0 && formatted.bytes().any(|b| matches!(b, b'1'..=b'9'));
+            (formatted, negative)
+        }
+    };
+    let (integer, fraction) = match formatted.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (formatted.as_str(), None),
+    };
+    let mut output = String::new();
+    if negative {
+        output.push('-');
+    } else if spec.sign {
+        output.push('+');
+    }
+    if spec.group {
+        output.push_str(&group_thousands(integer));
+    } else {
+        output.push_str(integer);
+    }
+    if let Some(fraction) = fraction {
+        output.push('.');
+        output.push_str(fraction);
+    }
+    output
+}
+
+fn group_thousands(digits: &str) -> String {
+    digits
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Every value templates can use. Session values are relative to the session start
+pub fn variables(data: &TrackedData) -> Variables {
+    let mut vars = Variables::new();
+    let Some(user) = &data.user_extended else {
+        return vars;
+    };
+    user_variables(&mut vars, user);
+
+    let initial = data.session.as_ref().map(|session| &session.user_extended);
+    if let (Some(current), Some(initial)) = (
+        user.statistics.as_ref(),
+        initial.and_then(|user| user.statistics.as_ref()),
+    ) {
+        vars.insert(
+            "session_pp_gain",
+            Value::Float((current.pp - initial.pp) as f64),
+        );
+        vars.insert(
+            "session_accuracy_gain",
+            Value::Float((current.accuracy - initial.accuracy) as f64),
+        );
+        vars.insert(
+            "session_playcount",
+            Value::Int(current.playcount as i64 - initial.playcount as i64),
+        );
+        vars.insert(
+            "session_ranked_score_gain",
+            Value::Int(current.ranked_score as i64 - initial.ranked_score as i64),
+        );
+        if let (Some(current), Some(initial)) = (current.global_rank, initial.global_rank) {
+            // Positive when climbing
+            vars.insert(
+                "session_rank_gain",
+                Value::Int(initial as i64 - current as i64),
+            );
+        }
+    }
+
+    if let Some(top) = data.user_scores.as_ref().and_then(|scores| scores.first()) {
+        vars.insert("top_pp", Value::Float(top.pp.unwrap_or_default() as f64));
+    }
+    if let Some(recent) = data.user_recent.as_ref().and_then(|scores| scores.first()) {
+        score_variables(&mut vars, recent);
+    }
+    if let Some(grind) = data.grind.summary() {
+        grind_variables(&mut vars, &grind);
+    }
+    vars
+}
+
+fn user_variables(vars: &mut Variables, user: &UserExtended) {
+    vars.insert("username", Value::Text(user.username.to_string()));
+    if let Some(peak) = &user.highest_rank {
+        vars.insert("peak_rank", Value::Int(peak.rank as i64));
+    }
+    let Some(statistics) = &user.statistics else {
+        return;
+    };
+    vars.insert("pp", Value::Float(statistics.pp as f64));
+    vars.insert("accuracy", Value::Float(statistics.accuracy as f64));
+    vars.insert("playcount", Value::Int(statistics.playcount as i64));
+    vars.insert("ranked_score", Value::Int(statistics.ranked_score as i64));
+    vars.insert("level", Value::Int(statistics.level.current as i64));
+    if let Some(rank) = statistics.global_rank {
+        vars.insert("global_rank", Value::Int(rank as i64));
+    }
+    if let Some(rank) = statistics.country_rank {
+        vars.insert("country_rank", Value::Int(rank as i64));
+    }
+}
+
+fn score_variables(vars: &mut Variables, score: &Score) {
+    vars.insert(
+        "recent_pp",
+        Value::Float(score.pp.unwrap_or_default() as f64),
+    );
+    vars.insert("recent_accuracy", Value::Float(score.accuracy as f64));
+    vars.insert("recent_combo", Value::Int(score.max_combo as i64));
+    vars.insert("recent_grade", Value::Text(format!("{:?}", score.grade)));
+    if let Some(mapset) = &score.mapset {
+        vars.insert("recent_title", Value::Text(mapset.title.clone()));
+        vars.insert("recent_artist", Value::Text(mapset.artist.clone()));
+    }
+    if let Some(map) = &score.map {
+        vars.insert("recent_version", Value::Text(map.version.clone()));
+    }
+}
+
+fn grind_variables(vars: &mut Variables, grind: &GrindSummary) {
+    vars.insert("grind_attempts", Value::Int(grind.attempts as i64));
+    vars.insert("grind_retries", Value::Int(grind.retries as i64));
+    vars.insert("grind_passes", Value::Int(grind.passes as i64));
+    vars.insert("grind_best_combo", Value::Int(grind.best_combo as i64));
+    if let Some(accuracy) = grind.best_accuracy {
+        vars.insert("grind_best_accuracy", Value::Float(accuracy as f64));
+    }
+    if let Some(pp) = grind.best_pp {
+        vars.insert("grind_best_pp", Value::Float(pp as f64));
+    }
+    if let Some(completion) = grind.best_completion {
+        vars.insert("grind_best_completion", Value::Float(completion as f64));
+    }
+    if let Some(trend) = grind.trend {
+        vars.insert("grind_trend", Value::Float(trend as f64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str) -> String {
+        let variables = Variables::from([
+            ("username", Value::Text("<chiffa>".to_owned())),
+            ("pp", Value::Float(12345.678)),
+            ("rank", Value::Int(1234567)),
+            ("gain", Value::Float(-0.001)),
+            ("loss", Value::Int(-3)),
+        ]);
+        Template::parse(source).unwrap().render(&variables)
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(render("{username}: {pp}pp"), "<chiffa>: 12345.68pp");
+        assert_eq!(render("#{ rank }"), "#1234567");
+        assert_eq!(render("no placeholders"), "no placeholders");
+    }
+
+    #[test]
+    fn renders_escaped_braces() {
+        assert_eq!(render("{{username}} }}"), "{username} }");
+        assert_eq!(render("{{{rank}}}"), "{1234567}");
+    }
+
+    #[test]
+    fn renders_unknown_keys_as_nothing() {
+        assert_eq!(render("a{missing}b{missing:+.2}c"), "abc");
+    }
+
+    #[test]
+    fn rejects_unterminated_braces() {
+        assert!(Template::parse("{pp").is_err());
+        assert!(Template::parse("pp}").is_err());
+        assert!(Template::parse_with("{{pp", Syntax::Double).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_placeholders() {
+        assert!(Template::parse("{}").is_err());
+        assert!(Template::parse("{bad-name}").is_err());
+        assert!(Template::parse("{pp:x}").is_err());
+        assert!(Template::parse("{pp:.}").is_err());
+    }
+
+    #[test]
+    fn formats_numbers() {
+        assert_eq!(render("{pp:.1}"), "12345.7");
+        assert_eq!(render("{pp:.0}"), "12346");
+        assert_eq!(render("{pp:+,.2}"), "+12,345.68");
+        assert_eq!(render("{rank:,}"), "1,234,567");
+        assert_eq!(render("{rank:.2}"), "1234567.00");
+        assert_eq!(render("{loss:+}"), "-3");
+        assert_eq!(render("{loss:+.1}"), "-3.0");
+        // Rounds to zero without a sign
+        assert_eq!(render("{gain}"), "0.00");
+        assert_eq!(render("{gain:+}"), "+0.00");
+        assert_eq!(render("{username:+,.2}"), "<chiffa>");
+    }
+
+    #[test]
+    fn double_syntax_leaves_single_braces_alone() {
+        let template =
+            Template::parse_with("a {b} {{ rank:, }} {{pp:.1}}", Syntax::Double).unwrap();
+        let variables = Variables::from([("rank", Value::Int(1000)), ("pp", Value::Float(1.25))]);
+        assert_eq!(template.render(&variables), "a {b} 1,000 1.2");
+    }
+
+    #[test]
+    fn escapes_html() {
+        let template = Template::parse_with("<b>{{username}}</b>", Syntax::Double).unwrap();
+        let variables = Variables::from([("username", Value::Text("<a & 'b'>".to_owned()))]);
+        assert_eq!(
+            template.render_html(&variables),
+            "<b>&lt;a &amp; &#39;b&#39;&gt;</b>"
+        );
+    }
+}
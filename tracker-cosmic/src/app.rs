@@ -1,29 +1,40 @@
+use crate::charts::{ChartRange, draw_charts};
+use crate::compare::draw_compare;
 use crate::config::Config as AppConfig;
-use crate::config::get_config_cosmic;
-use crate::image_fetch::fetch_multiple;
+use crate::config::{get_config_cosmic, take_secret};
+use crate::history::{self, History, HistoryFile, Sample};
+use crate::image_fetch::{ImageKey, ImageQueue, mapset_id, profile_images};
+use crate::score_detail::{Breakdown, ScoreDetail, calculate, draw_score_detail};
+use crate::score_filter::{FilterMessage, ScoreFilter};
+use crate::settings::{SettingsForm, SettingsMessage, draw_settings};
 use crate::socket;
+use crate::top_changes::{TopChanges, draw_top_changes};
 use cosmic::app::{Core, Task, context_drawer};
 use cosmic::cosmic_config::{self, Config, CosmicConfigEntry};
 use cosmic::iced::advanced::widget::{self};
-use cosmic::iced::alignment::{Horizontal, Vertical};
-use cosmic::iced::wgpu::naga::FastHashMap;
-use cosmic::iced::{Alignment, Length, Padding, Subscription};
+use cosmic::iced::{Alignment, Padding, Subscription};
+use cosmic::iced_widget::image::Handle;
 use cosmic::iced_widget::{column, row};
 use cosmic::prelude::CollectionWidget;
 use cosmic::theme::Container;
 use cosmic::widget::button::link;
 use cosmic::widget::text;
-use cosmic::widget::text::{title1, title3};
-use cosmic::widget::{container, icon, menu, nav_bar, scrollable, vertical_space};
-use cosmic::{Application, ApplicationExt, Apply, Element, Theme, cosmic_theme, theme};
-use image::DynamicImage;
-use rosu_v2::prelude::{Score, UserExtended};
-use server::setup::thread_init;
-use std::collections::HashMap;
+use cosmic::widget::text::title3;
+use cosmic::widget::{icon, menu, nav_bar, scrollable, vertical_space};
+use cosmic::{Application, ApplicationExt, Element, Theme, cosmic_theme, theme};
+use rosu_v2::prelude::{MostPlayedMap, Score, UserExtended};
+use server::control::{self, Command};
+use server::setup::{ConfigSource, thread_init};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use tracing::{debug, error};
-use types::Either;
+use types::payload::CompareSummary;
+use utils::{BeatmapFiles, ImageCache};
 
-use super::components::{draw_scores, draw_user};
+use super::components::{
+    draw_controls, draw_most_played, draw_notices, draw_placeholder, draw_recent_scores,
+    draw_scores, draw_user, format_number,
+};
 use super::socket::{Event, Message};
 
 /// The application model stores app-specific state used to describe its interface and
@@ -39,12 +50,18 @@ pub struct AppModel {
     /// Key bindings for the application's menu bar.
     key_binds: HashMap<menu::KeyBind, MenuAction>,
     // Configuration data that persists between application runs.
-    #[allow(
-        dead_code,
-        reason = "The config isn't editable in runtime yet, but the handler is necessary"
-    )]
     config_handler: Option<Config>,
     config: AppConfig,
+    // The osu! client secret, kept out of `config` in the secret store
+    secret: String,
+    // Unsaved contents of the settings page
+    settings: SettingsForm,
+    // Notices shown above every page until dismissed, oldest first
+    notices: VecDeque<String>,
+    // Handle to the tracker running in the background
+    tracker: Option<control::Handle>,
+    // Contents of the "track another user" input
+    track_input: String,
     // State of the websocket connection
     state: State,
     // Latest received user data
@@ -52,14 +69,33 @@ pub struct AppModel {
     user_tops: Option<Vec<Score>>,
     user_firsts: Option<Vec<Score>>,
     user_recent: Option<Vec<Score>>,
+    user_pinned: Option<Vec<Score>>,
+    user_most_played: Option<Vec<MostPlayedMap>>,
+    // The tracked user side by side with the players from the settings
+    compare: Option<Box<CompareSummary>>,
     // First received (a.k.a. initial) user data
     initial_user_extended: Option<Box<UserExtended>>,
     initial_user_tops: Option<Vec<Score>>,
     initial_user_firsts: Option<Vec<Score>>,
-    // In-memory background cover cache
-    backgrounds: FastHashMap<u32, Option<DynamicImage>>,
+    // Score covers, avatars and flags
+    images: ImageQueue,
+    // Filters and sort order of the score pages
+    score_filter: ScoreFilter,
+    // Score opened from one of the score pages
+    score_detail: Option<ScoreDetail>,
+    // .osu files for the score breakdown
+    beatmaps: Option<Arc<BeatmapFiles>>,
+    // When the first user data of the session was received, as a unix timestamp
+    session_started: Option<i64>,
+    // Stats over time for the charts page
+    history: History,
+    history_file: Option<HistoryFile>,
+    chart_range: ChartRange,
 }
 
+/// Older notices are dropped beyond this
+const MAX_NOTICES: usize = 5;
+
 #[derive(Default)]
 pub enum State {
     #[default]
@@ -76,7 +112,19 @@ pub enum AppMessage {
     // StartServer(Vec<AbortHandle>),
     StartServer,
     ReceiveMessage(Event),
-    ReceiveBackground(u32, Option<DynamicImage>),
+    ReceiveImage(ImageKey, Option<Handle>),
+    Filter(FilterMessage),
+    ShowScore(u64),
+    CloseScore,
+    ReceiveBreakdown(u64, Result<Breakdown, String>),
+    ChartRange(ChartRange),
+    Settings(SettingsMessage),
+    DismissNotice(usize),
+    TrackInput(String),
+    Track,
+    Untrack,
+    ResetSession,
+    Refresh,
 }
 
 /// Create a COSMIC application from the app model
@@ -128,8 +176,32 @@ impl Application for AppModel {
             .data::<Page>(Page::RecentPage)
             // text-html looks like Earth, makes sense for leaderboards, fight me
             .icon(icon::from_name("text-html-symbolic"));
+        nav.insert()
+            .text("Pinned")
+            .data::<Page>(Page::PinnedPage)
+            .icon(icon::from_name("view-pin-symbolic"));
+        nav.insert()
+            .text("Most played")
+            .data::<Page>(Page::MostPlayedPage)
+            .icon(icon::from_name("media-playlist-repeat-symbolic"));
+        nav.insert()
+            .text("Top changes")
+            .data::<Page>(Page::TopChangesPage)
+            .icon(icon::from_name("view-sort-descending-symbolic"));
+        nav.insert()
+            .text("Charts")
+            .data::<Page>(Page::ChartsPage)
+            .icon(icon::from_name("utilities-system-monitor-symbolic"));
+        nav.insert()
+            .text("Compare")
+            .data::<Page>(Page::ComparePage)
+            .icon(icon::from_name("system-users-symbolic"));
+        nav.insert()
+            .text("Settings")
+            .data::<Page>(Page::SettingsPage)
+            .icon(icon::from_name("preferences-system-symbolic"));
 
-        let (config_handler, config) =
+        let (config_handler, mut config) =
             match cosmic_config::Config::new(Self::APP_ID, AppConfig::VERSION) {
                 Ok(config_handler) => {
                     let config = match AppConfig::get_entry(&config_handler) {
@@ -147,6 +219,25 @@ impl Application for AppModel {
                 }
             };
 
+        let secret = config_handler
+            .as_ref()
+            .map(|config_handler| take_secret(config_handler, &mut config))
+            .unwrap_or_default();
+
+        let history_file = HistoryFile::new()
+            .inspect_err(|e| error!("failed to locate history file: {}", e))
+            .ok();
+        let history = history_file
+            .as_ref()
+            .and_then(|file| {
+                file.load()
+                    .inspect_err(|e| error!("failed to load history: {}", e))
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        let (tracker, commands) = control::channel();
+
         // Construct the app model with the runtime's core.
         let mut app = AppModel {
             core,
@@ -155,16 +246,37 @@ impl Application for AppModel {
             key_binds: HashMap::new(),
             // Optional configuration file for an application.
             config_handler,
+            settings: SettingsForm::new(&config, &secret),
             config,
+            secret,
+            tracker: Some(tracker.clone()),
+            images: ImageQueue::new(
+                ImageCache::builder()
+                    .build()
+                    .inspect_err(|e| error!("failed to create image cache: {}", e))
+                    .ok()
+                    .map(Arc::new),
+            ),
+            beatmaps: BeatmapFiles::new()
+                .inspect_err(|e| error!("failed to create beatmap cache: {}", e))
+                .ok()
+                .map(Arc::new),
+            history,
+            history_file,
             ..Default::default()
         };
 
         let rename = app.update_title();
 
         // Create a startup command that starts the socket server.
-        let command = Task::perform(thread_init(Either::Right(get_config_cosmic)), |_| {
-            cosmic::action::Action::App(AppMessage::StartServer)
-        });
+        let command = Task::perform(
+            thread_init(
+                ConfigSource::Loader(get_config_cosmic),
+                tracker.clone(),
+                commands,
+            ),
+            |_| cosmic::action::Action::App(AppMessage::StartServer),
+        );
 
         let batch = Task::batch([rename, command]);
         (app, batch)
@@ -208,13 +320,33 @@ impl Application for AppModel {
     /// Application events will be processed through the view. Any messages emitted by
     /// events received by widgets will be passed to the update method.
     fn view(&self) -> Element<Self::Message> {
-        match self.nav.active_data::<Page>() {
+        let page = match self.nav.active_data::<Page>() {
+            Some(
+                Page::TopsPage
+                | Page::FirstsPage
+                | Page::RecentPage
+                | Page::PinnedPage
+                | Page::TopChangesPage,
+            ) if self.score_detail.is_some() => self.score_detail_view(),
             Some(Page::UserPage) => self.user_view(),
             Some(Page::TopsPage) => self.tops_view(),
             Some(Page::FirstsPage) => self.firsts_view(),
             Some(Page::RecentPage) => self.recent_view(),
-            None => todo!(),
+            Some(Page::PinnedPage) => self.pinned_view(),
+            Some(Page::MostPlayedPage) => self.most_played_view(),
+            Some(Page::TopChangesPage) => self.top_changes_view(),
+            Some(Page::ChartsPage) => self.charts_view(),
+            Some(Page::ComparePage) => self.compare_view(),
+            Some(Page::SettingsPage) => draw_settings(&self.settings, &self.config, &self.secret),
+            None => draw_placeholder("Nothing to show"),
+        };
+        if self.notices.is_empty() {
+            return page;
         }
+        cosmic::widget::column()
+            .push(draw_notices(&self.notices))
+            .push(page)
+            .into()
     }
 
     /// Register subscriptions for this application.
@@ -223,12 +355,24 @@ impl Application for AppModel {
     /// emit messages to the application through a channel. They are started at the
     /// beginning of the application, and persist through its lifetime.
     fn subscription(&self) -> Subscription<Self::Message> {
+        // Keyed by port, so changing it reconnects
+        let port = self.config.port;
         Subscription::batch(vec![
             // Create a subscription which emits updates through a channel.
-            Subscription::run(socket::connect_user).map(AppMessage::ReceiveMessage),
-            Subscription::run(socket::connect_tops).map(AppMessage::ReceiveMessage),
-            Subscription::run(socket::connect_firsts).map(AppMessage::ReceiveMessage),
-            Subscription::run(socket::connect_recent).map(AppMessage::ReceiveMessage),
+            Subscription::run_with_id(("user", port), socket::connect_user(port))
+                .map(AppMessage::ReceiveMessage),
+            Subscription::run_with_id(("tops", port), socket::connect_tops(port))
+                .map(AppMessage::ReceiveMessage),
+            Subscription::run_with_id(("firsts", port), socket::connect_firsts(port))
+                .map(AppMessage::ReceiveMessage),
+            Subscription::run_with_id(("recent", port), socket::connect_recent(port))
+                .map(AppMessage::ReceiveMessage),
+            Subscription::run_with_id(("pinned", port), socket::connect_pinned(port))
+                .map(AppMessage::ReceiveMessage),
+            Subscription::run_with_id(("most-played", port), socket::connect_most_played(port))
+                .map(AppMessage::ReceiveMessage),
+            Subscription::run_with_id(("compare", port), socket::connect_compare(port))
+                .map(AppMessage::ReceiveMessage),
             // Watch for application configuration changes.
             self.core()
                 .watch_config::<AppConfig>(Self::APP_ID)
@@ -264,6 +408,15 @@ impl Application for AppModel {
             }
 
             AppMessage::UpdateConfig(config) => {
+                if self.config != config {
+                    if let Some(tracker) = &self.tracker {
+                        tracker.send(Command::Reload);
+                    }
+                    // Keep unsaved edits, show the new config otherwise
+                    if !self.settings.is_dirty(&self.config, &self.secret) {
+                        self.settings = SettingsForm::new(&config, &self.secret);
+                    }
+                }
                 self.config = config;
             }
 
@@ -287,41 +440,143 @@ impl Application for AppModel {
                     Message::Disconnected => {}
                     Message::User(user_extended) => {
                         debug!("User received: {}", user_extended.username);
+                        let switched = self
+                            .initial_user_extended
+                            .as_ref()
+                            .is_some_and(|initial| initial.user_id != user_extended.user_id);
+                        if switched {
+                            self.reset_session();
+                        } else if self.config.notify_rank {
+                            let rank = |user: &UserExtended| {
+                                user.statistics.as_ref().and_then(|stats| stats.global_rank)
+                            };
+                            let changed = self
+                                .user_extended
+                                .as_deref()
+                                .and_then(rank)
+                                .zip(rank(&user_extended))
+                                .filter(|(previous, current)| previous != current);
+                            if let Some((previous, current)) = changed {
+                                self.notify(format!(
+                                    "Global rank #{} \u{2192} #{}",
+                                    format_number(previous),
+                                    format_number(current)
+                                ));
+                            }
+                        }
+                        let images = self.images.request(profile_images(&user_extended));
+                        let now = history::now();
+                        let saved = self.record_history(&user_extended, now);
                         self.user_extended = Some(user_extended.clone());
                         if self.initial_user_extended.is_none() {
                             self.initial_user_extended = Some(user_extended);
+                            self.session_started = Some(now);
                         }
+                        return Task::batch([images, saved]);
                     }
                     Message::Tops(vec) => {
                         debug!("Top plays received: {}", vec.len());
+                        if self.config.notify_top_plays {
+                            for notice in new_top_plays(self.user_tops.as_deref(), &vec) {
+                                self.notify(notice);
+                            }
+                        }
+                        let covers = self
+                            .images
+                            .request(vec.iter().filter_map(mapset_id).map(ImageKey::Cover));
                         self.user_tops = Some(vec.clone());
-                        let ids: Box<[u32]> = vec
-                            .iter()
-                            .map(|map| map.mapset.as_ref().unwrap().mapset_id)
-                            .filter(|id| self.backgrounds.contains_key(id))
-                            .collect();
                         if self.initial_user_tops.is_none() {
                             self.initial_user_tops = Some(vec);
                         }
-                        let stream = fetch_multiple(ids);
-                        return Task::stream(stream);
+                        return covers;
                     }
                     Message::Firsts(vec) => {
                         debug!("Firsts received: {}", vec.len());
+                        let covers = self
+                            .images
+                            .request(vec.iter().filter_map(mapset_id).map(ImageKey::Cover));
                         self.user_firsts = Some(vec.clone());
                         if self.initial_user_firsts.is_none() {
                             self.initial_user_firsts = Some(vec);
                         }
+                        return covers;
                     }
                     Message::Recent(vec) => {
                         debug!("Recent received: {}", vec.len());
+                        let covers = self
+                            .images
+                            .request(vec.iter().filter_map(mapset_id).map(ImageKey::Cover));
                         self.user_recent = Some(vec);
+                        return covers;
+                    }
+                    Message::Pinned(vec) => {
+                        debug!("Pinned received: {}", vec.len());
+                        let covers = self
+                            .images
+                            .request(vec.iter().filter_map(mapset_id).map(ImageKey::Cover));
+                        self.user_pinned = Some(vec);
+                        return covers;
+                    }
+                    Message::MostPlayed(vec) => {
+                        debug!("Most played received: {}", vec.len());
+                        let covers = self.images.request(
+                            vec.iter()
+                                .map(|most_played| ImageKey::Cover(most_played.mapset.mapset_id)),
+                        );
+                        self.user_most_played = Some(vec);
+                        return covers;
+                    }
+                    Message::Compare(summary) => {
+                        debug!(
+                            "Comparison received: {} players",
+                            summary.as_ref().map_or(0, |summary| summary.players.len())
+                        );
+                        self.compare = summary;
                     }
                 },
             },
-            AppMessage::ReceiveBackground(id, image) => {
-                self.backgrounds.insert(id, image);
+            AppMessage::ReceiveImage(key, image) => return self.images.finish(key, image),
+            AppMessage::Filter(message) => self.score_filter.update(message),
+            AppMessage::ShowScore(score_id) => return self.show_score(score_id),
+            AppMessage::CloseScore => self.score_detail = None,
+            AppMessage::ReceiveBreakdown(score_id, breakdown) => {
+                // Ignore breakdowns of scores which were closed in the meantime
+                if let Some(detail) = self
+                    .score_detail
+                    .as_mut()
+                    .filter(|detail| detail.score.id == score_id)
+                {
+                    detail.breakdown = Some(breakdown);
+                }
+            }
+            AppMessage::ChartRange(range) => self.chart_range = range,
+            AppMessage::Settings(SettingsMessage::Save) => self.save_settings(),
+            AppMessage::Settings(SettingsMessage::Revert) => {
+                self.settings = SettingsForm::new(&self.config, &self.secret);
+            }
+            AppMessage::Settings(message) => self.settings.update(message),
+            AppMessage::DismissNotice(index) => {
+                self.notices.remove(index);
+            }
+            AppMessage::TrackInput(input) => self.track_input = input,
+            AppMessage::Track => {
+                let username = self.track_input.trim().to_owned();
+                if !username.is_empty() {
+                    self.track_input.clear();
+                    self.reset_session();
+                    self.send_command(Command::Track(username));
+                }
+            }
+            AppMessage::Untrack => {
+                self.reset_session();
+                self.user_extended = None;
+                self.send_command(Command::Untrack);
+            }
+            AppMessage::ResetSession => {
+                self.reset_session();
+                self.send_command(Command::ResetSession);
             }
+            AppMessage::Refresh => self.send_command(Command::Refresh),
         }
         Task::none()
     }
@@ -330,6 +585,7 @@ impl Application for AppModel {
     fn on_nav_select(&mut self, id: nav_bar::Id) -> Task<Self::Message> {
         // Activate the page in the model.
         self.nav.activate(id);
+        self.score_detail = None;
 
         self.update_title()
     }
@@ -377,51 +633,248 @@ where
             Task::none()
         }
     }
+    /// Forget the session-start data, the next received data becomes the new baseline
+    fn reset_session(&mut self) {
+        self.initial_user_extended = None;
+        self.initial_user_tops = None;
+        self.initial_user_firsts = None;
+        self.session_started = None;
+    }
+    /// Sample the user's stats and save the history if the sample was kept
+    fn record_history(&mut self, user: &UserExtended, now: i64) -> Task<AppMessage> {
+        let Some(sample) = Sample::new(user, now) else {
+            return Task::none();
+        };
+        let (true, Some(file)) = (self.history.push(user.user_id, sample), &self.history_file)
+        else {
+            return Task::none();
+        };
+        let save = file.clone().save(self.history.clone());
+        cosmic::iced::Task::future(async move {
+            if let Err(e) = save.await {
+                error!("failed to save history: {}", e);
+            }
+        })
+        .discard()
+    }
+    /// Write the settings page to the cosmic config and reload the tracker with it
+    fn save_settings(&mut self) {
+        let mut config = match self.settings.to_config() {
+            Ok(config) => config,
+            Err(e) => {
+                self.settings.error = Some(e);
+                return;
+            }
+        };
+        let Some(config_handler) = &self.config_handler else {
+            self.settings.error = Some("The config can't be written".to_owned());
+            return;
+        };
+        let secret = self.settings.secret().to_owned();
+        if let Err(e) = utils::secret::store(&config.user_client, &secret) {
+            error!(
+                "failed to store the client secret, saving it in the config: {}",
+                e
+            );
+            config.user_secret = secret.clone();
+        }
+        if let Err(e) = config.write_entry(config_handler) {
+            error!("failed to write config: {}", e);
+            self.settings.error = Some(format!("Failed to save: {e}"));
+            return;
+        }
+        self.settings.error = None;
+        self.secret = secret;
+        // Set before the config watcher sees the change, so the tracker reloads once
+        self.config = config;
+        self.send_command(Command::Reload);
+    }
+    /// Open the detail page of a score from one of the score pages and calculate its breakdown
+    fn show_score(&mut self, score_id: u64) -> Task<AppMessage> {
+        let score = [
+            &self.user_tops,
+            &self.user_firsts,
+            &self.user_recent,
+            &self.user_pinned,
+        ]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .find(|score| score.id == score_id);
+        let Some(score) = score.cloned().map(Box::new) else {
+            return Task::none();
+        };
+        let Some(beatmaps) = self.beatmaps.clone() else {
+            self.score_detail = Some(ScoreDetail {
+                score,
+                breakdown: Some(Err("Beatmaps can't be downloaded".to_owned())),
+            });
+            return Task::none();
+        };
+        let checksum = score.map.as_ref().and_then(|map| map.checksum.clone());
+        let to_calculate = score.clone();
+        self.score_detail = Some(ScoreDetail {
+            score,
+            breakdown: None,
+        });
+        Task::perform(
+            async move {
+                let osu_file = beatmaps
+                    .get(to_calculate.map_id, checksum.as_deref())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                tokio::task::spawn_blocking(move || calculate(&osu_file, &to_calculate))
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .map_err(|e| e.to_string())
+            },
+            move |breakdown| {
+                cosmic::action::Action::App(AppMessage::ReceiveBreakdown(score_id, breakdown))
+            },
+        )
+    }
+    fn notify(&mut self, notice: String) {
+        if self.notices.len() == MAX_NOTICES {
+            self.notices.pop_front();
+        }
+        self.notices.push_back(notice);
+    }
+    fn send_command(&self, command: Command) {
+        let sent = self
+            .tracker
+            .as_ref()
+            .is_some_and(|tracker| tracker.send(command));
+        if !sent {
+            error!("Tracker isn't running");
+        }
+    }
     fn user_view(&self) -> Element<AppMessage> {
         let user_current = self.user_extended.as_ref();
-        match user_current {
+        let user = match user_current {
             Some(user_inner) => draw_user(
                 user_inner,
                 self.initial_user_extended.as_ref().unwrap_or(user_inner),
+                &self.images,
             ),
-            None => title1("Waiting")
-                .apply(container)
-                .width(Length::Fill)
-                .height(Length::Fill)
-                .align_x(Horizontal::Center)
-                .align_y(Vertical::Center)
-                .into(),
-        }
+            None => draw_placeholder("No data yet"),
+        };
+        cosmic::widget::column()
+            .push(draw_controls(&self.track_input))
+            .push(user)
+            .into()
     }
     fn tops_view(&self) -> Element<AppMessage> {
         if let Some(scores) = &self.user_tops {
-            draw_scores(scores.as_slice(), &self.backgrounds)
+            draw_scores(scores.as_slice(), &self.images, &self.score_filter)
         } else {
-            text("Waiting for scores :D").into()
+            draw_placeholder("No scores yet")
         }
     }
     fn firsts_view(&self) -> Element<AppMessage> {
         if let Some(scores) = &self.user_firsts {
-            draw_scores(scores.as_slice(), &self.backgrounds)
+            draw_scores(scores.as_slice(), &self.images, &self.score_filter)
+        } else {
+            draw_placeholder("No scores yet")
+        }
+    }
+    fn pinned_view(&self) -> Element<AppMessage> {
+        if let Some(scores) = &self.user_pinned {
+            draw_scores(scores.as_slice(), &self.images, &self.score_filter)
         } else {
-            text("Waiting for scores :D").into()
+            draw_placeholder("No scores yet")
+        }
+    }
+    fn most_played_view(&self) -> Element<AppMessage> {
+        if let Some(maps) = &self.user_most_played {
+            draw_most_played(maps.as_slice(), &self.images)
+        } else {
+            draw_placeholder("No beatmaps yet")
+        }
+    }
+    fn top_changes_view(&self) -> Element<AppMessage> {
+        let (Some(initial), Some(current)) = (&self.initial_user_tops, &self.user_tops) else {
+            return draw_placeholder("No scores yet");
+        };
+        match TopChanges::new(initial, current) {
+            Some(changes) => draw_top_changes(&changes),
+            None => draw_placeholder("No scores of the tracked user yet"),
+        }
+    }
+    fn charts_view(&self) -> Element<AppMessage> {
+        if let Some(user) = &self.user_extended {
+            draw_charts(
+                user,
+                &self.history,
+                self.chart_range,
+                self.session_started,
+                history::now(),
+            )
+        } else {
+            draw_placeholder("No data yet")
+        }
+    }
+    fn compare_view(&self) -> Element<AppMessage> {
+        match &self.compare {
+            Some(summary) => draw_compare(summary),
+            None => draw_placeholder(
+                "No players to compare with yet, add their user ids in the settings",
+            ),
+        }
+    }
+    fn score_detail_view(&self) -> Element<AppMessage> {
+        match &self.score_detail {
+            Some(detail) => draw_score_detail(detail, &self.images),
+            None => text("No score selected").into(),
         }
     }
     fn recent_view(&self) -> Element<AppMessage> {
         if let Some(scores) = &self.user_recent {
-            draw_scores(scores.as_slice(), &self.backgrounds)
+            draw_recent_scores(scores.as_slice(), &self.images, &self.score_filter)
         } else {
-            text("Waiting for scores :D").into()
+            draw_placeholder("No scores yet")
         }
     }
 }
 
+/// Notices of scores that weren't in the previous top plays of the same user and mode
+fn new_top_plays(previous: Option<&[Score]>, current: &[Score]) -> Vec<String> {
+    // Nothing to compare against, or everything changed because someone else is tracked now
+    let comparable = previous
+        .and_then(|previous| previous.first())
+        .zip(current.first())
+        .is_some_and(|(old, new)| old.user_id == new.user_id && old.mode == new.mode);
+    let Some(previous) = previous.filter(|_| comparable) else {
+        return Vec::new();
+    };
+    current
+        .iter()
+        .filter(|score| previous.iter().all(|old| old.id != score.id))
+        .map(|score| {
+            let title = score
+                .mapset
+                .as_ref()
+                .map_or("Unknown map", |mapset| mapset.title.as_str());
+            let version = score.map.as_ref().map_or("?", |map| map.version.as_str());
+            format!(
+                "New top play: {title} [{version}], {:.0}pp",
+                score.pp.unwrap_or_default()
+            )
+        })
+        .collect()
+}
+
 /// The page to display in the application.
 pub enum Page {
     UserPage,
     TopsPage,
     FirstsPage,
     RecentPage,
+    PinnedPage,
+    MostPlayedPage,
+    TopChangesPage,
+    ChartsPage,
+    ComparePage,
+    SettingsPage,
 }
 
 /// The context page to display in the context drawer.
@@ -445,3 +898,24 @@ impl menu::action::MenuAction for MenuAction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn notices_new_top_play_without_beatmap() {
+        let old = fixtures::score();
+        let mut new = fixtures::score();
+        new.id += 1;
+        let notices = new_top_plays(Some(&[old.clone()]), &[old, new]);
+        assert_eq!(notices, ["New top play: Unknown map [?], 442pp"]);
+    }
+
+    #[test]
+    fn no_notices_without_previous_top_plays() {
+        assert!(new_top_plays(None, &[fixtures::score()]).is_empty());
+        assert!(new_top_plays(Some(&[]), &[fixtures::score()]).is_empty());
+    }
+}
@@ -0,0 +1,285 @@
+//! Charts of how the tracked user's stats changed over time.
+//!
+//! Samples recorded by the GUI are the main source, for longer ranges the rank history and
+//! monthly play counts sent by osu! fill in what happened before the first sample
+
+use cosmic::iced::{Alignment, Color, Length};
+use cosmic::prelude::CollectionWidget;
+use cosmic::theme::Container;
+use cosmic::widget::text::title3;
+use cosmic::widget::{container, scrollable, text, vertical_space};
+use cosmic::{Element, iced_widget::row};
+use rosu_v2::prelude::UserExtended;
+
+use crate::app::AppMessage;
+use crate::components::{format_accuracy, format_number};
+use crate::history::{DAY, History, Sample};
+
+/// Points are grouped into this many bars
+const BARS: usize = 48;
+const CHART_HEIGHT: f32 = 120.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChartRange {
+    /// Since the session was started or reset
+    #[default]
+    Session,
+    Day,
+    Week,
+    Month,
+}
+
+impl ChartRange {
+    const ALL: [Self; 4] = [Self::Session, Self::Day, Self::Week, Self::Month];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Session => "Session",
+            Self::Day => "Day",
+            Self::Week => "Week",
+            Self::Month => "Month",
+        }
+    }
+
+    /// Start of the range, sessions which didn't start yet cover nothing
+    fn start(self, now: i64, session_started: Option<i64>) -> i64 {
+        match self {
+            Self::Session => session_started.unwrap_or(now),
+            Self::Day => now - DAY,
+            Self::Week => now - 7 * DAY,
+            Self::Month => now - 30 * DAY,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Metric {
+    Rank,
+    Pp,
+    Accuracy,
+    Playcount,
+}
+
+impl Metric {
+    const ALL: [Self; 4] = [Self::Rank, Self::Pp, Self::Accuracy, Self::Playcount];
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Rank => "Global rank",
+            Self::Pp => "pp",
+            Self::Accuracy => "Accuracy",
+            Self::Playcount => "Play count",
+        }
+    }
+
+    fn value(self, sample: &Sample) -> Option<f64> {
+        match self {
+            Self::Rank => sample.global_rank.map(f64::from),
+            Self::Pp => Some(f64::from(sample.pp)),
+            Self::Accuracy => Some(f64::from(sample.accuracy)),
+            Self::Playcount => Some(f64::from(sample.playcount)),
+        }
+    }
+
+    /// Bars of these are flipped, so improving always makes them taller
+    fn lower_is_better(self) -> bool {
+        matches!(self, Self::Rank)
+    }
+
+    fn format(self, value: f64) -> String {
+        match self {
+            Self::Rank | Self::Playcount => format_number(value.abs().round() as u64),
+            Self::Pp => format!("{:.2}", value.abs()),
+            Self::Accuracy => format_accuracy(value.abs() as f32),
+        }
+    }
+
+    /// Points osu! knows about, oldest first
+    fn api_points(self, user: &UserExtended, now: i64) -> Vec<(i64, f64)> {
+        match self {
+            // Daily, the last entry is today
+            Self::Rank => {
+                let history = user.rank_history.as_deref().unwrap_or_default();
+                let days = history.len() as i64;
+                history
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, rank)| **rank != 0)
+                    .map(|(day, rank)| (now - (days - 1 - day as i64) * DAY, f64::from(*rank)))
+                    .collect()
+            }
+            // Plays per month, walked back from the current play count
+            Self::Playcount => {
+                let (Some(statistics), Some(months)) =
+                    (&user.statistics, user.monthly_playcounts.as_deref())
+                else {
+                    return Vec::new();
+                };
+                let mut months = months.iter().collect::<Vec<_>>();
+                months.sort_unstable_by_key(|month| month.start_date);
+                let mut playcount = i64::from(statistics.playcount);
+                let mut points = months
+                    .iter()
+                    .rev()
+                    .map(|month| {
+                        playcount -= i64::from(month.count);
+                        let start = month.start_date.midnight().assume_utc().unix_timestamp();
+                        (start, playcount.max(0) as f64)
+                    })
+                    .collect::<Vec<_>>();
+                points.reverse();
+                points
+            }
+            Self::Pp | Self::Accuracy => Vec::new(),
+        }
+    }
+}
+
+/// Page with a chart per metric and the range to show them for
+pub(crate) fn draw_charts<'a>(
+    user: &'a UserExtended,
+    history: &'a History,
+    range: ChartRange,
+    session_started: Option<i64>,
+    now: i64,
+) -> Element<'a, AppMessage> {
+    let from = range.start(now, session_started);
+    let samples = history.since(user.user_id, from);
+    let ranges: [Element<AppMessage>; 4] = ChartRange::ALL.map(|option| {
+        let button = if option == range {
+            cosmic::widget::button::suggested(option.label())
+        } else {
+            cosmic::widget::button::standard(option.label())
+        };
+        button.on_press(AppMessage::ChartRange(option)).into()
+    });
+    let charts = Metric::ALL.map(|metric| {
+        let mut points = metric
+            .api_points(user, now)
+            .into_iter()
+            .filter(|(at, _)| *at >= from)
+            .collect::<Vec<_>>();
+        let first_sample = samples.first().map_or(now, |sample| sample.at);
+        points.retain(|(at, _)| *at < first_sample);
+        points.extend(
+            samples
+                .iter()
+                .filter_map(|sample| Some((sample.at, metric.value(sample)?))),
+        );
+        draw_chart(metric, &points, from, now)
+    });
+    scrollable(
+        cosmic::widget::column()
+            .spacing(20)
+            .padding(20)
+            .max_width(800)
+            .push(cosmic::widget::row().spacing(10).extend(ranges))
+            .extend(charts),
+    )
+    .into()
+}
+
+fn draw_chart<'a>(
+    metric: Metric,
+    points: &[(i64, f64)],
+    from: i64,
+    to: i64,
+) -> Element<'a, AppMessage> {
+    let summary = match (points.first(), points.last()) {
+        (Some((_, first)), Some((_, last))) => {
+            let prefix = if matches!(metric, Metric::Rank) {
+                "#"
+            } else {
+                ""
+            };
+            let delta = last - first;
+            let sign = if delta < 0.0 { "-" } else { "+" };
+            format!(
+                "{prefix}{} ({sign}{})",
+                metric.format(*last),
+                metric.format(delta)
+            )
+        }
+        _ => "No data for this range".to_owned(),
+    };
+    let bars = bars(points, from, to);
+    let (min, max) = bars
+        .iter()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        });
+    let bars = bars.into_iter().map(|value| {
+        let portion = value.map(|value| {
+            if max <= min {
+                return 50;
+            }
+            let mut share = (value - min) / (max - min);
+            if metric.lower_is_better() {
+                share = 1.0 - share;
+            }
+            5 + (share * 95.0).round() as u16
+        });
+        draw_bar(portion)
+    });
+    let chart = container(
+        cosmic::widget::row()
+            .spacing(2)
+            .extend(bars)
+            .height(Length::Fixed(CHART_HEIGHT)),
+    )
+    .padding(10)
+    .class(Container::Card)
+    .width(Length::Fill);
+    cosmic::widget::column()
+        .spacing(8)
+        .push(
+            row![title3(metric.title()), text(summary)]
+                .spacing(10)
+                .align_y(Alignment::Center),
+        )
+        .push(chart)
+        .into()
+}
+
+/// Bar taking up `portion` percent of the chart's height, or a gap before the first point
+fn draw_bar<'a>(portion: Option<u16>) -> Element<'a, AppMessage> {
+    let Some(portion) = portion else {
+        return vertical_space().width(Length::Fill).into();
+    };
+    let bar = container(vertical_space())
+        .width(Length::Fill)
+        .height(Length::FillPortion(portion))
+        .class(Container::custom(|theme| container::Style {
+            background: Some(Color::from(theme.cosmic().accent.base).into()),
+            ..Default::default()
+        }));
+    cosmic::widget::column()
+        .push_maybe(
+            (portion < 100).then(|| vertical_space().height(Length::FillPortion(100 - portion))),
+        )
+        .push(bar)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+}
+
+/// Latest value at the end of every bar, carried over bars without points
+fn bars(points: &[(i64, f64)], from: i64, to: i64) -> Vec<Option<f64>> {
+    let width = ((to - from) / BARS as i64).max(1);
+    let mut points = points.iter().peekable();
+    let mut last = None;
+    (1..=BARS)
+        .map(|bar| {
+            let end = if bar == BARS {
+                i64::MAX
+            } else {
+                from + width * bar as i64
+            };
+            while let Some((_, value)) = points.next_if(|(at, _)| *at < end) {
+                last = Some(*value);
+            }
+            last
+        })
+        .collect()
+}
@@ -0,0 +1,140 @@
+//! The tracked user side by side with the players from `compare_users`, as the server ranks them
+
+use cosmic::Element;
+use cosmic::iced::{Alignment, Length};
+use cosmic::prelude::CollectionWidget;
+use cosmic::theme::Container;
+use cosmic::widget::text::{title3, title4};
+use cosmic::widget::{container, scrollable, text};
+use types::payload::{CommonBeatmap, CompareSummary, ComparedPlayer};
+
+use crate::app::AppMessage;
+use crate::components::format_accuracy;
+
+pub(crate) fn draw_compare(summary: &CompareSummary) -> Element<AppMessage> {
+    let player = |user_id: u32| {
+        summary
+            .players
+            .iter()
+            .find(|player| player.profile.user_id == user_id)
+    };
+    let statistic = |player: &ComparedPlayer, value: fn(&types::payload::Statistics) -> String| {
+        player
+            .profile
+            .statistics
+            .as_ref()
+            .map_or_else(|| "-".to_owned(), value)
+    };
+    let rankings = &summary.rankings;
+    let leaderboards = cosmic::widget::row()
+        .spacing(10)
+        .push(leaderboard(
+            "pp",
+            rankings.pp.iter().filter_map(|id| player(*id)),
+            |p| statistic(p, |s| format!("{:.2}", s.pp)),
+        ))
+        .push(leaderboard(
+            "Accuracy",
+            rankings.accuracy.iter().filter_map(|id| player(*id)),
+            |p| statistic(p, |s| format_accuracy(s.accuracy)),
+        ))
+        .push(leaderboard(
+            "Session pp",
+            rankings.session_pp_gain.iter().filter_map(|id| player(*id)),
+            |p| format!("{:+.2}", p.session.pp),
+        ))
+        .push(leaderboard(
+            "Session plays",
+            rankings
+                .session_playcount
+                .iter()
+                .filter_map(|id| player(*id)),
+            |p| p.session.playcount.to_string(),
+        ));
+    let common: Element<AppMessage> = if summary.common_beatmaps.is_empty() {
+        text("No beatmaps in the top plays of more than one player").into()
+    } else {
+        cosmic::widget::column()
+            .spacing(10)
+            .extend(summary.common_beatmaps.iter().map(draw_common_beatmap))
+            .into()
+    };
+    scrollable(
+        cosmic::widget::column()
+            .spacing(20)
+            .padding(20)
+            .max_width(800)
+            .push(title3("Compare"))
+            .push(leaderboards)
+            .push(title4("Common top plays"))
+            .push(common),
+    )
+    .into()
+}
+
+/// Card with players in the order of one ranking
+fn leaderboard<'a>(
+    title: &'a str,
+    players: impl Iterator<Item = &'a ComparedPlayer>,
+    value: impl Fn(&ComparedPlayer) -> String,
+) -> Element<'a, AppMessage> {
+    let rows = players.enumerate().map(|(i, player)| {
+        let name = if player.tracked {
+            format!("{}. {} (tracked)", i + 1, player.profile.username)
+        } else {
+            format!("{}. {}", i + 1, player.profile.username)
+        };
+        cosmic::widget::row()
+            .spacing(10)
+            .push(text(name).width(Length::Fill))
+            .push(text(value(player)))
+            .into()
+    });
+    container(
+        cosmic::widget::column()
+            .spacing(6)
+            .push(title4(title))
+            .extend(rows),
+    )
+    .padding(12)
+    .width(Length::FillPortion(1))
+    .class(Container::Card)
+    .into()
+}
+
+fn draw_common_beatmap(common: &CommonBeatmap) -> Element<AppMessage> {
+    let title = match &common.beatmap {
+        Some(map) => format!("{} - {} [{}]", map.artist, map.title, map.version),
+        None => "Unknown beatmap".to_owned(),
+    };
+    let rows = common.scores.iter().map(|compared| {
+        let score = &compared.score;
+        let mods = if score.mods.is_empty() {
+            "NM".to_owned()
+        } else {
+            score.mods.concat()
+        };
+        let pp = score
+            .pp
+            .map_or_else(|| "- pp".to_owned(), |pp| format!("{pp:.2} pp"));
+        cosmic::widget::row()
+            .spacing(10)
+            .align_y(Alignment::Center)
+            .push(text(compared.username.as_str()).width(Length::Fill))
+            .push(text(pp).width(Length::Fixed(90.0)))
+            .push(text(format_accuracy(score.accuracy)).width(Length::Fixed(70.0)))
+            .push(text(mods).width(Length::Fixed(70.0)))
+            .push(text(format!("top #{}", compared.top_position)).width(Length::Fixed(70.0)))
+            .into()
+    });
+    container(
+        cosmic::widget::column()
+            .spacing(6)
+            .push(text(title))
+            .extend(rows),
+    )
+    .padding(12)
+    .width(Length::Fill)
+    .class(Container::Card)
+    .into()
+}
@@ -1,8 +1,6 @@
-use ::image::{DynamicImage, GenericImageView};
 use cosmic::iced::advanced::widget::{self};
-use cosmic::iced::alignment::Horizontal;
-use cosmic::iced::wgpu::naga::FastHashMap;
-use cosmic::iced::{Alignment, Background, Length, Padding};
+use cosmic::iced::alignment::{Horizontal, Vertical};
+use cosmic::iced::{Alignment, Background, ContentFit, Length, Padding};
 use cosmic::iced_widget::image::Handle;
 use cosmic::iced_widget::{column, row, stack};
 use cosmic::prelude::CollectionWidget;
@@ -10,28 +8,75 @@ use cosmic::theme::Container;
 use cosmic::widget::text::{title1, title3};
 use cosmic::widget::{Image, container, scrollable, vertical_space};
 use cosmic::widget::{image, text};
-use cosmic::{Element, Theme, theme};
-use rosu_v2::prelude::{Score, UserExtended};
+use cosmic::{Apply, Element, Theme, theme};
+use rosu_v2::prelude::{MostPlayedMap, Score, UserExtended};
+use std::collections::{HashMap, VecDeque};
+use types::payload::completion;
 
 use super::app::AppMessage;
+use super::image_fetch::{ImageKey, ImageQueue, mapset_id};
+use super::score_filter::{ScoreFilter, draw_filters};
 
 pub fn draw_scores<'a>(
     scores: &'a [Score],
-    background: &'a FastHashMap<u32, Option<DynamicImage>>,
+    images: &'a ImageQueue,
+    filter: &'a ScoreFilter,
 ) -> Element<'a, AppMessage> {
-    let mut score_text = scores
-        .iter()
+    draw_score_list(scores, images, filter, |_| None)
+}
+
+/// Recent scores, each with whether it passed and which attempt on its beatmap it was
+pub(crate) fn draw_recent_scores<'a>(
+    scores: &'a [Score],
+    images: &'a ImageQueue,
+    filter: &'a ScoreFilter,
+) -> Element<'a, AppMessage> {
+    // Numbered oldest first, the API sends the newest first
+    let mut tries = HashMap::<u32, u32>::new();
+    let mut attempts = HashMap::<u64, u32>::new();
+    for score in scores.iter().rev() {
+        let attempt = tries.entry(score.map_id).or_default();
+        *attempt += 1;
+        attempts.insert(score.id, *attempt);
+    }
+    draw_score_list(scores, images, filter, |score| {
+        let result = match (score.passed, completion(score)) {
+            (true, _) => "Passed".to_owned(),
+            (false, Some(completion)) => format!("Failed at {completion:.0}%"),
+            (false, None) => "Failed".to_owned(),
+        };
+        let total = tries.get(&score.map_id).copied().unwrap_or(1);
+        Some(match attempts.get(&score.id) {
+            Some(attempt) if total > 1 => format!("{result}, try {attempt} of {total}"),
+            _ => result,
+        })
+    })
+}
+
+/// `status` is an extra line for each score, e.g. whether it passed
+fn draw_score_list<'a>(
+    scores: &'a [Score],
+    images: &'a ImageQueue,
+    filter: &'a ScoreFilter,
+    status: impl Fn(&Score) -> Option<String>,
+) -> Element<'a, AppMessage> {
+    let mut score_text = filter
+        .apply(scores)
+        .into_iter()
         .map(|score| {
-            let bg = background
-                .get(&score.mapset.as_ref().unwrap().mapset_id)
-                .unwrap_or(&None);
-            draw_score(score, bg)
+            let bg = mapset_id(score).and_then(|id| images.get(&ImageKey::Cover(id)));
+            draw_score(score, bg, status(score))
         })
         .collect::<Vec<_>>();
-    if scores.is_empty() {
-        score_text = vec![stack!(cosmic::widget::text("No scores!"))];
+    if score_text.is_empty() {
+        let empty = if scores.is_empty() {
+            "No scores!"
+        } else {
+            "No scores match the filters"
+        };
+        score_text = vec![stack!(cosmic::widget::text(empty))];
     }
-    scrollable(
+    let list = scrollable(
         cosmic::widget::column()
             .spacing(20)
             .append(&mut score_text)
@@ -43,35 +88,44 @@ pub fn draw_scores<'a>(
                 bottom: 0.0,
                 left: 10.0,
             }),
-    )
-    .into()
+    );
+    cosmic::widget::column()
+        .push(draw_filters(filter))
+        .push(list)
+        .into()
 }
 fn draw_score<'a>(
     score: &'a Score,
-    background: &'a Option<DynamicImage>,
+    background: Option<&'a Handle>,
+    status: Option<String>,
 ) -> cosmic::iced_widget::Stack<'a, AppMessage, Theme> {
-    let mapset = score.mapset.as_ref().unwrap();
-    let map = score.map.as_ref().unwrap();
+    // Either can be missing, e.g. for deleted beatmaps
+    let title = score
+        .mapset
+        .as_ref()
+        .map_or("Unknown beatmap", |mapset| mapset.title.as_str());
+    let version = score.map.as_ref().map_or("?", |map| map.version.as_str());
     let title_diff: Element<AppMessage> = cosmic::widget::button::custom(
-        text(format!("{} [{}]", mapset.title.clone(), &map.version))
-            .wrapping(widget::text::Wrapping::None),
+        text(format!("{title} [{version}]")).wrapping(widget::text::Wrapping::None),
     )
     .class(theme::Button::Link)
-    .on_press(AppMessage::LaunchUrl(format!(
-        "https://osu.ppy.sh/scores/{}",
-        score.id
-    )))
+    .on_press(AppMessage::ShowScore(score.id))
     // TODO: Change to Length::Shrink and add horizontal_space()
     .width(Length::Fill)
     .padding(0)
     .into();
-    let artist = text(mapset.artist.clone()).height(Length::Fill);
+    let artist = score
+        .mapset
+        .as_ref()
+        .map_or("Unknown artist", |mapset| mapset.artist.as_str());
+    let artist = text(artist).height(Length::Fill);
     let date = text(score.ended_at.date().to_string());
-    let pp = text(format!(
-        "{} pp",
-        score.pp.unwrap_or_default().trunc() as u32
-    ))
-    .height(Length::Fill);
+    // Loved and unranked scores don't give pp
+    let pp = match score.pp {
+        Some(pp) => format!("{} pp", pp.trunc() as u32),
+        None => "- pp".to_owned(),
+    };
+    let pp = text(pp).height(Length::Fill);
     let combo = text(format!("{} combo", score.max_combo)).height(Length::Fill);
     let spacing = vertical_space();
     let col = row![
@@ -80,45 +134,155 @@ fn draw_score<'a>(
             .width(Length::FillPortion(2))
             .height(Length::Fill),
         column![pp, date]
+            .push_maybe(status.map(text))
             .padding(10)
             .width(Length::FillPortion(1))
             .height(Length::Fill)
     ];
-    let bg: Option<Image> = background
-        .clone()
-        .map(<DynamicImage>::into_bytes)
-        .map(Handle::from_bytes)
-        .map(Image::new);
+    let bg: Option<Image> = background.map(|handle| {
+        Image::new(handle.clone())
+            .width(Length::Fill)
+            .height(Length::Fixed(100.0))
+            .content_fit(ContentFit::Cover)
+    });
 
     let card = container(col)
-        .class(Container::custom(|theme| {
-            let cosmic = theme.cosmic();
-            let corners = cosmic.corner_radii;
-            container::Style {
-                text_color: Some(cosmic.background.on.into()),
-                background: Some(
-                    cosmic::iced::Color::from(cosmic.background.component.base).into(),
-                ),
-                border: cosmic::iced::Border {
-                    radius: corners.radius_m.into(),
-                    width: 1.0,
-                    color: cosmic.background.divider.into(),
-                },
-                shadow: cosmic::iced::Shadow::default(),
-                icon_color: Some(cosmic.background.on.into()),
-            }
-        }))
+        .class(Container::custom(card_style))
         .width(Length::Fill)
         .height(Length::Fixed(100.0));
     let bg = cosmic::iced_widget::Stack::new().push_maybe(bg).push(card);
     bg.into()
 }
+fn card_style(theme: &Theme) -> container::Style {
+    let cosmic = theme.cosmic();
+    let corners = cosmic.corner_radii;
+    container::Style {
+        text_color: Some(cosmic.background.on.into()),
+        background: Some(cosmic::iced::Color::from(cosmic.background.component.base).into()),
+        border: cosmic::iced::Border {
+            radius: corners.radius_m.into(),
+            width: 1.0,
+            color: cosmic.background.divider.into(),
+        },
+        shadow: cosmic::iced::Shadow::default(),
+        icon_color: Some(cosmic.background.on.into()),
+    }
+}
+/// Most played beatmaps in the order osu! sends them, most played first
+pub(crate) fn draw_most_played<'a>(
+    maps: &'a [MostPlayedMap],
+    images: &'a ImageQueue,
+) -> Element<'a, AppMessage> {
+    if maps.is_empty() {
+        return draw_placeholder("No beatmaps played yet");
+    }
+    let cards = maps.iter().map(|most_played| {
+        let (map, mapset) = (&most_played.map, &most_played.mapset);
+        let title = text(format!("{} [{}]", mapset.title, map.version))
+            .wrapping(widget::text::Wrapping::None);
+        let artist = text(format!("{} // {}", mapset.artist, mapset.creator_name));
+        let col = row![
+            column![title, artist, vertical_space()]
+                .padding(10)
+                .width(Length::FillPortion(2))
+                .height(Length::Fill),
+            column![
+                text(format!("{} plays", format_number(most_played.count as u64))),
+                text(format!("{:.2}\u{2605}", map.stars)),
+            ]
+            .padding(10)
+            .width(Length::FillPortion(1))
+            .height(Length::Fill)
+        ];
+        let cover = images
+            .get(&ImageKey::Cover(mapset.mapset_id))
+            .map(|handle| {
+                Image::new(handle.clone())
+                    .width(Length::Fill)
+                    .height(Length::Fixed(100.0))
+                    .content_fit(ContentFit::Cover)
+            });
+        let card = container(col)
+            .class(Container::custom(card_style))
+            .width(Length::Fill)
+            .height(Length::Fixed(100.0));
+        cosmic::iced_widget::Stack::new()
+            .push_maybe(cover)
+            .push(card)
+            .into()
+    });
+    scrollable(
+        cosmic::widget::column()
+            .spacing(20)
+            .extend(cards)
+            .width(Length::Fill)
+            .max_width(800)
+            .padding(Padding {
+                top: 0.0,
+                right: 20.0,
+                bottom: 0.0,
+                left: 10.0,
+            }),
+    )
+    .into()
+}
+/// Centered message for pages without data to show yet
+pub(crate) fn draw_placeholder(message: &str) -> Element<AppMessage> {
+    title3(message)
+        .apply(container)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .align_x(Horizontal::Center)
+        .align_y(Vertical::Center)
+        .into()
+}
+/// Dismissable notices, e.g. about new top plays
+pub(crate) fn draw_notices(notices: &VecDeque<String>) -> Element<AppMessage> {
+    let notices = notices.iter().enumerate().map(|(index, notice)| {
+        container(
+            row![
+                text(notice.as_str()).width(Length::Fill),
+                cosmic::widget::button::text("Dismiss").on_press(AppMessage::DismissNotice(index)),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center),
+        )
+        .padding(10)
+        .class(Container::Card)
+        .into()
+    });
+    cosmic::widget::column()
+        .spacing(10)
+        .padding(10)
+        .extend(notices)
+        .into()
+}
+/// Row of buttons controlling the tracker
+pub(crate) fn draw_controls(track_input: &str) -> Element<AppMessage> {
+    let input = cosmic::widget::text_input("Track another user", track_input)
+        .on_input(AppMessage::TrackInput)
+        .on_submit(|_| AppMessage::Track)
+        .width(Length::Fill);
+    row![
+        input,
+        cosmic::widget::button::suggested("Track").on_press(AppMessage::Track),
+        cosmic::widget::button::standard("Untrack").on_press(AppMessage::Untrack),
+        cosmic::widget::button::standard("Reset session").on_press(AppMessage::ResetSession),
+        cosmic::widget::button::standard("Refresh").on_press(AppMessage::Refresh),
+    ]
+    .spacing(10)
+    .padding(10)
+    .align_y(Alignment::Center)
+    .into()
+}
+
 /// LIFETIME: Realistically - shares one with `App`
 pub(crate) fn draw_user<'u>(
     current: &'u UserExtended,
     initial: &'u UserExtended,
+    images: &'u ImageQueue,
 ) -> Element<'u, AppMessage> {
-    let title = centered_username(current);
+    let title = profile_header(current, images);
     let data = cosmic::widget::container(user_extended_data(current, initial));
     let children = cosmic::widget::column()
         .push(title)
@@ -130,13 +294,81 @@ pub(crate) fn draw_user<'u>(
         .into()
 }
 
-fn centered_username(user: &UserExtended) -> Element<AppMessage> {
-    let username = cosmic::widget::container(
-        title1(user.username.clone().into_string()).align_x(Alignment::Center),
+const HEADER_HEIGHT: f32 = 160.0;
+const AVATAR_SIZE: f32 = 96.0;
+const FLAG_HEIGHT: f32 = 20.0;
+
+/// Profile cover with the avatar, name, flags and supporter badge on top.
+/// Images which couldn't be loaded are left out
+fn profile_header<'u>(user: &'u UserExtended, images: &'u ImageQueue) -> Element<'u, AppMessage> {
+    let image_of = |key: ImageKey| images.get(&key).map(|handle| Image::new(handle.clone()));
+    let avatar = image_of(ImageKey::Url(user.avatar_url.clone())).map(|avatar| {
+        avatar
+            .width(Length::Fixed(AVATAR_SIZE))
+            .height(Length::Fixed(AVATAR_SIZE))
+            .content_fit(ContentFit::Cover)
+    });
+    let flag = image_of(ImageKey::Flag(user.country_code.to_string()))
+        .map(|flag| flag.height(Length::Fixed(FLAG_HEIGHT)));
+    let mut origin = cosmic::widget::row()
+        .spacing(8)
+        .align_y(Alignment::Center)
+        .push_maybe(flag)
+        .push(text(user.country.clone()));
+    if let Some(team) = &user.team {
+        let team_flag = team
+            .flag_url
+            .clone()
+            .and_then(|url| image_of(ImageKey::Url(url)))
+            .map(|flag| flag.height(Length::Fixed(FLAG_HEIGHT)));
+        origin = origin
+            .push_maybe(team_flag)
+            .push(text(format!("[{}] {}", team.short_name, team.name)));
+    }
+    let supporter = user.is_supporter.then(|| text("\u{2665} osu!supporter"));
+    let details = cosmic::widget::column()
+        .spacing(4)
+        .push(title1(user.username.clone().into_string()))
+        .push(origin)
+        .push_maybe(supporter);
+    let overlay = container(
+        cosmic::widget::row()
+            .spacing(16)
+            .padding(16)
+            .align_y(Alignment::Center)
+            .push_maybe(avatar)
+            .push(details),
     )
-    .align_x(Horizontal::Center)
-    .center_x(Length::Fill);
-    username.into()
+    .class(Container::custom(|theme| {
+        let cosmic = theme.cosmic();
+        let mut background = cosmic::iced::Color::from(cosmic.background.base);
+        background.a = 0.75;
+        container::Style {
+            text_color: Some(cosmic.background.on.into()),
+            background: Some(background.into()),
+            ..Default::default()
+        }
+    }))
+    .width(Length::Fill);
+
+    // No placeholder for the cover, the header looks fine without one
+    let cover = images
+        .get_loaded(&ImageKey::Url(user.cover.url.clone()))
+        .map(|handle| {
+            Image::new(handle.clone())
+                .width(Length::Fill)
+                .height(Length::Fixed(HEADER_HEIGHT))
+                .content_fit(ContentFit::Cover)
+        });
+    cosmic::iced_widget::Stack::new()
+        .push_maybe(cover)
+        .push(
+            container(overlay)
+                .width(Length::Fill)
+                .height(Length::Fixed(HEADER_HEIGHT))
+                .align_y(Vertical::Bottom),
+        )
+        .into()
 }
 
 /// Widget for displaying a tri-column of user data
@@ -150,8 +382,12 @@ fn user_extended_data<'u>(
         .align_x(Horizontal::Center)
         .width(Length::Fill)
         .padding(20);
-    let current_statistics = current.statistics.as_ref().unwrap();
-    let initial_statistics = initial.statistics.as_ref().unwrap();
+    // Users who never played the mode come without statistics
+    let Some(current_statistics) = current.statistics.as_ref() else {
+        return draw_placeholder("No statistics yet");
+    };
+    let initial_statistics = initial.statistics.as_ref().unwrap_or(current_statistics);
+    let peak_rank = |user: &UserExtended| user.highest_rank.as_ref().map(|peak| peak.rank);
     let children = [
         make_pair::<f32>(
             "pp",
@@ -159,35 +395,29 @@ fn user_extended_data<'u>(
             initial_statistics.pp,
             None::<fn(f32) -> String>,
         ),
-        make_pair(
+        make_optional_pair(
             "rank",
-            current_statistics.global_rank.unwrap_or(0),
-            initial_statistics.global_rank.unwrap_or(0),
-            None::<fn(u32) -> String>,
+            current_statistics.global_rank,
+            initial_statistics.global_rank,
         ),
-        make_pair::<u32>(
+        make_optional_pair(
             "country rank",
-            current_statistics.country_rank.unwrap_or(0),
-            initial_statistics.country_rank.unwrap_or(0),
-            None::<fn(u32) -> String>,
-        ),
-        make_pair::<u32>(
-            "peak rank",
-            current.highest_rank.as_ref().unwrap().rank,
-            initial.highest_rank.as_ref().unwrap().rank,
-            None::<fn(u32) -> String>,
+            current_statistics.country_rank,
+            initial_statistics.country_rank,
         ),
+        make_optional_pair("peak rank", peak_rank(current), peak_rank(initial)),
         make_pair::<f32>(
             "accuracy",
             current_statistics.accuracy,
             initial_statistics.accuracy,
             Some(format_accuracy),
         ),
+        // Signed, ranked score drops when scores are deleted or maps lose their ranked status
         make_pair(
             "ranked score",
-            current_statistics.ranked_score,
-            initial_statistics.ranked_score,
-            Some(format_number),
+            current_statistics.ranked_score as i64,
+            initial_statistics.ranked_score as i64,
+            Some(format_signed_number),
         ),
         make_pair(
             "A ranks",
@@ -226,6 +456,25 @@ fn user_extended_data<'u>(
     items.into()
 }
 
+/// Like [`make_pair`], with a dash for values the user doesn't have, e.g. the rank of an
+/// inactive user. Without an initial value the change is counted from the current one
+fn make_optional_pair<'a>(
+    title: &'a str,
+    current: Option<u32>,
+    initial: Option<u32>,
+) -> Element<'a, AppMessage> {
+    // Signed, ranks go down as they improve
+    match current {
+        Some(current) => make_pair(
+            title,
+            i64::from(current),
+            i64::from(initial.unwrap_or(current)),
+            None::<fn(i64) -> String>,
+        ),
+        None => data_row(title, "-".to_owned(), String::new()),
+    }
+}
+
 fn make_pair<'a, T>(
     title: &'a str,
     current: T,
@@ -246,6 +495,10 @@ where
         }
         None => format!("{}", current - initial),
     };
+    data_row(title, current_string, delta_string)
+}
+
+fn data_row<'a>(title: &'a str, current: String, delta: String) -> Element<'a, AppMessage> {
     container(
         row![
             widget::text::Text::new(title)
@@ -253,11 +506,11 @@ where
                 .size(16)
                 .width(Length::FillPortion(1)),
             // cosmic::widget::divider::vertical::default(),
-            widget::text::Text::new(current_string)
+            widget::text::Text::new(current)
                 .align_x(Horizontal::Right)
                 .size(16)
                 .width(Length::FillPortion(1)),
-            cosmic::widget::text(delta_string)
+            cosmic::widget::text(delta)
                 .align_x(Horizontal::Right)
                 .size(16)
                 .width(Length::FillPortion(1))
@@ -270,19 +523,54 @@ where
     .center_y(Length::Shrink)
     .into()
 }
-fn format_number(int: impl Into<u64>) -> String {
-    let num = int
-        .into()
-        .to_string()
-        .as_bytes()
-        .rchunks(3)
-        .rev()
-        .map(std::str::from_utf8)
-        .collect::<Result<Vec<&str>, _>>()
-        .unwrap()
-        .join(",");
+pub(crate) fn format_number(int: impl Into<u64>) -> String {
+    let digits = int.into().to_string();
+    let mut num = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            num.push(',');
+        }
+        num.push(digit);
+    }
     num
 }
-fn format_accuracy(acc: f32) -> String {
+fn format_signed_number(int: i64) -> String {
+    let number = format_number(int.unsigned_abs());
+    if int < 0 {
+        format!("-{number}")
+    } else {
+        number
+    }
+}
+pub(crate) fn format_accuracy(acc: f32) -> String {
     format!("{:.2}%", acc)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn draws_score_without_beatmap() {
+        let score = fixtures::score();
+        assert!(score.map.is_none() && score.mapset.is_none());
+        let _ = draw_score(&score, None, Some("new".to_owned()));
+        let _ = draw_score(&score, None, None);
+    }
+
+    #[test]
+    fn draws_user_without_statistics() {
+        let user = fixtures::user();
+        assert!(user.statistics.is_none() && user.highest_rank.is_none());
+        let _ = user_extended_data(&user, &user);
+    }
+
+    #[test]
+    fn draws_user_without_initial_statistics() {
+        let current = fixtures::user_with_statistics();
+        let initial = fixtures::user();
+        let _ = user_extended_data(&current, &initial);
+        let _ = user_extended_data(&current, &current);
+    }
+}
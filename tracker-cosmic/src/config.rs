@@ -1,17 +1,45 @@
-use cosmic::cosmic_config::{self, cosmic_config_derive::CosmicConfigEntry, ConfigGet, CosmicConfigEntry};
-use serde::{Deserialize, Serialize};
 use color_eyre::eyre::Result;
-use types::Api;
+use constants::{DEFAULT_POLL_INTERVAL, DEFAULT_PORT, DEFAULT_RECENT_LIMIT};
+use cosmic::cosmic_config::{
+    self, ConfigGet, CosmicConfigEntry, cosmic_config_derive::CosmicConfigEntry,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use types::{Api, Config as TrackerConfig, Mode};
+use utils::secret;
 
 #[derive(Debug, Clone, CosmicConfigEntry, Eq, PartialEq, Deserialize, Serialize)]
 #[version = 1]
 pub struct Config {
     #[serde(default)]
-    user_client: String,
+    pub user_client: String,
+    /// Only set if the secret store failed, see [`take_secret`]
+    #[serde(default)]
+    pub user_secret: String,
+    #[serde(default)]
+    pub tracked_user_name: String,
+    #[serde(default)]
+    pub mode: Option<Mode>,
+    #[serde(default)]
+    pub port: u16,
+    /// In seconds
+    #[serde(default)]
+    pub poll_interval: u64,
+    /// Recent scores fetched
+    #[serde(default)]
+    pub recent_limit: usize,
+    /// Fetch failed plays as recent scores
     #[serde(default)]
-    user_secret: String,
+    pub include_fails: bool,
+    /// User ids of the players on the compare page
     #[serde(default)]
-    tracked_user_name: String,
+    pub compare_users: Vec<u32>,
+    /// Show a notice when a new top play is set
+    #[serde(default)]
+    pub notify_top_plays: bool,
+    /// Show a notice when the global rank changes
+    #[serde(default)]
+    pub notify_rank: bool,
 }
 
 impl Default for Config {
@@ -20,16 +48,68 @@ impl Default for Config {
             user_client: String::new(),
             user_secret: String::new(),
             tracked_user_name: String::new(),
+            mode: None,
+            port: DEFAULT_PORT,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            recent_limit: DEFAULT_RECENT_LIMIT,
+            include_fails: false,
+            compare_users: Vec::new(),
+            notify_top_plays: true,
+            notify_rank: true,
         }
     }
 }
 
-pub fn get_config_cosmic() -> Result<Api> {
-    let config_handler =
-        cosmic_config::Config::new(constants::APP_ID, constants::CONFIG_VERSION)?;
-    Ok(Api {
-        id: config_handler.get::<String>("user_client")?,
-        secret: config_handler.get::<String>("user_secret")?,
+pub fn get_config_cosmic() -> Result<TrackerConfig> {
+    let config_handler = cosmic_config::Config::new(constants::APP_ID, constants::CONFIG_VERSION)?;
+    let id = config_handler.get::<String>("user_client")?;
+    let plaintext = config_handler
+        .get::<String>("user_secret")
+        .unwrap_or_default();
+    // Only in the config if it couldn't be stored
+    let secret = if plaintext.is_empty() {
+        secret::load(&id)?.unwrap_or_default()
+    } else {
+        plaintext
+    };
+    let api = Api {
+        id,
+        secret,
         username: config_handler.get::<String>("tracked_user_name")?,
+        // Missing in configs written before these were added
+        mode: config_handler.get::<Option<Mode>>("mode").ok().flatten(),
+    };
+    Ok(TrackerConfig {
+        port: config_handler.get::<u16>("port").ok(),
+        poll_interval: config_handler.get::<u64>("poll_interval").ok(),
+        recent_limit: config_handler.get::<usize>("recent_limit").ok(),
+        include_fails: config_handler.get::<bool>("include_fails").ok(),
+        compare_users: config_handler
+            .get::<Vec<u32>>("compare_users")
+            .unwrap_or_default(),
+        ..api.into()
     })
-}
\ No newline at end of file
+}
+
+/// The client secret. A secret left in plain text in the config is moved into the secret store
+pub fn take_secret(config_handler: &cosmic_config::Config, config: &mut Config) -> String {
+    if config.user_secret.is_empty() {
+        return secret::load(&config.user_client)
+            .inspect_err(|e| error!("failed to load the client secret: {}", e))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+    }
+    let plaintext = config.user_secret.clone();
+    match secret::store(&config.user_client, &plaintext) {
+        Ok(()) => match config.set_user_secret(config_handler, String::new()) {
+            Ok(_) => info!("Moved the client secret from the config into the secret store"),
+            Err(e) => error!("failed to remove the client secret from the config: {}", e),
+        },
+        Err(e) => error!(
+            "failed to store the client secret, leaving it in the config: {}",
+            e
+        ),
+    }
+    plaintext
+}
@@ -0,0 +1,22 @@
+//! Scores and users as the osu! API sends them, without the optional fields the pages have to
+//! draw placeholders for
+
+use rosu_v2::prelude::{Score, UserExtended};
+
+/// A score without its beatmap and mapset, e.g. of a deleted beatmap
+pub(crate) fn score() -> Score {
+    serde_json::from_str(include_str!("fixtures/score.json")).unwrap()
+}
+
+/// A user without statistics and peak rank, e.g. one who never played the mode
+pub(crate) fn user() -> UserExtended {
+    serde_json::from_str(include_str!("fixtures/user.json")).unwrap()
+}
+
+/// [`user`] with statistics, still without a peak rank
+pub(crate) fn user_with_statistics() -> UserExtended {
+    let mut user: serde_json::Value =
+        serde_json::from_str(include_str!("fixtures/user.json")).unwrap();
+    user["statistics"] = serde_json::from_str(include_str!("fixtures/statistics.json")).unwrap();
+    serde_json::from_value(user).unwrap()
+}
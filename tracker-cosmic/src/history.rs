@@ -0,0 +1,126 @@
+//! Stats of tracked users over time, kept on disk so charts survive restarts
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{Result, eyre::eyre};
+use directories::ProjectDirs;
+use rosu_v2::prelude::UserExtended;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+pub(crate) const DAY: i64 = 24 * 60 * 60;
+/// Samples older than this are dropped
+const MAX_AGE: i64 = 31 * DAY;
+/// Unchanged stats are sampled at most this often
+const SAMPLE_INTERVAL: i64 = 60 * 60;
+
+/// Current unix timestamp, in seconds
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Stats of a user at one point in time
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Sample {
+    /// Unix timestamp, in seconds
+    pub at: i64,
+    pub pp: f32,
+    pub global_rank: Option<u32>,
+    pub accuracy: f32,
+    pub playcount: u32,
+}
+
+impl Sample {
+    /// `None` if the user came without statistics
+    pub fn new(user: &UserExtended, at: i64) -> Option<Self> {
+        let statistics = user.statistics.as_ref()?;
+        Some(Self {
+            at,
+            pp: statistics.pp,
+            global_rank: statistics.global_rank,
+            accuracy: statistics.accuracy,
+            playcount: statistics.playcount,
+        })
+    }
+
+    fn same_stats(&self, other: &Self) -> bool {
+        Self {
+            at: other.at,
+            ..*self
+        } == *other
+    }
+}
+
+/// Samples of every tracked user, by user id
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub(crate) struct History {
+    users: HashMap<u32, Vec<Sample>>,
+}
+
+impl History {
+    /// Record a sample, returns whether it was kept
+    pub fn push(&mut self, user_id: u32, sample: Sample) -> bool {
+        let samples = self.users.entry(user_id).or_default();
+        let redundant = samples
+            .last()
+            .is_some_and(|last| last.same_stats(&sample) && sample.at - last.at < SAMPLE_INTERVAL);
+        if redundant {
+            return false;
+        }
+        samples.push(sample);
+        let cutoff = sample.at - MAX_AGE;
+        samples.retain(|sample| sample.at >= cutoff);
+        true
+    }
+
+    /// Samples of a user taken at or after `from`, oldest first
+    pub fn since(&self, user_id: u32, from: i64) -> &[Sample] {
+        let samples = self.users.get(&user_id).map_or(&[][..], Vec::as_slice);
+        let start = samples.partition_point(|sample| sample.at < from);
+        &samples[start..]
+    }
+}
+
+/// Location of the persisted history
+#[derive(Debug, Clone)]
+pub(crate) struct HistoryFile {
+    path: PathBuf,
+}
+
+impl HistoryFile {
+    pub fn new() -> Result<Self> {
+        let path = ProjectDirs::from("com", "chiffa", "rosuTracker")
+            .ok_or_else(|| eyre!("Failed to find the data directory"))?
+            .data_dir()
+            .join("history.json");
+        Ok(Self { path })
+    }
+
+    /// Blocking, the file is small enough to read while starting up
+    pub fn load(&self) -> Result<History> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(History::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Atomically replace the saved history
+    pub async fn save(self, history: History) -> Result<()> {
+        let bytes = serde_json::to_vec(&history)?;
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        debug!("Saved history to {}", self.path.display());
+        Ok(())
+    }
+}
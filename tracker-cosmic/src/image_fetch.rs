@@ -1,18 +1,176 @@
-use cosmic::{app, iced_futures};
-use cosmic::iced::futures::Stream;
-use futures_util::SinkExt;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use cosmic::app::Task;
+use cosmic::iced_widget::image::Handle;
+use image::DynamicImage;
+use rosu_v2::prelude::{Score, UserExtended};
+use tracing::debug;
+use utils::ImageCache;
+
 use crate::app::AppMessage;
-use utils::try_get_image;
-
-pub fn fetch_multiple(id_list: Box<[u32]>) -> impl Stream<Item = cosmic::action::Action<AppMessage>> {
-    iced_futures::stream::channel(100, move |mut output| async move {
-        for id in id_list {
-            let image = try_get_image(id).await.ok();
-            let _ = output
-                .send(cosmic::action::Action::App(AppMessage::ReceiveBackground(
-                    id, image,
-                )))
-                .await;
+
+/// Images downloaded at the same time
+const MAX_CONCURRENT: usize = 4;
+/// Attempts per image before giving up on it
+const MAX_ATTEMPTS: u32 = 3;
+/// Multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Covers are stored per mapset
+pub(crate) fn mapset_id(score: &Score) -> Option<u32> {
+    score
+        .mapset
+        .as_ref()
+        .map(|mapset| mapset.mapset_id)
+        .or_else(|| score.map.as_ref().map(|map| map.mapset_id))
+}
+
+/// Avatar, flags and profile cover of a user
+pub(crate) fn profile_images(user: &UserExtended) -> impl Iterator<Item = ImageKey> + use<> {
+    let urls = [
+        Some(user.avatar_url.clone()),
+        Some(user.cover.url.clone()),
+        user.team.as_ref().and_then(|team| team.flag_url.clone()),
+    ];
+    urls.into_iter()
+        .flatten()
+        .filter(|url| !url.is_empty())
+        .map(ImageKey::Url)
+        .chain([ImageKey::Flag(user.country_code.to_string())])
+}
+
+/// Shown until an image is loaded
+fn placeholder() -> &'static Handle {
+    static PLACEHOLDER: OnceLock<Handle> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Handle::from_rgba(1, 1, vec![0x80, 0x80, 0x80, 0x40]))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ImageKey {
+    /// Beatmapset cover, by mapset id
+    Cover(u32),
+    /// Country flag, by country code
+    Flag(String),
+    /// Anything else by URL, e.g. avatars and profile covers
+    Url(String),
+}
+
+enum State {
+    Loading,
+    Loaded(Handle),
+    Failed,
+}
+
+/// Deduplicated queue of every image the GUI shows
+#[derive(Default)]
+pub struct ImageQueue {
+    images: HashMap<ImageKey, State>,
+    pending: VecDeque<ImageKey>,
+    in_flight: usize,
+    cache: Option<Arc<ImageCache>>,
+}
+
+impl ImageQueue {
+    pub fn new(cache: Option<Arc<ImageCache>>) -> Self {
+        Self {
+            cache,
+            ..Default::default()
+        }
+    }
+
+    /// Queue every image which isn't loaded or queued yet
+    pub fn request(&mut self, keys: impl IntoIterator<Item = ImageKey>) -> Task<AppMessage> {
+        for key in keys {
+            if !self.images.contains_key(&key) {
+                self.images.insert(key.clone(), State::Loading);
+                self.pending.push_back(key);
+            }
         }
-    })
-}
\ No newline at end of file
+        self.start()
+    }
+
+    /// Store a downloaded image and start on the next one
+    pub fn finish(&mut self, key: ImageKey, image: Option<Handle>) -> Task<AppMessage> {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        let state = image.map_or(State::Failed, State::Loaded);
+        self.images.insert(key, state);
+        self.start()
+    }
+
+    /// The image, a placeholder while it's loading, or nothing if it couldn't be loaded
+    pub fn get(&self, key: &ImageKey) -> Option<&Handle> {
+        match self.images.get(key)? {
+            State::Loading => Some(placeholder()),
+            State::Loaded(handle) => Some(handle),
+            State::Failed => None,
+        }
+    }
+
+    /// Like `get`, but without the placeholder
+    pub fn get_loaded(&self, key: &ImageKey) -> Option<&Handle> {
+        match self.images.get(key)? {
+            State::Loaded(handle) => Some(handle),
+            _ => None,
+        }
+    }
+
+    fn start(&mut self) -> Task<AppMessage> {
+        let Some(cache) = &self.cache else {
+            for key in self.pending.drain(..) {
+                self.images.insert(key, State::Failed);
+            }
+            return Task::none();
+        };
+        let mut tasks = Vec::new();
+        while self.in_flight < MAX_CONCURRENT {
+            let Some(key) = self.pending.pop_front() else {
+                break;
+            };
+            self.in_flight += 1;
+            let fetched = fetch(cache.clone(), key.clone());
+            tasks.push(Task::perform(fetched, move |image| {
+                cosmic::action::Action::App(AppMessage::ReceiveImage(key, image))
+            }));
+        }
+        Task::batch(tasks)
+    }
+}
+
+async fn fetch(cache: Arc<ImageCache>, key: ImageKey) -> Option<Handle> {
+    for attempt in 1..=MAX_ATTEMPTS {
+        let image = match &key {
+            ImageKey::Cover(mapset_id) => cache.cover(*mapset_id).await,
+            ImageKey::Flag(country_code) => cache.flag(country_code).await,
+            ImageKey::Url(url) => cache.get(url).await,
+        };
+        match image {
+            Ok(image) => return Some(to_handle(image)),
+            Err(e) => {
+                debug!("Attempt {attempt} to load {:?} failed: {e}", key);
+                if attempt < MAX_ATTEMPTS {
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Convert once here instead of on every redraw
+fn to_handle(image: DynamicImage) -> Handle {
+    let rgba = image.into_rgba8();
+    Handle::from_rgba(rgba.width(), rgba.height(), rgba.into_raw())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn no_mapset_without_beatmap() {
+        assert_eq!(mapset_id(&fixtures::score()), None);
+    }
+}
@@ -7,6 +7,15 @@ mod components;
 mod socket;
 mod config;
 mod image_fetch;
+mod history;
+mod charts;
+mod settings;
+mod score_detail;
+mod score_filter;
+mod top_changes;
+mod compare;
+#[cfg(test)]
+mod fixtures;
 
 /// Initialise the gui with its local runtime
 // pub fn init() -> cosmic::Result {
@@ -27,7 +36,15 @@ pub fn set_cosmic_config(new_config: Api) -> () {
         cosmic_config::Config::new(constants::APP_ID, constants::CONFIG_VERSION)
             .unwrap();
     let mut config = crate::config::Config::default();
+    match utils::secret::store(&new_config.id, &new_config.secret) {
+        Ok(()) => {
+            let _ = config.set_user_secret(&config_handler, String::new());
+        }
+        Err(e) => {
+            tracing::error!("Failed to store the client secret, saving it in the config: {e}");
+            let _ = config.set_user_secret(&config_handler, new_config.secret);
+        }
+    }
     let _ = config.set_user_client(&config_handler, new_config.id);
-    let _ = config.set_user_secret(&config_handler, new_config.secret);
     let _ = config.set_tracked_user_name(&config_handler, new_config.username);
 }
\ No newline at end of file
@@ -0,0 +1,272 @@
+//! Detail page of a single score, with a performance breakdown calculated by rosu-pp
+
+use color_eyre::{Result, eyre::eyre};
+use cosmic::Element;
+use cosmic::iced::{Alignment, ContentFit, Length};
+use cosmic::iced_widget::row;
+use cosmic::prelude::CollectionWidget;
+use cosmic::theme::Container;
+use cosmic::widget::text::{title3, title4};
+use cosmic::widget::{Image, button, container, scrollable, text};
+use rosu_pp::any::PerformanceAttributes;
+use rosu_pp::model::mode::GameMode as PpMode;
+use rosu_pp::{Beatmap, Performance};
+use rosu_v2::prelude::{GameMode, Score};
+
+use crate::app::AppMessage;
+use crate::components::{format_accuracy, format_number};
+use crate::image_fetch::{ImageKey, ImageQueue, mapset_id};
+
+const COVER_HEIGHT: f32 = 140.0;
+
+/// Difficulty and performance of a score as rosu-pp sees it
+#[derive(Debug, Clone)]
+pub struct Breakdown {
+    /// Star rating with the score's mods
+    pub stars: f64,
+    pub max_combo: u32,
+    pub pp: f64,
+    /// Named parts of the pp, e.g. aim and speed
+    pub parts: Vec<(&'static str, f64)>,
+}
+
+/// The score being looked at, and its breakdown once it's calculated
+pub(crate) struct ScoreDetail {
+    pub score: Box<Score>,
+    pub breakdown: Option<Result<Breakdown, String>>,
+}
+
+/// Blocking, parses the map and runs the calculation
+pub(crate) fn calculate(osu_file: &[u8], score: &Score) -> Result<Breakdown> {
+    let map = Beatmap::from_bytes(osu_file).map_err(|e| eyre!("Failed to parse beatmap: {e}"))?;
+    let mode = match score.mode {
+        GameMode::Osu => PpMode::Osu,
+        GameMode::Taiko => PpMode::Taiko,
+        GameMode::Catch => PpMode::Catch,
+        GameMode::Mania => PpMode::Mania,
+    };
+    let statistics = &score.statistics;
+    let attributes = Performance::new(&map)
+        .mode_or_ignore(mode)
+        .mods(score.mods.bits())
+        .lazer(score.set_on_lazer)
+        .combo(score.max_combo)
+        .n_geki(statistics.perfect)
+        .n300(statistics.great)
+        .n_katu(statistics.good)
+        .n100(statistics.ok)
+        .n50(statistics.meh)
+        .misses(statistics.miss)
+        .large_tick_hits(statistics.large_tick_hit)
+        .small_tick_hits(statistics.small_tick_hit)
+        .slider_end_hits(statistics.slider_tail_hit)
+        .calculate();
+    let parts = match &attributes {
+        PerformanceAttributes::Osu(osu) => vec![
+            ("Aim", osu.pp_aim),
+            ("Speed", osu.pp_speed),
+            ("Accuracy", osu.pp_acc),
+            ("Flashlight", osu.pp_flashlight),
+        ],
+        PerformanceAttributes::Taiko(taiko) => vec![
+            ("Difficulty", taiko.pp_difficulty),
+            ("Accuracy", taiko.pp_acc),
+        ],
+        PerformanceAttributes::Mania(mania) => vec![("Difficulty", mania.pp_difficulty)],
+        // Catch pp isn't split up
+        PerformanceAttributes::Catch(_) => Vec::new(),
+    };
+    Ok(Breakdown {
+        stars: attributes.stars(),
+        max_combo: attributes.max_combo(),
+        pp: attributes.pp(),
+        parts,
+    })
+}
+
+/// Judgements of a score, named like the ruleset names them. Empty ones are left out
+fn hit_statistics(score: &Score) -> Vec<(&'static str, u32)> {
+    let s = &score.statistics;
+    let statistics = match score.mode {
+        GameMode::Osu => vec![
+            ("300", s.great),
+            ("100", s.ok),
+            ("50", s.meh),
+            ("Miss", s.miss),
+            ("Slider ticks", s.large_tick_hit),
+            ("Slider ends", s.slider_tail_hit),
+        ],
+        GameMode::Taiko => vec![("Great", s.great), ("Good", s.ok), ("Miss", s.miss)],
+        GameMode::Catch => vec![
+            ("Fruits", s.great),
+            ("Drops", s.large_tick_hit),
+            ("Droplets", s.small_tick_hit),
+            ("Miss", s.miss + s.large_tick_miss),
+            ("Missed droplets", s.small_tick_miss),
+        ],
+        GameMode::Mania => vec![
+            ("Max", s.perfect),
+            ("300", s.great),
+            ("200", s.good),
+            ("100", s.ok),
+            ("50", s.meh),
+            ("Miss", s.miss),
+        ],
+    };
+    statistics
+        .into_iter()
+        .filter(|(name, count)| *count > 0 || *name == "Miss")
+        .collect()
+}
+
+fn format_length(seconds: u32) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub(crate) fn draw_score_detail<'a>(
+    detail: &'a ScoreDetail,
+    images: &'a ImageQueue,
+) -> Element<'a, AppMessage> {
+    let score = &detail.score;
+    let breakdown = detail.breakdown.as_ref();
+    let title = match (&score.mapset, &score.map) {
+        (Some(mapset), Some(map)) => format!("{} [{}]", mapset.title, map.version),
+        _ => format!("Beatmap {}", score.map_id),
+    };
+    let artist = score
+        .mapset
+        .as_ref()
+        .map(|mapset| format!("{} // {}", mapset.artist, mapset.creator_name));
+    let cover = mapset_id(score)
+        .and_then(|id| images.get(&ImageKey::Cover(id)))
+        .map(|handle| {
+            Image::new(handle.clone())
+                .width(Length::Fill)
+                .height(Length::Fixed(COVER_HEIGHT))
+                .content_fit(ContentFit::Cover)
+        });
+    let header = cosmic::widget::row()
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .push(button::standard("Back").on_press(AppMessage::CloseScore))
+        .push(
+            cosmic::widget::column()
+                .width(Length::Fill)
+                .push(title3(title))
+                .push_maybe(artist.map(text)),
+        )
+        .push(
+            button::link("View on osu!").on_press(AppMessage::LaunchUrl(format!(
+                "https://osu.ppy.sh/scores/{}",
+                score.id
+            ))),
+        );
+
+    let max_combo = breakdown
+        .and_then(|breakdown| breakdown.as_ref().ok())
+        .map(|breakdown| breakdown.max_combo)
+        .or_else(|| score.map.as_ref().and_then(|map| map.max_combo));
+    let combo = match max_combo {
+        Some(max_combo) => format!("{}x / {}x", score.max_combo, max_combo),
+        None => format!("{}x", score.max_combo),
+    };
+    let mods = if score.mods.is_empty() {
+        "NM".to_owned()
+    } else {
+        score.mods.to_string()
+    };
+    let summary = section(
+        "Score",
+        [
+            ("Grade", format!("{:?}", score.grade)),
+            ("Score", format_number(score.score)),
+            ("Accuracy", format_accuracy(score.accuracy)),
+            ("Combo", combo),
+            ("Mods", mods),
+            (
+                "pp",
+                score
+                    .pp
+                    .map_or_else(|| "-".to_owned(), |pp| format!("{pp:.2}")),
+            ),
+            ("Set at", score.ended_at.date().to_string()),
+        ],
+    );
+    let hits = section(
+        "Hits",
+        hit_statistics(score)
+            .into_iter()
+            .map(|(name, count)| (name, format_number(count))),
+    );
+    let performance: Element<AppMessage> = match breakdown {
+        None => text("Calculating\u{2026}").into(),
+        Some(Err(e)) => text(format!("No breakdown: {e}")).into(),
+        Some(Ok(breakdown)) => section(
+            "Performance",
+            [
+                ("Stars", format!("{:.2}\u{2605}", breakdown.stars)),
+                ("Total", format!("{:.2} pp", breakdown.pp)),
+            ]
+            .into_iter()
+            .chain(
+                breakdown
+                    .parts
+                    .iter()
+                    .map(|(name, pp)| (*name, format!("{pp:.2} pp"))),
+            ),
+        ),
+    };
+    let beatmap = score.map.as_ref().map(|map| {
+        section(
+            "Beatmap",
+            [
+                ("Status", format!("{:?}", map.status)),
+                ("Stars (nomod)", format!("{:.2}\u{2605}", map.stars)),
+                ("Length", format_length(map.seconds_drain)),
+                ("BPM", format!("{:.0}", map.bpm)),
+                ("CS", format!("{:.1}", map.cs)),
+                ("AR", format!("{:.1}", map.ar)),
+                ("OD", format!("{:.1}", map.od)),
+                ("HP", format!("{:.1}", map.hp)),
+            ],
+        )
+    });
+
+    scrollable(
+        cosmic::widget::column()
+            .spacing(20)
+            .padding(20)
+            .max_width(800)
+            .push(header)
+            .push_maybe(cover)
+            .push(summary)
+            .push(hits)
+            .push(performance)
+            .push_maybe(beatmap),
+    )
+    .into()
+}
+
+/// Card with a title and label/value rows
+fn section<'a>(
+    title: &'a str,
+    rows: impl IntoIterator<Item = (&'a str, String)>,
+) -> Element<'a, AppMessage> {
+    let rows = rows.into_iter().map(|(label, value)| {
+        row![
+            text(label).width(Length::FillPortion(1)),
+            text(value).width(Length::FillPortion(1)),
+        ]
+        .into()
+    });
+    container(
+        cosmic::widget::column()
+            .spacing(6)
+            .push(title4(title))
+            .extend(rows),
+    )
+    .padding(16)
+    .width(Length::Fill)
+    .class(Container::Card)
+    .into()
+}
@@ -0,0 +1,316 @@
+//! Filters and sort order of the score pages, shared by tops, firsts and recent
+
+use std::cmp::Ordering;
+
+use cosmic::Element;
+use cosmic::iced::{Alignment, Length, Padding};
+use cosmic::widget::{button, dropdown, text_input};
+use rosu_v2::prelude::{Grade, Score};
+
+use crate::app::AppMessage;
+
+const SORT_KEYS: [&str; 6] = ["API order", "pp", "Date", "Accuracy", "Stars", "Length"];
+/// Silver grades are grouped with their gold counterparts
+const GRADES: [&str; 8] = ["Any grade", "SS", "S", "A", "B", "C", "D", "F"];
+const SHORT_INPUT_WIDTH: f32 = 110.0;
+
+#[derive(Debug, Clone)]
+pub enum FilterMessage {
+    Search(String),
+    Mods(String),
+    Grade(usize),
+    MinStars(String),
+    MaxStars(String),
+    From(String),
+    To(String),
+    Mapper(String),
+    Sort(usize),
+    ToggleDirection,
+    Clear,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// As the API sent them, e.g. by weight for top plays
+    #[default]
+    Api,
+    Pp,
+    Date,
+    Accuracy,
+    Stars,
+    Length,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 6] = [
+        SortKey::Api,
+        SortKey::Pp,
+        SortKey::Date,
+        SortKey::Accuracy,
+        SortKey::Stars,
+        SortKey::Length,
+    ];
+
+    fn compare(self, a: &Score, b: &Score) -> Ordering {
+        let stars = |score: &Score| score.map.as_ref().map_or(0.0, |map| map.stars);
+        let length = |score: &Score| score.map.as_ref().map_or(0, |map| map.seconds_drain);
+        match self {
+            SortKey::Api => Ordering::Equal,
+            SortKey::Pp => {
+                a.pp.unwrap_or_default()
+                    .total_cmp(&b.pp.unwrap_or_default())
+            }
+            SortKey::Date => a.ended_at.cmp(&b.ended_at),
+            SortKey::Accuracy => a.accuracy.total_cmp(&b.accuracy),
+            SortKey::Stars => stars(a).total_cmp(&stars(b)),
+            SortKey::Length => length(a).cmp(&length(b)),
+        }
+    }
+}
+
+/// Inputs are kept as typed, unparseable ones don't filter anything
+#[derive(PartialEq)]
+pub(crate) struct ScoreFilter {
+    search: String,
+    mods: String,
+    /// Index into [`GRADES`], 0 for any
+    grade: usize,
+    min_stars: String,
+    max_stars: String,
+    /// `YYYY-MM-DD`, or a prefix of it like `2025-03`
+    from: String,
+    to: String,
+    mapper: String,
+    sort: SortKey,
+    /// Largest first, the API order is reversed otherwise
+    descending: bool,
+}
+
+impl Default for ScoreFilter {
+    fn default() -> Self {
+        Self {
+            search: String::new(),
+            mods: String::new(),
+            grade: 0,
+            min_stars: String::new(),
+            max_stars: String::new(),
+            from: String::new(),
+            to: String::new(),
+            mapper: String::new(),
+            sort: SortKey::Api,
+            descending: true,
+        }
+    }
+}
+
+impl ScoreFilter {
+    pub fn update(&mut self, message: FilterMessage) {
+        match message {
+            FilterMessage::Search(search) => self.search = search,
+            FilterMessage::Mods(mods) => self.mods = mods,
+            FilterMessage::Grade(grade) => self.grade = grade,
+            FilterMessage::MinStars(stars) => self.min_stars = stars,
+            FilterMessage::MaxStars(stars) => self.max_stars = stars,
+            FilterMessage::From(from) => self.from = from,
+            FilterMessage::To(to) => self.to = to,
+            FilterMessage::Mapper(mapper) => self.mapper = mapper,
+            FilterMessage::Sort(index) => {
+                self.sort = SortKey::ALL.get(index).copied().unwrap_or_default();
+            }
+            FilterMessage::ToggleDirection => self.descending = !self.descending,
+            FilterMessage::Clear => *self = Self::default(),
+        }
+    }
+
+    /// Whether any filter is set, the sort order doesn't count
+    pub fn is_filtering(&self) -> bool {
+        *self
+            != Self {
+                sort: self.sort,
+                descending: self.descending,
+                ..Self::default()
+            }
+    }
+
+    /// Scores passing every filter, in the selected order
+    pub fn apply<'a>(&self, scores: &'a [Score]) -> Vec<&'a Score> {
+        let mut scores: Vec<&Score> = scores.iter().filter(|score| self.matches(score)).collect();
+        let sort = self.sort;
+        // Stable, so equal scores keep the API order
+        scores.sort_by(|a, b| sort.compare(a, b));
+        if self.descending != (sort == SortKey::Api) {
+            scores.reverse();
+        }
+        scores
+    }
+
+    fn matches(&self, score: &Score) -> bool {
+        let map = score.map.as_ref();
+        let mapset = score.mapset.as_ref();
+        let search = self.search.trim().to_lowercase();
+        if !search.is_empty() {
+            let fields = [
+                mapset.map(|mapset| mapset.title.as_str()),
+                mapset.map(|mapset| mapset.artist.as_str()),
+                map.map(|map| map.version.as_str()),
+            ];
+            let found = fields
+                .into_iter()
+                .flatten()
+                .any(|field| field.to_lowercase().contains(&search));
+            if !found {
+                return false;
+            }
+        }
+        let mapper = self.mapper.trim().to_lowercase();
+        if !mapper.is_empty() {
+            let creator = mapset.map(|mapset| mapset.creator_name.to_lowercase());
+            if !creator.is_some_and(|creator| creator.contains(&mapper)) {
+                return false;
+            }
+        }
+        if !self.matches_mods(score) || !self.matches_grade(score.grade) {
+            return false;
+        }
+        let stars = map.map(|map| map.stars);
+        let min_stars = self.min_stars.trim().parse::<f32>().ok();
+        let max_stars = self.max_stars.trim().parse::<f32>().ok();
+        if min_stars.is_some_and(|min| stars.is_none_or(|stars| stars < min))
+            || max_stars.is_some_and(|max| stars.is_none_or(|stars| stars > max))
+        {
+            return false;
+        }
+        // ISO dates compare like strings, a prefix covers a whole month or year
+        let date = score.ended_at.date().to_string();
+        let (from, to) = (self.from.trim(), self.to.trim());
+        if (!from.is_empty() && date.as_str() < from)
+            || (!to.is_empty() && date.as_str() > to && !date.starts_with(to))
+        {
+            return false;
+        }
+        true
+    }
+
+    /// Every listed mod has to be on, `NM` only matches scores without mods
+    fn matches_mods(&self, score: &Score) -> bool {
+        let wanted = self
+            .mods
+            .to_uppercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .flat_map(split_acronyms)
+            .collect::<Vec<_>>();
+        if wanted.is_empty() {
+            return true;
+        }
+        if wanted == ["NM"] {
+            return score.mods.is_empty();
+        }
+        wanted.iter().all(|acronym| {
+            score
+                .mods
+                .iter()
+                .any(|mod_| mod_.acronym().as_str() == acronym.as_str())
+        })
+    }
+
+    fn matches_grade(&self, grade: Grade) -> bool {
+        let wanted: &[Grade] = match self.grade {
+            1 => &[Grade::X, Grade::XH],
+            2 => &[Grade::S, Grade::SH],
+            3 => &[Grade::A],
+            4 => &[Grade::B],
+            5 => &[Grade::C],
+            6 => &[Grade::D],
+            7 => &[Grade::F],
+            _ => return true,
+        };
+        wanted.contains(&grade)
+    }
+}
+
+/// `HDDT` into `HD` and `DT`. Acronyms longer than two letters have to be separated
+fn split_acronyms(word: &str) -> Vec<String> {
+    match word.len() {
+        0 => Vec::new(),
+        len if len % 2 == 0 => word
+            .as_bytes()
+            .chunks(2)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+            .collect(),
+        _ => vec![word.to_owned()],
+    }
+}
+
+/// Search, filter and sort controls above a score list
+pub(crate) fn draw_filters(filter: &ScoreFilter) -> Element<AppMessage> {
+    let message = AppMessage::Filter;
+    let direction = if filter.descending {
+        "\u{2193}"
+    } else {
+        "\u{2191}"
+    };
+    let sort = SortKey::ALL.iter().position(|key| *key == filter.sort);
+    let search = cosmic::widget::row()
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .push(
+            text_input("Search title, artist or difficulty", &filter.search)
+                .on_input(move |search| message(FilterMessage::Search(search)))
+                .width(Length::Fill),
+        )
+        .push(dropdown(&SORT_KEYS, sort, move |index| {
+            message(FilterMessage::Sort(index))
+        }))
+        .push(button::standard(direction).on_press(message(FilterMessage::ToggleDirection)))
+        .push(
+            button::standard("Clear")
+                .on_press_maybe(filter.is_filtering().then(|| message(FilterMessage::Clear))),
+        );
+    let filters = cosmic::widget::row()
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .push(short_input("Mods", &filter.mods, FilterMessage::Mods))
+        .push(dropdown(&GRADES, Some(filter.grade), move |index| {
+            message(FilterMessage::Grade(index))
+        }))
+        .push(short_input(
+            "Min \u{2605}",
+            &filter.min_stars,
+            FilterMessage::MinStars,
+        ))
+        .push(short_input(
+            "Max \u{2605}",
+            &filter.max_stars,
+            FilterMessage::MaxStars,
+        ))
+        .push(short_input("From", &filter.from, FilterMessage::From))
+        .push(short_input("To", &filter.to, FilterMessage::To))
+        .push(
+            text_input("Mapper", &filter.mapper)
+                .on_input(move |mapper| message(FilterMessage::Mapper(mapper)))
+                .width(Length::Fill),
+        );
+    cosmic::widget::column()
+        .spacing(10)
+        .padding(Padding {
+            top: 10.0,
+            right: 20.0,
+            bottom: 10.0,
+            left: 10.0,
+        })
+        .max_width(800)
+        .push(search)
+        .push(filters)
+        .into()
+}
+
+fn short_input<'a>(
+    placeholder: &'static str,
+    value: &'a str,
+    on_input: fn(String) -> FilterMessage,
+) -> Element<'a, AppMessage> {
+    text_input(placeholder, value)
+        .on_input(move |input| AppMessage::Filter(on_input(input)))
+        .width(Length::Fixed(SHORT_INPUT_WIDTH))
+        .into()
+}
@@ -0,0 +1,302 @@
+//! Settings page, edits a draft of the config which is only written once it's saved
+
+use constants::{HOST, MAX_RECENT_LIMIT, MIN_POLL_INTERVAL, OAUTH_LOGIN_ENDPOINT};
+use cosmic::Element;
+use cosmic::iced::{Alignment, Length};
+use cosmic::prelude::CollectionWidget;
+use cosmic::widget::{
+    button, dropdown, scrollable, secure_input, settings, text, text_input, toggler,
+};
+use server::oauth::redirect_uri;
+use types::Mode;
+
+use crate::app::AppMessage;
+use crate::config::Config as AppConfig;
+
+const INPUT_WIDTH: f32 = 280.0;
+/// The first entry leaves the mode up to the user's profile
+const MODES: [&str; 5] = [
+    "Profile default",
+    "osu!",
+    "osu!taiko",
+    "osu!catch",
+    "osu!mania",
+];
+
+#[derive(Debug, Clone)]
+pub enum SettingsMessage {
+    ClientId(String),
+    ClientSecret(String),
+    Username(String),
+    Mode(usize),
+    Port(String),
+    PollInterval(String),
+    RecentLimit(String),
+    IncludeFails(bool),
+    CompareUsers(String),
+    NotifyTopPlays(bool),
+    NotifyRank(bool),
+    ToggleSecret,
+    Save,
+    Revert,
+}
+
+/// Unsaved state of the settings page
+pub(crate) struct SettingsForm {
+    client_id: String,
+    client_secret: String,
+    username: String,
+    mode: Option<Mode>,
+    port: String,
+    poll_interval: String,
+    recent_limit: String,
+    include_fails: bool,
+    /// Comma separated user ids
+    compare_users: String,
+    notify_top_plays: bool,
+    notify_rank: bool,
+    secret_hidden: bool,
+    /// Why the last save failed
+    pub error: Option<String>,
+}
+
+impl Default for SettingsForm {
+    fn default() -> Self {
+        Self::new(&AppConfig::default(), "")
+    }
+}
+
+impl SettingsForm {
+    /// The secret is kept out of the config, see [`crate::config::take_secret`]
+    pub fn new(config: &AppConfig, secret: &str) -> Self {
+        Self {
+            client_id: config.user_client.clone(),
+            client_secret: secret.to_owned(),
+            username: config.tracked_user_name.clone(),
+            mode: config.mode,
+            port: config.port.to_string(),
+            poll_interval: config.poll_interval.to_string(),
+            recent_limit: config.recent_limit.to_string(),
+            include_fails: config.include_fails,
+            compare_users: config
+                .compare_users
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            notify_top_plays: config.notify_top_plays,
+            notify_rank: config.notify_rank,
+            secret_hidden: true,
+            error: None,
+        }
+    }
+
+    /// Apply an edit. `Save` and `Revert` are left to the caller, who owns the config
+    pub fn update(&mut self, message: SettingsMessage) {
+        match message {
+            SettingsMessage::ClientId(id) => self.client_id = id,
+            SettingsMessage::ClientSecret(secret) => self.client_secret = secret,
+            SettingsMessage::Username(username) => self.username = username,
+            SettingsMessage::Mode(index) => {
+                self.mode = index
+                    .checked_sub(1)
+                    .and_then(|index| Mode::ALL.get(index).copied());
+            }
+            SettingsMessage::Port(port) => self.port = port,
+            SettingsMessage::PollInterval(interval) => self.poll_interval = interval,
+            SettingsMessage::RecentLimit(limit) => self.recent_limit = limit,
+            SettingsMessage::IncludeFails(include) => self.include_fails = include,
+            SettingsMessage::CompareUsers(users) => self.compare_users = users,
+            SettingsMessage::NotifyTopPlays(notify) => self.notify_top_plays = notify,
+            SettingsMessage::NotifyRank(notify) => self.notify_rank = notify,
+            SettingsMessage::ToggleSecret => self.secret_hidden = !self.secret_hidden,
+            SettingsMessage::Save | SettingsMessage::Revert => {}
+        }
+    }
+
+    pub fn secret(&self) -> &str {
+        self.client_secret.trim()
+    }
+
+    /// The config this form describes, without the secret, or why it isn't valid
+    pub fn to_config(&self) -> Result<AppConfig, String> {
+        let client_id = self.client_id.trim();
+        if !client_id.is_empty() && client_id.parse::<u64>().is_err() {
+            return Err("The client ID has to be a number".to_owned());
+        }
+        let port = match self.port.trim().parse::<u16>() {
+            Ok(port) if port != 0 => port,
+            _ => return Err("The port has to be a number between 1 and 65535".to_owned()),
+        };
+        let poll_interval = match self.poll_interval.trim().parse::<u64>() {
+            Ok(interval) if interval >= MIN_POLL_INTERVAL => interval,
+            _ => {
+                return Err(format!(
+                    "The poll interval has to be at least {MIN_POLL_INTERVAL} seconds"
+                ));
+            }
+        };
+        let recent_limit = match self.recent_limit.trim().parse::<usize>() {
+            Ok(limit) if (1..=MAX_RECENT_LIMIT).contains(&limit) => limit,
+            _ => {
+                return Err(format!(
+                    "The recent score limit has to be between 1 and {MAX_RECENT_LIMIT}"
+                ));
+            }
+        };
+        let compare_users = self
+            .compare_users
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|id| !id.is_empty())
+            .map(str::parse::<u32>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "Compared players have to be user ids, separated by commas".to_owned())?;
+        Ok(AppConfig {
+            user_client: client_id.to_owned(),
+            user_secret: String::new(),
+            tracked_user_name: self.username.trim().to_owned(),
+            mode: self.mode,
+            port,
+            poll_interval,
+            recent_limit,
+            include_fails: self.include_fails,
+            compare_users,
+            notify_top_plays: self.notify_top_plays,
+            notify_rank: self.notify_rank,
+        })
+    }
+
+    /// Whether the form differs from the saved config and secret
+    pub fn is_dirty(&self, config: &AppConfig, secret: &str) -> bool {
+        let saved = AppConfig {
+            user_secret: String::new(),
+            ..config.clone()
+        };
+        self.to_config().as_ref() != Ok(&saved) || self.secret() != secret
+    }
+}
+
+pub(crate) fn draw_settings<'a>(
+    form: &'a SettingsForm,
+    config: &AppConfig,
+    secret: &str,
+) -> Element<'a, AppMessage> {
+    let message = AppMessage::Settings;
+    let credentials = settings::section()
+        .title("osu!api")
+        .add(settings::item(
+            "Client ID",
+            text_input("12345", &form.client_id)
+                .on_input(move |id| message(SettingsMessage::ClientId(id)))
+                .width(Length::Fixed(INPUT_WIDTH)),
+        ))
+        .add(settings::item(
+            "Client secret",
+            secure_input(
+                "",
+                &form.client_secret,
+                Some(message(SettingsMessage::ToggleSecret)),
+                form.secret_hidden,
+            )
+            .on_input(move |secret| message(SettingsMessage::ClientSecret(secret)))
+            .width(Length::Fixed(INPUT_WIDTH)),
+        ));
+    // Served by the tracker, so these use the saved port
+    let login = settings::section()
+        .title("osu! login")
+        .add(settings::item(
+            "Log in",
+            text(format!(
+                "http://{HOST}:{}{OAUTH_LOGIN_ENDPOINT}",
+                config.port
+            )),
+        ))
+        .add(settings::item(
+            "Application callback URL",
+            text(redirect_uri(config.port)),
+        ));
+    let selected_mode = form
+        .mode
+        .and_then(|mode| Mode::ALL.iter().position(|other| *other == mode))
+        .map_or(0, |index| index + 1);
+    let tracking = settings::section()
+        .title("Tracking")
+        .add(settings::item(
+            "Username",
+            text_input("Nobody", &form.username)
+                .on_input(move |username| message(SettingsMessage::Username(username)))
+                .width(Length::Fixed(INPUT_WIDTH)),
+        ))
+        .add(settings::item(
+            "Mode",
+            dropdown(&MODES, Some(selected_mode), move |index| {
+                message(SettingsMessage::Mode(index))
+            }),
+        ))
+        .add(settings::item(
+            "Poll interval (seconds)",
+            text_input("5", &form.poll_interval)
+                .on_input(move |interval| message(SettingsMessage::PollInterval(interval)))
+                .width(Length::Fixed(INPUT_WIDTH)),
+        ))
+        .add(settings::item(
+            "Recent scores",
+            text_input("20", &form.recent_limit)
+                .on_input(move |limit| message(SettingsMessage::RecentLimit(limit)))
+                .width(Length::Fixed(INPUT_WIDTH)),
+        ))
+        .add(settings::item(
+            "Include failed plays",
+            toggler(form.include_fails)
+                .on_toggle(move |include| message(SettingsMessage::IncludeFails(include))),
+        ))
+        .add(settings::item(
+            "Compared players (user ids)",
+            text_input("2, 124493", &form.compare_users)
+                .on_input(move |users| message(SettingsMessage::CompareUsers(users)))
+                .width(Length::Fixed(INPUT_WIDTH)),
+        ))
+        .add(settings::item(
+            "Server port",
+            text_input("7272", &form.port)
+                .on_input(move |port| message(SettingsMessage::Port(port)))
+                .width(Length::Fixed(INPUT_WIDTH)),
+        ));
+    let notifications = settings::section()
+        .title("Notifications")
+        .add(settings::item(
+            "New top plays",
+            toggler(form.notify_top_plays)
+                .on_toggle(move |notify| message(SettingsMessage::NotifyTopPlays(notify))),
+        ))
+        .add(settings::item(
+            "Global rank changes",
+            toggler(form.notify_rank)
+                .on_toggle(move |notify| message(SettingsMessage::NotifyRank(notify))),
+        ));
+    let dirty = form.is_dirty(config, secret);
+    let actions = cosmic::widget::row()
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .push_maybe(form.error.as_deref().map(text))
+        .push(cosmic::widget::horizontal_space())
+        .push(
+            button::standard("Revert")
+                .on_press_maybe(dirty.then(|| message(SettingsMessage::Revert))),
+        )
+        .push(
+            button::suggested("Save").on_press_maybe(dirty.then(|| message(SettingsMessage::Save))),
+        );
+    scrollable(
+        cosmic::widget::column()
+            .spacing(20)
+            .padding(20)
+            .max_width(800)
+            .push(credentials)
+            .push(login)
+            .push(tracking)
+            .push(notifications)
+            .push(actions),
+    )
+    .into()
+}
@@ -6,30 +6,50 @@ use cosmic::iced::{
     stream,
 };
 use futures_util::{Stream, StreamExt};
-use rosu_v2::prelude::{Score, UserExtended};
+use rosu_v2::prelude::{MostPlayedMap, Score, UserExtended};
 use serde::Deserialize;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use types::payload::{ComparePayload, CompareSummary};
 
-use constants::{FIRSTS_URI, RECENT_URI, TOPS_URI, USER_URI};
+use constants::{
+    BASE_URI, COMPARE_ENDPOINT, FIRSTS_ENDPOINT, MOST_PLAYED_ENDPOINT, PINNED_ENDPOINT,
+    RAW_FORMAT_QUERY, RECENT_ENDPOINT, TOPS_ENDPOINT, USER_ENDPOINT,
+};
 
 /// Create a stream of `UserExtended` messages
-pub fn connect_user() -> impl Stream<Item = Event> {
-    connect_websocket::<UserExtended, User>(USER_URI)
+pub fn connect_user(port: u16) -> impl Stream<Item = Event> {
+    connect_websocket::<UserExtended, User>(uri(port, USER_ENDPOINT))
 }
 /// Create a stream of user first place scores
-pub fn connect_firsts() -> impl Stream<Item = Event> {
-    connect_websocket::<Vec<Score>, Firsts>(FIRSTS_URI)
+pub fn connect_firsts(port: u16) -> impl Stream<Item = Event> {
+    connect_websocket::<Vec<Score>, Firsts>(uri(port, FIRSTS_ENDPOINT))
 }
 /// Create a stream of user top scores
-pub fn connect_tops() -> impl Stream<Item = Event> {
-    connect_websocket::<Vec<Score>, Tops>(TOPS_URI)
+pub fn connect_tops(port: u16) -> impl Stream<Item = Event> {
+    connect_websocket::<Vec<Score>, Tops>(uri(port, TOPS_ENDPOINT))
+}
+pub fn connect_recent(port: u16) -> impl Stream<Item = Event> {
+    connect_websocket::<Vec<Score>, Recent>(uri(port, RECENT_ENDPOINT))
+}
+/// Create a stream of user pinned scores
+pub fn connect_pinned(port: u16) -> impl Stream<Item = Event> {
+    connect_websocket::<Vec<Score>, Pinned>(uri(port, PINNED_ENDPOINT))
+}
+/// Create a stream of the user's most played beatmaps
+pub fn connect_most_played(port: u16) -> impl Stream<Item = Event> {
+    connect_websocket::<Vec<MostPlayedMap>, MostPlayed>(uri(port, MOST_PLAYED_ENDPOINT))
 }
-pub fn connect_recent() -> impl Stream<Item = Event> {
-    connect_websocket::<Vec<Score>, Recent>(RECENT_URI)
+/// Create a stream of the comparison with other players
+pub fn connect_compare(port: u16) -> impl Stream<Item = Event> {
+    connect_websocket::<ComparePayload, Compare>(uri(port, COMPARE_ENDPOINT))
+}
+/// The GUI deserializes rosu-v2 types, so it asks for the raw format
+fn uri(port: u16, endpoint: &str) -> String {
+    format!("{BASE_URI}:{port}{endpoint}{RAW_FORMAT_QUERY}")
 }
 /// General websocket stream creation. `U` should be a newtype over `T`
-fn connect_websocket<T, U>(uri: &str) -> impl Stream<Item = Event> + use<'_, T, U>
+fn connect_websocket<T, U>(uri: String) -> impl Stream<Item = Event> + use<T, U>
 where
     T: for<'a> Deserialize<'a>,
     U: IntoMessage<T>,
@@ -39,7 +59,7 @@ where
 
         loop {
             match &mut state {
-                State::Disconnected => match connect_async(uri).await {
+                State::Disconnected => match connect_async(uri.as_str()).await {
                     Ok((websocket, _)) => {
                         let (sender, _) = mpsc::channel(100);
 
@@ -97,6 +117,9 @@ pub enum Message {
     Tops(Vec<Score>),
     Firsts(Vec<Score>),
     Recent(Vec<Score>),
+    Pinned(Vec<Score>),
+    MostPlayed(Vec<MostPlayedMap>),
+    Compare(Option<Box<CompareSummary>>),
 }
 /// Workaround to allow type-level difference between user tops and user firsts
 trait IntoMessage<T> {
@@ -127,6 +150,24 @@ impl IntoMessage<Vec<Score>> for Recent {
     }
 }
 
+impl IntoMessage<Vec<Score>> for Pinned {
+    fn into_message(value: Vec<Score>) -> Message {
+        Message::Pinned(value)
+    }
+}
+
+impl IntoMessage<Vec<MostPlayedMap>> for MostPlayed {
+    fn into_message(value: Vec<MostPlayedMap>) -> Message {
+        Message::MostPlayed(value)
+    }
+}
+
+impl IntoMessage<ComparePayload> for Compare {
+    fn into_message(value: ComparePayload) -> Message {
+        Message::Compare(value.map(Box::new))
+    }
+}
+
 struct User(UserExtended);
 
 impl From<User> for UserExtended {
@@ -157,6 +198,28 @@ impl From<Recent> for Vec<Score> {
         val.0
     }
 }
+struct Pinned(Vec<Score>);
+
+impl From<Pinned> for Vec<Score> {
+    fn from(val: Pinned) -> Self {
+        val.0
+    }
+}
+
+struct MostPlayed(Vec<MostPlayedMap>);
+
+impl From<MostPlayed> for Vec<MostPlayedMap> {
+    fn from(val: MostPlayed) -> Self {
+        val.0
+    }
+}
+struct Compare(ComparePayload);
+
+impl From<Compare> for ComparePayload {
+    fn from(val: Compare) -> Self {
+        val.0
+    }
+}
 #[allow(dead_code, reason = "WIP")]
 #[derive(Debug, Clone)]
 pub struct Connection(mpsc::Sender<Message>);
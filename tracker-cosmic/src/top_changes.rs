@@ -0,0 +1,208 @@
+//! How the top plays changed since the session started.
+//!
+//! Plays are matched by score id. A play's weighted pp is its pp times 0.95 to the power of its
+//! index, like osu! weighs them for the profile pp
+
+use cosmic::Element;
+use cosmic::iced::{Alignment, Color, Length};
+use cosmic::prelude::CollectionWidget;
+use cosmic::theme::Container;
+use cosmic::widget::text::{title3, title4};
+use cosmic::widget::{button, container, scrollable, text};
+use rosu_v2::prelude::Score;
+
+use crate::app::AppMessage;
+
+const WEIGHT: f32 = 0.95;
+
+fn weighted(score: &Score, index: usize) -> f32 {
+    score.pp.unwrap_or_default() * WEIGHT.powi(index as i32)
+}
+
+/// A play which is or was in the top plays
+pub(crate) struct TopChange<'a> {
+    pub score: &'a Score,
+    /// Zero based, `None` if the play isn't in that list
+    pub before: Option<usize>,
+    pub now: Option<usize>,
+    /// Change of the weighted pp this play contributes
+    pub weighted_delta: f32,
+}
+
+/// Changes of the top plays, grouped by kind. Unchanged plays are left out
+#[derive(Default)]
+pub(crate) struct TopChanges<'a> {
+    pub new: Vec<TopChange<'a>>,
+    pub moved_down: Vec<TopChange<'a>>,
+    pub moved_up: Vec<TopChange<'a>>,
+    pub dropped: Vec<TopChange<'a>>,
+    /// Sum of the weighted pp of all top plays, before and now
+    pub weighted_before: f32,
+    pub weighted_now: f32,
+}
+
+impl<'a> TopChanges<'a> {
+    /// `None` if the lists belong to different users or modes, e.g. after switching users
+    pub fn new(initial: &'a [Score], current: &'a [Score]) -> Option<Self> {
+        let comparable = initial
+            .first()
+            .zip(current.first())
+            .is_none_or(|(old, new)| old.user_id == new.user_id && old.mode == new.mode);
+        if !comparable {
+            return None;
+        }
+        let position = |scores: &[Score], id: u64| scores.iter().position(|score| score.id == id);
+        let mut changes = Self {
+            weighted_before: initial
+                .iter()
+                .enumerate()
+                .map(|(i, s)| weighted(s, i))
+                .sum(),
+            weighted_now: current
+                .iter()
+                .enumerate()
+                .map(|(i, s)| weighted(s, i))
+                .sum(),
+            ..Self::default()
+        };
+        for (now, score) in current.iter().enumerate() {
+            let before = position(initial, score.id);
+            let change = TopChange {
+                score,
+                before,
+                now: Some(now),
+                weighted_delta: weighted(score, now)
+                    - before.map_or(0.0, |before| weighted(&initial[before], before)),
+            };
+            match before {
+                None => changes.new.push(change),
+                Some(before) if before < now => changes.moved_down.push(change),
+                Some(before) if before > now => changes.moved_up.push(change),
+                Some(_) => {}
+            }
+        }
+        for (before, score) in initial.iter().enumerate() {
+            if position(current, score.id).is_none() {
+                changes.dropped.push(TopChange {
+                    score,
+                    before: Some(before),
+                    now: None,
+                    weighted_delta: -weighted(score, before),
+                });
+            }
+        }
+        Some(changes)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.new.is_empty()
+            && self.moved_down.is_empty()
+            && self.moved_up.is_empty()
+            && self.dropped.is_empty()
+    }
+}
+
+pub(crate) fn draw_top_changes<'a>(changes: &TopChanges<'a>) -> Element<'a, AppMessage> {
+    let delta = changes.weighted_now - changes.weighted_before;
+    let summary = text(format!(
+        "Weighted pp of the top plays: {:.2} \u{2192} {:.2} ({delta:+.2})",
+        changes.weighted_before, changes.weighted_now
+    ));
+    let header = cosmic::widget::row()
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .push(title3("Since the session started").width(Length::Fill))
+        .push(button::standard("Reset session").on_press(AppMessage::ResetSession));
+    let body = if changes.is_empty() {
+        cosmic::widget::column().push(text("No changes yet"))
+    } else {
+        cosmic::widget::column()
+            .spacing(20)
+            .push_maybe(section("New plays", &changes.new, true))
+            .push_maybe(section("Moved down", &changes.moved_down, false))
+            .push_maybe(section("Moved up", &changes.moved_up, false))
+            .push_maybe(section(
+                "Dropped out of the top plays",
+                &changes.dropped,
+                false,
+            ))
+    };
+    scrollable(
+        cosmic::widget::column()
+            .spacing(20)
+            .padding(20)
+            .max_width(800)
+            .push(header)
+            .push(summary)
+            .push(body),
+    )
+    .into()
+}
+
+/// `None` for an empty group
+fn section<'a>(
+    title: &'static str,
+    changes: &[TopChange<'a>],
+    highlight: bool,
+) -> Option<Element<'a, AppMessage>> {
+    if changes.is_empty() {
+        return None;
+    }
+    let rows = changes.iter().map(|change| draw_change(change, highlight));
+    Some(
+        cosmic::widget::column()
+            .spacing(8)
+            .push(title4(title))
+            .extend(rows)
+            .into(),
+    )
+}
+
+fn draw_change<'a>(change: &TopChange<'a>, highlight: bool) -> Element<'a, AppMessage> {
+    let score = change.score;
+    let title = match (&score.mapset, &score.map) {
+        (Some(mapset), Some(map)) => format!("{} [{}]", mapset.title, map.version),
+        _ => format!("Beatmap {}", score.map_id),
+    };
+    let position = match (change.before, change.now) {
+        (None, Some(now)) => format!("#{}", now + 1),
+        (Some(before), Some(now)) => format!("#{} \u{2192} #{}", before + 1, now + 1),
+        (Some(before), None) => format!("was #{}", before + 1),
+        (None, None) => String::new(),
+    };
+    let row = cosmic::widget::row()
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .push(text(position).width(Length::Fixed(100.0)))
+        .push(
+            cosmic::widget::button::custom(text(title))
+                .class(cosmic::theme::Button::Link)
+                .on_press_maybe(change.now.map(|_| AppMessage::ShowScore(score.id)))
+                .padding(0)
+                .width(Length::Fill),
+        )
+        .push(text(format!("{:.0}pp", score.pp.unwrap_or_default())))
+        .push(
+            text(format!("{:+.2} weighted", change.weighted_delta))
+                .width(Length::Fixed(120.0))
+                .align_x(cosmic::iced::alignment::Horizontal::Right),
+        );
+    let card = container(row).padding(10).width(Length::Fill);
+    if !highlight {
+        return card.class(Container::Card).into();
+    }
+    card.class(Container::custom(|theme| {
+        let cosmic = theme.cosmic();
+        container::Style {
+            text_color: Some(cosmic.background.on.into()),
+            background: Some(Color::from(cosmic.background.component.base).into()),
+            border: cosmic::iced::Border {
+                radius: cosmic.corner_radii.radius_s.into(),
+                width: 2.0,
+                color: cosmic.accent.base.into(),
+            },
+            ..Default::default()
+        }
+    }))
+    .into()
+}
@@ -1,10 +1,142 @@
+use constants::{
+    DEFAULT_POLL_INTERVAL, DEFAULT_PORT, DEFAULT_RECENT_LIMIT, MAX_RECENT_LIMIT, MIN_POLL_INTERVAL,
+};
+use rosu_v2::prelude::GameMode;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub mod payload;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
 pub struct Api {
     pub id: String,
     pub secret: String,
     pub username: String,
+    /// Ruleset to track, the user's default one if unset
+    #[serde(default)]
+    pub mode: Option<Mode>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Osu,
+    Taiko,
+    Catch,
+    Mania,
+}
+
+impl Mode {
+    pub const ALL: [Self; 4] = [Self::Osu, Self::Taiko, Self::Catch, Self::Mania];
+}
+
+impl From<Mode> for GameMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Osu => GameMode::Osu,
+            Mode::Taiko => GameMode::Taiko,
+            Mode::Catch => GameMode::Catch,
+            Mode::Mania => GameMode::Mania,
+        }
+    }
+}
+
+/// Full tracker configuration, as stored in `config.toml`
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    #[serde(flatten)]
+    pub api: Api,
+    /// Where the tracking session is persisted between runs.
+    /// Defaults to the platform data directory
+    #[serde(default)]
+    pub state_file: Option<String>,
+    /// Bearer token required by the `/control/*` endpoints.
+    /// The control API is disabled if this isn't set
+    #[serde(default)]
+    pub control_token: Option<String>,
+    #[serde(default)]
+    pub access: Access,
+    /// Text files rendered on every change, e.g. for OBS text sources
+    #[serde(default)]
+    pub sinks: Vec<FileSink>,
+    /// Directory with HTML/CSS overlay templates served under `/overlay/`.
+    /// Defaults to `overlays` in the platform config directory
+    #[serde(default)]
+    pub overlay_dir: Option<String>,
+    /// Port the server listens on, 7272 by default
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Seconds between fetches, 5 by default and at least 2
+    #[serde(default)]
+    pub poll_interval: Option<u64>,
+    /// Other players compared with the tracked user on `/compare`, by user id
+    #[serde(default)]
+    pub compare_users: Vec<u32>,
+    /// Recent scores fetched, 20 by default and at most 100
+    #[serde(default)]
+    pub recent_limit: Option<usize>,
+    /// Whether failed plays are part of the recent scores, they aren't by default
+    #[serde(default)]
+    pub include_fails: Option<bool>,
+}
+
+impl Config {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    pub fn poll_interval(&self) -> u64 {
+        self.poll_interval
+            .unwrap_or(DEFAULT_POLL_INTERVAL)
+            .max(MIN_POLL_INTERVAL)
+    }
+
+    pub fn recent_limit(&self) -> usize {
+        self.recent_limit
+            .unwrap_or(DEFAULT_RECENT_LIMIT)
+            .clamp(1, MAX_RECENT_LIMIT)
+    }
+
+    pub fn include_fails(&self) -> bool {
+        self.include_fails.unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct FileSink {
+    pub path: String,
+    /// e.g. `{pp:,.2} pp ({session_pp_gain:+.2}) #{global_rank:,}`
+    pub template: String,
+}
+
+/// Who is allowed to connect to the websocket endpoints
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Access {
+    /// Tokens accepted by the server. Every channel is public if this is empty
+    #[serde(default)]
+    pub tokens: Vec<AccessToken>,
+    /// Browser origins allowed to connect, e.g. `http://localhost:8080`.
+    /// Any origin is allowed if this is empty
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct AccessToken {
+    pub token: String,
+    /// Channels this token can subscribe to (`user`, `tops`, `firsts`, `recent`, `pinned`,
+    /// `most-played`, `grind`, `compare`, `overlay`, and `oauth` for the login).
+    /// All of them if unset
+    #[serde(default)]
+    pub channels: Option<Vec<String>>,
+}
+
+impl From<Api> for Config {
+    fn from(api: Api) -> Self {
+        Self {
+            api,
+            ..Default::default()
+        }
+    }
 }
 
 pub enum Either<L, R> {
@@ -0,0 +1,434 @@
+//! Versioned payloads sent to websocket and event stream clients.
+//!
+//! These are decoupled from rosu-v2, so upstream changes don't break overlays.
+//! Bump [`SCHEMA_VERSION`] whenever a field is removed, renamed or changes its meaning,
+//! adding optional fields is fine without a bump
+
+use std::collections::BTreeMap;
+
+use rosu_v2::prelude::{
+    BeatmapExtended, BeatmapsetExtended, GameMode, MostPlayedMap, Score, UserExtended,
+    UserStatistics,
+};
+use schemars::{JsonSchema, Schema, schema_for};
+use serde::{Deserialize, Serialize};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Every payload is wrapped in this
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Payload<T> {
+    /// Version of the payload format, see `/schema`
+    pub schema_version: u32,
+    pub data: T,
+}
+
+impl<T> Payload<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            data,
+        }
+    }
+}
+
+/// Document served at `/schema`
+#[derive(Serialize, Debug)]
+pub struct SchemaDocument {
+    pub schema_version: u32,
+    /// JSON Schema of the payloads of every channel, by channel name
+    pub channels: BTreeMap<&'static str, Schema>,
+}
+
+impl SchemaDocument {
+    pub fn new() -> Self {
+        let scores = schema_for!(Payload<ScoresPayload>);
+        let channels = BTreeMap::from([
+            ("user", schema_for!(Payload<Option<UserPayload>>)),
+            ("tops", scores.clone()),
+            ("firsts", scores.clone()),
+            ("recent", scores.clone()),
+            ("pinned", scores),
+            ("most-played", schema_for!(Payload<MostPlayedPayload>)),
+            ("grind", schema_for!(Payload<GrindPayload>)),
+            ("compare", schema_for!(Payload<ComparePayload>)),
+        ]);
+        Self {
+            schema_version: SCHEMA_VERSION,
+            channels,
+        }
+    }
+}
+
+impl Default for SchemaDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Payload of the `user` channel
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct UserPayload {
+    pub profile: Profile,
+    /// Missing until the first fetch of a session
+    pub session: Option<SessionSummary>,
+}
+
+/// Payload of the `tops`, `firsts`, `recent` and `pinned` channels
+pub type ScoresPayload = Vec<ScoreSummary>;
+
+/// Payload of the `most-played` channel, most played first
+pub type MostPlayedPayload = Vec<MostPlayedSummary>;
+
+/// Payload of the `grind` channel, missing until the first recent play
+pub type GrindPayload = Option<GrindSummary>;
+
+/// Payload of the `compare` channel, missing until there's someone to compare with
+pub type ComparePayload = Option<CompareSummary>;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub user_id: u32,
+    pub username: String,
+    /// Two letter country code, e.g. `DE`
+    pub country_code: String,
+    pub avatar_url: String,
+    /// `osu`, `taiko`, `catch` or `mania`
+    pub mode: String,
+    pub statistics: Option<Statistics>,
+    /// Best global rank of the last 90 days
+    pub peak_rank: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Statistics {
+    pub pp: f32,
+    /// In percent, e.g. `98.76`
+    pub accuracy: f32,
+    pub global_rank: Option<u32>,
+    pub country_rank: Option<u32>,
+    pub playcount: u32,
+    /// In seconds
+    pub playtime: u32,
+    pub ranked_score: u64,
+    pub total_score: u64,
+    pub total_hits: u32,
+    pub max_combo: u32,
+    /// Level with progress towards the next one, e.g. `101.42`
+    pub level: f32,
+    pub grades: GradeCounts,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct GradeCounts {
+    pub ssh: i32,
+    pub ss: i32,
+    pub sh: i32,
+    pub s: i32,
+    pub a: i32,
+}
+
+/// Where the tracked user was when the session started
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct SessionSummary {
+    /// Unix timestamp, in seconds
+    pub started_at: u64,
+    pub initial: Profile,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ScoreSummary {
+    pub score_id: u64,
+    pub user_id: u32,
+    /// `osu`, `taiko`, `catch` or `mania`
+    pub mode: String,
+    pub pp: Option<f32>,
+    /// In percent, e.g. `98.76`
+    pub accuracy: f32,
+    /// `XH`, `X`, `SH`, `S`, `A`, `B`, `C`, `D` or `F`
+    pub grade: String,
+    pub score: u32,
+    pub max_combo: u32,
+    pub perfect_combo: bool,
+    /// `false` for failed plays, which only show up in `recent`
+    pub passed: bool,
+    /// How much of the beatmap was played, in percent. Missing if that can't be told,
+    /// e.g. for osu!catch
+    pub completion: Option<f32>,
+    /// Mod acronyms, e.g. `["HD", "DT"]`
+    pub mods: Vec<String>,
+    /// Unix timestamp, in seconds
+    pub ended_at: i64,
+    /// Share of the pp which counts towards the profile, only set for top plays
+    pub weight: Option<f32>,
+    pub beatmap: Option<BeatmapSummary>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct BeatmapSummary {
+    pub map_id: u32,
+    pub mapset_id: u32,
+    pub artist: String,
+    pub title: String,
+    /// Difficulty name
+    pub version: String,
+    pub creator: String,
+    pub stars: f32,
+    /// In seconds
+    pub length: u32,
+    pub bpm: f32,
+    pub cover_url: String,
+}
+
+/// Consecutive plays on one beatmap, without a long break in between
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct GrindSummary {
+    pub beatmap: Option<BeatmapSummary>,
+    /// Plays so far, including the first one
+    pub attempts: u32,
+    /// Plays after the first one
+    pub retries: u32,
+    pub passes: u32,
+    /// Unix timestamp of the first play, in seconds
+    pub started_at: i64,
+    /// Best accuracy of a pass, missing while every play failed
+    pub best_accuracy: Option<f32>,
+    pub best_combo: u32,
+    pub best_pp: Option<f32>,
+    /// Furthest any play got into the beatmap, in percent
+    pub best_completion: Option<f32>,
+    /// Mean accuracy of the last five plays minus that of the five before, in percentage points.
+    /// Fewer plays are compared early on, missing below two plays. Positive when improving
+    pub trend: Option<f32>,
+    /// The last 100 plays, oldest first
+    pub history: Vec<AttemptSummary>,
+}
+
+/// The tracked user side by side with the players from `compare_users`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct CompareSummary {
+    /// The tracked user first, then the others as configured
+    pub players: Vec<ComparedPlayer>,
+    pub rankings: Rankings,
+    /// Beatmaps in the top plays of at least two players, shared by the most players first
+    pub common_beatmaps: Vec<CommonBeatmap>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ComparedPlayer {
+    pub profile: Profile,
+    /// Whether this is the tracked user
+    pub tracked: bool,
+    pub session: SessionGains,
+}
+
+/// Changes since the session started
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Default)]
+pub struct SessionGains {
+    pub pp: f32,
+    /// In percentage points
+    pub accuracy: f32,
+    pub playcount: i64,
+    pub ranked_score: i64,
+    /// Positive when climbing, missing for unranked players
+    pub global_rank: Option<i64>,
+}
+
+/// User ids of the players, best first
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Default)]
+pub struct Rankings {
+    pub pp: Vec<u32>,
+    pub accuracy: Vec<u32>,
+    pub session_pp_gain: Vec<u32>,
+    /// By plays since the session started
+    pub session_playcount: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct CommonBeatmap {
+    pub beatmap: Option<BeatmapSummary>,
+    /// Best pp first
+    pub scores: Vec<ComparedScore>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ComparedScore {
+    pub username: String,
+    /// Position in the player's top plays, starting at 1
+    pub top_position: u32,
+    pub score: ScoreSummary,
+}
+
+/// One play of a grind
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct AttemptSummary {
+    /// Starts at 1
+    pub attempt: u32,
+    pub score_id: u64,
+    /// In percent, e.g. `98.76`
+    pub accuracy: f32,
+    pub max_combo: u32,
+    pub pp: Option<f32>,
+    /// `XH`, `X`, `SH`, `S`, `A`, `B`, `C`, `D` or `F`
+    pub grade: String,
+    pub passed: bool,
+    /// How much of the beatmap was played, in percent
+    pub completion: Option<f32>,
+    /// Unix timestamp, in seconds
+    pub ended_at: i64,
+}
+
+/// A beatmap and how often the user played it, in any mode
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct MostPlayedSummary {
+    pub playcount: u32,
+    pub map_id: u32,
+    pub mapset_id: u32,
+    pub artist: String,
+    pub title: String,
+    /// Difficulty name
+    pub version: String,
+    pub creator: String,
+    pub stars: f32,
+    /// In seconds
+    pub length: u32,
+    pub cover_url: String,
+}
+
+impl From<&UserExtended> for Profile {
+    fn from(user: &UserExtended) -> Self {
+        Self {
+            user_id: user.user_id,
+            username: user.username.to_string(),
+            country_code: user.country_code.to_string(),
+            avatar_url: user.avatar_url.clone(),
+            mode: mode_name(user.mode).to_owned(),
+            statistics: user.statistics.as_ref().map(Statistics::from),
+            peak_rank: user.highest_rank.as_ref().map(|peak| peak.rank),
+        }
+    }
+}
+
+impl From<&UserStatistics> for Statistics {
+    fn from(statistics: &UserStatistics) -> Self {
+        Self {
+            pp: statistics.pp,
+            accuracy: statistics.accuracy,
+            global_rank: statistics.global_rank,
+            country_rank: statistics.country_rank,
+            playcount: statistics.playcount,
+            playtime: statistics.playtime,
+            ranked_score: statistics.ranked_score,
+            total_score: statistics.total_score,
+            total_hits: statistics.total_hits,
+            max_combo: statistics.max_combo,
+            level: statistics.level.float(),
+            grades: GradeCounts {
+                ssh: statistics.grade_counts.ssh,
+                ss: statistics.grade_counts.ss,
+                sh: statistics.grade_counts.sh,
+                s: statistics.grade_counts.s,
+                a: statistics.grade_counts.a,
+            },
+        }
+    }
+}
+
+impl From<&Score> for ScoreSummary {
+    fn from(score: &Score) -> Self {
+        Self {
+            score_id: score.id,
+            user_id: score.user_id,
+            mode: mode_name(score.mode).to_owned(),
+            pp: score.pp,
+            accuracy: score.accuracy,
+            grade: format!("{:?}", score.grade),
+            score: score.score,
+            max_combo: score.max_combo,
+            perfect_combo: score.is_perfect_combo,
+            passed: score.passed,
+            completion: completion(score),
+            mods: score.mods.iter().map(|m| m.acronym().to_string()).collect(),
+            ended_at: score.ended_at.unix_timestamp(),
+            weight: score.weight.as_ref().map(|weight| weight.percentage),
+            beatmap: score
+                .map
+                .as_deref()
+                .map(|map| BeatmapSummary::new(map, score.mapset.as_deref())),
+        }
+    }
+}
+
+impl From<&MostPlayedMap> for MostPlayedSummary {
+    fn from(most_played: &MostPlayedMap) -> Self {
+        let (map, mapset) = (&most_played.map, &most_played.mapset);
+        Self {
+            playcount: most_played.count as u32,
+            map_id: most_played.map_id,
+            mapset_id: mapset.mapset_id,
+            artist: mapset.artist.clone(),
+            title: mapset.title.clone(),
+            version: map.version.clone(),
+            creator: mapset.creator_name.to_string(),
+            stars: map.stars,
+            length: map.seconds_total,
+            cover_url: mapset.covers.cover.clone(),
+        }
+    }
+}
+
+impl BeatmapSummary {
+    /// Scores carry their mapset separately from the map
+    pub fn new(map: &BeatmapExtended, mapset: Option<&BeatmapsetExtended>) -> Self {
+        let mapset = mapset.or(map.mapset.as_deref());
+        Self {
+            map_id: map.map_id,
+            mapset_id: map.mapset_id,
+            artist: mapset.map(|m| m.artist.clone()).unwrap_or_default(),
+            title: mapset.map(|m| m.title.clone()).unwrap_or_default(),
+            version: map.version.clone(),
+            creator: mapset
+                .map(|m| m.creator_name.to_string())
+                .unwrap_or_default(),
+            stars: map.stars,
+            length: map.seconds_total,
+            bpm: map.bpm,
+            cover_url: mapset.map(|m| m.covers.cover.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+/// How much of the beatmap a play covered, in percent, from its judgements and the
+/// beatmap's object counts. Passes are always complete
+pub fn completion(score: &Score) -> Option<f32> {
+    if score.passed {
+        return Some(100.0);
+    }
+    let map = score.map.as_deref()?;
+    let s = &score.statistics;
+    let (judged, objects) = match score.mode {
+        GameMode::Osu => (
+            s.great + s.ok + s.meh + s.miss,
+            map.count_circles + map.count_sliders + map.count_spinners,
+        ),
+        // Drumrolls and swells aren't judged like hits
+        GameMode::Taiko => (s.great + s.ok + s.miss, map.count_circles),
+        // Lazer judges the head and the tail of hold notes
+        GameMode::Mania => (
+            s.perfect + s.great + s.good + s.ok + s.meh + s.miss,
+            map.count_circles + map.count_sliders * if score.set_on_lazer { 2 } else { 1 },
+        ),
+        // Fruits, drops and droplets don't map onto object counts
+        GameMode::Catch => return None,
+    };
+    (objects > 0).then(|| (judged as f32 / objects as f32 * 100.0).min(100.0))
+}
+
+fn mode_name(mode: GameMode) -> &'static str {
+    match mode {
+        GameMode::Osu => "osu",
+        GameMode::Taiko => "taiko",
+        GameMode::Catch => "catch",
+        GameMode::Mania => "mania",
+    }
+}
@@ -0,0 +1,125 @@
+//! `.osu` files of beatmaps, for difficulty and performance calculation.
+//!
+//! Files are stored as downloaded in the cache directory. They are named by the beatmap's
+//! checksum where it's known, so an updated map is fetched again and replaces the old version.
+//! The least recently used maps are evicted once the cache grows past its size limit
+
+use color_eyre::{Result, eyre::eyre};
+use directories::ProjectDirs;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::{Request, Uri, body::Bytes, header::USER_AGENT};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use tracing::{debug, error};
+
+use crate::cache::{CacheDir, touch};
+
+const SITE_URL: &str = "https://osu.ppy.sh";
+/// Marathon maps are a few megabytes at most
+const MAX_DOWNLOAD_SIZE: usize = 16 * 1024 * 1024;
+/// Most maps are a few hundred kilobytes, this holds a couple hundred of them
+const MAX_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+pub struct BeatmapFiles {
+    dir: CacheDir,
+    client: Client<HttpsConnector<HttpConnector>, Empty<Bytes>>,
+}
+
+impl BeatmapFiles {
+    /// Files are kept in `beatmaps` in the platform cache directory
+    pub fn new() -> Result<Self> {
+        let dir = ProjectDirs::from("com", "chiffa", "rosuTracker")
+            .ok_or_else(|| eyre!("Failed to find the cache directory"))?
+            .cache_dir()
+            .join("beatmaps");
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_only()
+            .enable_http1()
+            .build();
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+        Ok(Self {
+            dir: CacheDir::new(dir, MAX_CACHE_SIZE),
+            client,
+        })
+    }
+
+    /// Contents of the `.osu` file of a beatmap, from the disk if it was fetched before
+    pub async fn get(&self, map_id: u32, checksum: Option<&str>) -> Result<Bytes> {
+        let name = match checksum {
+            Some(checksum) => format!("{map_id}-{checksum}.osu"),
+            None => format!("{map_id}.osu"),
+        };
+        let path = self.dir.path().join(&name);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => {
+                touch(path);
+                return Ok(bytes.into());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => debug!("Failed to read {}: {e}", path.display()),
+        }
+
+        let bytes = self.download(map_id).await?;
+        if let Err(e) = self.dir.store(&path, &bytes).await {
+            error!("Failed to cache beatmap {map_id}: {e}");
+        }
+        if checksum.is_some() {
+            self.remove_outdated(map_id, &name)
+                .await
+                .unwrap_or_else(|e| {
+                    debug!("Failed to remove outdated versions of beatmap {map_id}: {e}")
+                });
+        }
+        Ok(bytes)
+    }
+
+    async fn download(&self, map_id: u32) -> Result<Bytes> {
+        let uri: Uri = format!("{SITE_URL}/osu/{map_id}").parse()?;
+        let req = Request::get(uri)
+            .header(
+                USER_AGENT,
+                concat!("rosu-tracker/", env!("CARGO_PKG_VERSION")),
+            )
+            .body(Empty::new())?;
+        let res = self.client.request(req).await?;
+        if !res.status().is_success() {
+            return Err(eyre!("Failed to fetch beatmap {map_id}: {}", res.status()));
+        }
+        let bytes = Limited::new(res.into_body(), MAX_DOWNLOAD_SIZE)
+            .collect()
+            .await
+            .map_err(|e| eyre!("Failed to download beatmap {map_id}: {e}"))?
+            .to_bytes();
+        // Maps which aren't available anymore come back empty
+        if bytes.is_empty() {
+            return Err(eyre!("Beatmap {map_id} is not available"));
+        }
+        debug!("Downloaded beatmap {map_id} ({} bytes)", bytes.len());
+        Ok(bytes)
+    }
+
+    /// Remove every other file of `map_id`, i.e. versions with another or no checksum
+    async fn remove_outdated(&self, map_id: u32, current: &str) -> Result<()> {
+        let prefix = format!("{map_id}-");
+        let unknown = format!("{map_id}.osu");
+        let mut dir = tokio::fs::read_dir(self.dir.path()).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let outdated = name != current
+                && name.ends_with(".osu")
+                && (name.starts_with(&prefix) || name == unknown);
+            if outdated {
+                self.dir.remove(&entry.path()).await?;
+                debug!("Removed outdated {name}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! Size limit of the on-disk caches.
+//!
+//! The size of a cache directory is read once, then kept up to date as files are stored and
+//! removed. File modification times double as access times, the least recently used files are
+//! evicted once the directory grows past its limit
+
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use color_eyre::Result;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+pub(crate) struct CacheDir {
+    path: PathBuf,
+    max_size: u64,
+    /// Bytes in the directory, `None` until it was read
+    size: Mutex<Option<u64>>,
+}
+
+impl CacheDir {
+    pub(crate) fn new(path: PathBuf, max_size: u64) -> Self {
+        Self {
+            path,
+            max_size,
+            size: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the file next to its final path and move it in place, then make room
+    pub(crate) async fn store(&self, path: &Path, bytes: &[u8]) -> Result<()> {
+        tokio::fs::create_dir_all(&self.path).await?;
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes).await?;
+        let replaced = file_size(path).await;
+        tokio::fs::rename(&tmp, path).await?;
+        self.update(bytes.len() as u64, replaced).await
+    }
+
+    pub(crate) async fn remove(&self, path: &Path) -> Result<()> {
+        let removed = file_size(path).await;
+        tokio::fs::remove_file(path).await?;
+        self.update(0, removed).await
+    }
+
+    async fn update(&self, added: u64, removed: u64) -> Result<()> {
+        let mut size = self.size.lock().await;
+        let current = match *size {
+            Some(size) => (size + added).saturating_sub(removed),
+            // Reading the directory already counts the change
+            None => entries(&self.path)
+                .await?
+                .iter()
+                .map(|(_, len, _)| len)
+                .sum(),
+        };
+        *size = Some(current);
+        if current > self.max_size {
+            *size = Some(self.evict().await?);
+        }
+        Ok(())
+    }
+
+    /// Remove the least recently used files until the directory fits its size limit.
+    /// Returns the size left
+    async fn evict(&self) -> Result<u64> {
+        let mut entries = entries(&self.path).await?;
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort_unstable_by_key(|(used, ..)| *used);
+        for (_, len, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => total -= len,
+                Err(e) => debug!("Failed to evict {}: {e}", path.display()),
+            }
+        }
+        debug!("Evicted from {}, {total} bytes left", self.path.display());
+        Ok(total)
+    }
+}
+
+/// Last use, size and path of every file in `dir`
+async fn entries(dir: &Path) -> Result<Vec<(SystemTime, u64, PathBuf)>> {
+    let mut entries = Vec::new();
+    let mut dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        entries.push((used, metadata.len(), entry.path()));
+    }
+    Ok(entries)
+}
+
+/// Size of the file at `path`, 0 if there is none
+async fn file_size(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map_or(0, |metadata| metadata.len())
+}
+
+/// Mark a cache entry as recently used
+pub(crate) fn touch(path: PathBuf) {
+    tokio::task::spawn_blocking(move || {
+        let touched = std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(e) = touched {
+            debug!("Failed to touch {}: {e}", path.display());
+        }
+    });
+}
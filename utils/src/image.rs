@@ -1,65 +1,315 @@
-use std::io::Cursor;
-
-use color_eyre::Result;
-use futures_util::{SinkExt, Stream};
-use http_body_util::{BodyExt, Collected, Empty};
-use hyper::body::Bytes;
-use hyper::{Request, StatusCode};
-use hyper_util::rt::TokioIo;
-use image;
-use image::{DynamicImage, ImageReader};
-use tokio::net::TcpStream;
-use tokio::stream;
-
-const BASE_COVER_URL: &str = "http://assets.ppy.sh/beatmaps/";
-const END_COVER_URL: &str = "/covers/cover.jpg";
-
-pub(crate) async fn fetch_url(url: hyper::Uri) -> Result<Collected<Bytes>> {
-    let host = url.host().expect("uri has no host");
-    let port = url.port_u16().unwrap_or(80);
-    let addr = format!("{}:{}", host, port);
-    let stream = TcpStream::connect(addr).await?;
-    let io = TokioIo::new(stream);
-
-    let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-    tokio::task::spawn(async move {
-        if let Err(err) = conn.await {
-            println!("Connection failed: {:?}", err);
+//! Persistent cache for images served by osu!, e.g. beatmap covers, avatars and flags.
+//!
+//! Images are stored as downloaded in the cache directory, named by a hash of their URL.
+//! The least recently used images are evicted once the cache grows past its size limit
+
+use std::{borrow::Cow, io::Cursor, path::PathBuf};
+
+use color_eyre::{Result, eyre::eyre};
+use directories::ProjectDirs;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::{
+    Request, Uri,
+    body::Bytes,
+    header::{CONTENT_LENGTH, USER_AGENT},
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use image::{DynamicImage, ImageReader, Limits};
+use tracing::{debug, error};
+
+use crate::cache::{CacheDir, touch};
+
+const DEFAULT_BASE_URL: &str = "https://assets.ppy.sh";
+const DEFAULT_SITE_URL: &str = "https://osu.ppy.sh";
+/// Downloads larger than this are aborted
+const DEFAULT_MAX_DOWNLOAD_SIZE: usize = 8 * 1024 * 1024;
+/// The least recently used images are evicted above this
+const DEFAULT_MAX_CACHE_SIZE: u64 = 256 * 1024 * 1024;
+/// Images with a larger width or height aren't decoded
+const MAX_DIMENSION: u32 = 4096;
+
+pub struct ImageCacheBuilder {
+    dir: Option<PathBuf>,
+    base_url: String,
+    site_url: String,
+    max_download_size: usize,
+    max_cache_size: u64,
+}
+
+impl Default for ImageCacheBuilder {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            base_url: DEFAULT_BASE_URL.to_owned(),
+            site_url: DEFAULT_SITE_URL.to_owned(),
+            max_download_size: DEFAULT_MAX_DOWNLOAD_SIZE,
+            max_cache_size: DEFAULT_MAX_CACHE_SIZE,
         }
-    });
+    }
+}
 
-    let authority = url.authority().unwrap().clone();
+impl ImageCacheBuilder {
+    /// Where images are stored, `images` in the platform cache directory by default
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
 
-    let path = url.path();
-    let req = Request::builder()
-        .uri(path)
-        .header(hyper::header::HOST, authority.as_str())
-        .body(Empty::<Bytes>::new())?;
+    /// Server covers are fetched from, e.g. a mirror at `https://assets.example.com`
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_owned();
+        self
+    }
 
-    let mut res = sender.send_request(req).await?;
-    if res.status() == StatusCode::from_u16(404).unwrap() {
-        return Err(color_eyre::Report::msg("Image not found"));
+    /// Server flags are fetched from
+    pub fn site_url(mut self, site_url: impl Into<String>) -> Self {
+        self.site_url = site_url.into().trim_end_matches('/').to_owned();
+        self
     }
 
-    // println!("Response: {}", res.status());
-    tracing::debug!("Headers: {:#?}\n", res.headers());
+    pub fn max_download_size(mut self, bytes: usize) -> Self {
+        self.max_download_size = bytes;
+        self
+    }
 
-    let output = res.collect().await;
+    pub fn max_cache_size(mut self, bytes: u64) -> Self {
+        self.max_cache_size = bytes;
+        self
+    }
 
-    Ok(output?)
+    pub fn build(self) -> Result<ImageCache> {
+        let dir = match self.dir {
+            Some(dir) => dir,
+            None => ProjectDirs::from("com", "chiffa", "rosuTracker")
+                .ok_or_else(|| eyre!("Failed to find the cache directory"))?
+                .cache_dir()
+                .join("images"),
+        };
+        let connector = HttpsConnectorBuilder::new().with_native_roots()?;
+        // The tests serve images over plain HTTP
+        #[cfg(test)]
+        let connector = connector.https_or_http();
+        #[cfg(not(test))]
+        let connector = connector.https_only();
+        let connector = connector.enable_http1().build();
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+        Ok(ImageCache {
+            dir: CacheDir::new(dir, self.max_cache_size),
+            base_url: self.base_url,
+            site_url: self.site_url,
+            max_download_size: self.max_download_size,
+            client,
+        })
+    }
 }
 
-pub(crate) fn form_url(beatmapset_id: u32) -> hyper::Uri {
-    let uri_string = format!("{}{}{}", BASE_COVER_URL, beatmapset_id, END_COVER_URL);
-    uri_string.parse::<hyper::Uri>().unwrap()
+pub struct ImageCache {
+    dir: CacheDir,
+    base_url: String,
+    site_url: String,
+    max_download_size: usize,
+    client: Client<HttpsConnector<HttpConnector>, Empty<Bytes>>,
 }
 
-pub(crate) fn parse_image(bytes: Collected<Bytes>) -> Result<DynamicImage> {
-    let res = bytes.to_bytes();
+impl ImageCache {
+    pub fn builder() -> ImageCacheBuilder {
+        ImageCacheBuilder::default()
+    }
+
+    /// Cover of a beatmapset
+    pub async fn cover(&self, mapset_id: u32) -> Result<DynamicImage> {
+        let url = format!("{}/beatmaps/{mapset_id}/covers/cover.jpg", self.base_url);
+        self.get(&url).await
+    }
+
+    /// Flag of a country, by its two letter code
+    pub async fn flag(&self, country_code: &str) -> Result<DynamicImage> {
+        let url = format!(
+            "{}/images/flags/{}.png",
+            self.site_url,
+            country_code.to_ascii_uppercase()
+        );
+        self.get(&url).await
+    }
+
+    /// Image at `url`, from the disk if it was fetched before.
+    /// Site relative URLs, like the default avatar, are resolved against the site URL
+    pub async fn get(&self, url: &str) -> Result<DynamicImage> {
+        let url: Cow<str> = match url.strip_prefix('/') {
+            Some(path) => format!("{}/{path}", self.site_url).into(),
+            None => url.into(),
+        };
+        let url = url.as_ref();
+        let path = self.dir.path().join(cache_key(url));
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => match decode(bytes.into()).await {
+                Ok(image) => {
+                    touch(path);
+                    return Ok(image);
+                }
+                Err(e) => debug!("Dropping broken cache entry for {url}: {e}"),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => debug!("Failed to read cache entry for {url}: {e}"),
+        }
 
-    let mut image = ImageReader::new(Cursor::new(res));
-    image.set_format(image::ImageFormat::Jpeg);
-    let image = image.decode()?;
-    Ok(image)
+        let bytes = self.download(url).await?;
+        let image = decode(bytes.clone()).await?;
+        if let Err(e) = self.dir.store(&path, &bytes).await {
+            error!("Failed to cache {url}: {e}");
+        }
+        Ok(image)
+    }
+
+    async fn download(&self, url: &str) -> Result<Bytes> {
+        let uri: Uri = url.parse()?;
+        let req = Request::get(uri)
+            .header(
+                USER_AGENT,
+                concat!("rosu-tracker/", env!("CARGO_PKG_VERSION")),
+            )
+            .body(Empty::new())?;
+        let res = self.client.request(req).await?;
+        if !res.status().is_success() {
+            return Err(eyre!("Failed to fetch {url}: {}", res.status()));
+        }
+        let too_large = res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<usize>().ok())
+            .is_some_and(|len| len > self.max_download_size);
+        if too_large {
+            return Err(eyre!(
+                "{url} is larger than {} bytes",
+                self.max_download_size
+            ));
+        }
+        let bytes = Limited::new(res.into_body(), self.max_download_size)
+            .collect()
+            .await
+            .map_err(|e| eyre!("Failed to download {url}: {e}"))?
+            .to_bytes();
+        debug!("Downloaded {url} ({} bytes)", bytes.len());
+        Ok(bytes)
+    }
+}
+
+/// Decode an image on the blocking thread pool, large images take a while
+async fn decode(bytes: Bytes) -> Result<DynamicImage> {
+    tokio::task::spawn_blocking(move || decode_blocking(&bytes)).await?
 }
 
+/// Decode an image of whatever format it turns out to be
+fn decode_blocking(bytes: &[u8]) -> Result<DynamicImage> {
+    let format =
+        image::guess_format(bytes).map_err(|_| eyre!("Response is not a supported image"))?;
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    Ok(reader.decode()?)
+}
+
+/// FNV-1a of the URL. Unlike `DefaultHasher` this is stable across Rust versions
+fn cache_key(url: &str) -> String {
+    let hash = url.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, SystemTime},
+    };
+
+    use http_body_util::Full;
+    use hyper::{Response, server::conn::http1, service::service_fn};
+    use hyper_util::rt::TokioIo;
+    use image::ImageFormat;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serve `png` for every path, counting the requests
+    async fn serve(png: Bytes) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (png, counter) = (png.clone(), counter.clone());
+                let service = service_fn(move |_| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let png = png.clone();
+                    async move { Ok::<_, Infallible>(Response::new(Full::new(png))) }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        (url, requests)
+    }
+
+    fn png() -> Bytes {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(16, 16)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        png.into_inner().into()
+    }
+
+    #[tokio::test]
+    async fn fetches_caches_and_evicts() {
+        let png = png();
+        let len = png.len() as u64;
+        let (url, requests) = serve(png).await;
+        let dir = tempfile::tempdir().unwrap();
+        // Room for two covers
+        let cache = ImageCache::builder()
+            .dir(dir.path())
+            .base_url(&url)
+            .max_cache_size(2 * len + len / 2)
+            .build()
+            .unwrap();
+        let cover_path = |mapset_id: u32| {
+            let url = format!("{url}/beatmaps/{mapset_id}/covers/cover.jpg");
+            dir.path().join(cache_key(&url))
+        };
+
+        let cover = cache.cover(1).await.unwrap();
+        assert_eq!((cover.width(), cover.height()), (16, 16));
+        cache.cover(2).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert!(cover_path(1).exists() && cover_path(2).exists());
+
+        // Cover 1 is the least recently used, so it makes room for cover 3
+        let long_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+        std::fs::File::options()
+            .write(true)
+            .open(cover_path(1))
+            .and_then(|file| file.set_modified(long_ago))
+            .unwrap();
+        cache.cover(3).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(!cover_path(1).exists() && cover_path(3).exists());
+
+        cache.cover(2).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        cache.cover(1).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,119 @@
+//! osu! API client secrets and login tokens, kept out of the config files.
+//!
+//! Secrets live in the system keyring (Secret Service on Linux), keyed by client ID.
+//! Without a usable keyring they fall back to a file only the current user can read
+
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use color_eyre::{Result, eyre::eyre};
+use directories::ProjectDirs;
+use keyring::Entry;
+use tracing::{debug, warn};
+
+const SERVICE: &str = "rosu-tracker";
+
+/// Store the secret of a client, replacing the previous one
+pub fn store(client_id: &str, secret: &str) -> Result<()> {
+    set(&format!("client-{client_id}"), secret)
+}
+
+/// The secret of a client, if one was stored
+pub fn load(client_id: &str) -> Result<Option<String>> {
+    get(&format!("client-{client_id}"))
+}
+
+/// Store the serialized login token of a client, replacing the previous one
+pub fn store_login(client_id: &str, login: &str) -> Result<()> {
+    set(&format!("login-{client_id}"), login)
+}
+
+/// The serialized login token of a client, if anyone logged in
+pub fn load_login(client_id: &str) -> Result<Option<String>> {
+    get(&format!("login-{client_id}"))
+}
+
+pub fn delete_login(client_id: &str) -> Result<()> {
+    let name = format!("login-{client_id}");
+    match Entry::new(SERVICE, &name).and_then(|entry| entry.delete_credential()) {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(e) => debug!("Failed to delete {name} from the keyring: {e}"),
+    }
+    remove_from_file(&name)
+}
+
+fn set(name: &str, value: &str) -> Result<()> {
+    match Entry::new(SERVICE, name).and_then(|entry| entry.set_password(value)) {
+        // Don't leave an older copy in the fallback file
+        Ok(()) => remove_from_file(name),
+        Err(e) => {
+            warn!("Keyring unavailable, storing {name} in a file: {e}");
+            let mut secrets = read_file()?;
+            secrets.insert(name.to_owned(), value.to_owned());
+            write_file(&secrets)
+        }
+    }
+}
+
+fn get(name: &str) -> Result<Option<String>> {
+    match Entry::new(SERVICE, name).and_then(|entry| entry.get_password()) {
+        Ok(value) => return Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => debug!("Failed to read the keyring, trying the fallback file: {e}"),
+    }
+    Ok(read_file()?.remove(name))
+}
+
+fn remove_from_file(name: &str) -> Result<()> {
+    let mut secrets = read_file()?;
+    if secrets.remove(name).is_some() {
+        write_file(&secrets)?;
+    }
+    Ok(())
+}
+
+fn file_path() -> Result<PathBuf> {
+    Ok(ProjectDirs::from("com", "chiffa", "rosuTracker")
+        .ok_or_else(|| eyre!("Failed to find the data directory"))?
+        .data_dir()
+        .join("secrets.json"))
+}
+
+/// Secrets by keyring entry name
+fn read_file() -> Result<BTreeMap<String, String>> {
+    let path = file_path()?;
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::metadata(&path)?.permissions();
+        if permissions.mode() & 0o077 != 0 {
+            warn!("{} is readable by other users, fixing", path.display());
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Atomically replace the file, created readable by the current user only
+fn write_file(secrets: &BTreeMap<String, String>) -> Result<()> {
+    let path = file_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    // The mode only applies to new files
+    let _ = std::fs::remove_file(&tmp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(&serde_json::to_vec(secrets)?)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(())
+}